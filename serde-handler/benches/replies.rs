@@ -0,0 +1,88 @@
+//! Compares reading large replies as owned values with borrowing them from the reply buffer.
+//!
+//! Run with `cargo bench`.
+
+use std::{
+    hint::black_box,
+    thread,
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+
+use serde_handler::{api::*, channel};
+
+/// Requests `line` repeated `count` times.
+#[derive(Debug, Serialize, Deserialize)]
+struct LinesRequest<'a> {
+    line: &'a str,
+    count: usize,
+}
+
+impl Api for LinesRequest<'_> {
+    type Reply<'de> = Vec<&'de str>;
+    type Request<'de> = LinesRequest<'de>;
+
+    const NAME: &'static str = "lines";
+    const SERVICE: &'static str = "bench";
+}
+
+/// The same as [`LinesRequest`], but with a reply that owns its strings.
+#[derive(Debug, Serialize, Deserialize)]
+struct OwnedLinesRequest<'a> {
+    line: &'a str,
+    count: usize,
+}
+
+impl Api for OwnedLinesRequest<'_> {
+    type Reply<'de> = Vec<String>;
+    type Request<'de> = OwnedLinesRequest<'de>;
+
+    const NAME: &'static str = "owned_lines";
+    const SERVICE: &'static str = "bench";
+}
+
+/// Runs `f` repeatedly for about a second and reports the average time per call.
+fn bench(name: &str, mut f: impl FnMut()) {
+    // Warm up
+    for _ in 0..10 {
+        f();
+    }
+    let start = Instant::now();
+    let mut iterations = 0u32;
+    while start.elapsed() < Duration::from_secs(1) {
+        f();
+        iterations += 1;
+    }
+    println!("{name:<30} {:>12?}/iter", start.elapsed() / iterations);
+}
+
+fn main() {
+    let (requester, responder) = channel::new_pair();
+    thread::spawn(move || {
+        ApiRouter::new()
+            .register_handler::<LinesRequest, _>(|req| vec![req.line; req.count])
+            .register_handler::<OwnedLinesRequest, _>(|req| vec![req.line.to_string(); req.count])
+            .serve_on(responder)
+    });
+
+    for (count, len) in [(10, 10), (1_000, 100), (100, 10_000)] {
+        let line = "x".repeat(len);
+        let line = line.as_str();
+        let name = format!("owned    {count}x{len}");
+        bench(&name, || {
+            let reply = requester
+                .request(OwnedLinesRequest { line, count })
+                .unwrap();
+            black_box(reply.iter().map(String::len).sum::<usize>());
+        });
+        let name = format!("borrowed {count}x{len}");
+        bench(&name, || {
+            let guard = requester
+                .request_borrowed(LinesRequest { line, count })
+                .unwrap();
+            let reply = guard.reply().unwrap();
+            black_box(reply.iter().map(|line| line.len()).sum::<usize>());
+        });
+    }
+}
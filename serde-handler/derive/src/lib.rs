@@ -0,0 +1,222 @@
+//! `#[derive(Api)]` for `serde-handler`.
+//!
+//! Use it through the `derive` feature of `serde-handler`, which re-exports the macro next to the
+//! `Api` trait.
+
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use quote::quote;
+use syn::{
+    parse_macro_input, spanned::Spanned, visit_mut::VisitMut, DeriveInput, GenericArgument,
+    GenericParam, ItemTrait, Lifetime, LitBool, LitInt, LitStr, Type,
+};
+
+mod service;
+
+/// Implements `Api` for a request type.
+///
+/// ```ignore
+/// #[derive(Serialize, Deserialize, Api)]
+/// #[api(service = "text", name = "upper", reply = String)]
+/// struct UppercaseRequest<'a>(&'a str);
+/// ```
+///
+/// `service`, `name` and `reply` are required and become `Api::SERVICE`, `Api::NAME` and
+/// `Api::Reply`. The optional `timeout_ms = 500` sets `Api::TIMEOUT`, `version = 2` sets
+/// `Api::VERSION` and `idempotent = true` sets `Api::IDEMPOTENT`.
+///
+/// The request type may borrow from the request data through at most one lifetime parameter,
+/// which is rebound to produce `Api::Request<'de>`. Without a lifetime, `Api::Request<'de>` is the
+/// type itself. The reply may use the same lifetime to borrow from the reply data, as in
+/// `reply = &'a str`, which is rebound to produce `Api::Reply<'de>`.
+#[proc_macro_derive(Api, attributes(api))]
+pub fn derive_api(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+/// Generates a typed client and router for a service from a trait.
+///
+/// ```ignore
+/// #[service]
+/// trait Text {
+///     fn upper(&self, s: &str) -> String;
+/// }
+/// ```
+///
+/// Every method of the trait becomes an API of the service. For `Text`, this generates
+///
+/// - a `TextUpper<'a> { s: &'a str }` request type implementing `Api` with `NAME = "upper"` and
+///   `SERVICE = "text"` (or the `name` given as `#[service(name = "...")]`),
+/// - a `TextClient` wrapping a `Requester` with a `fn upper(&self, s: &str)` method that sends
+///   a `TextUpper` and returns its reply, and
+/// - a `fn text_router<S: Text + 'static>(service: S) -> ApiRouter<S>` that handles every API
+///   by calling the corresponding method of `service`, which it keeps as its state.
+///
+/// Methods must take `&self` or `&mut self`. Their arguments become the fields of the request
+/// type, so arguments can only borrow through elided lifetimes, from which the request type's
+/// single lifetime is made. A method marked `#[api(idempotent = true)]` sets `Api::IDEMPOTENT`
+/// of its request type.
+#[proc_macro_attribute]
+pub fn service(args: TokenStream, item: TokenStream) -> TokenStream {
+    let args = parse_macro_input!(args as service::ServiceArgs);
+    let item = parse_macro_input!(item as ItemTrait);
+    service::expand(args, item)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+struct ApiAttributes {
+    service: LitStr,
+    name: LitStr,
+    reply: Type,
+    timeout_ms: Option<LitInt>,
+    version: Option<LitInt>,
+    idempotent: Option<LitBool>,
+}
+
+fn parse_attributes(input: &DeriveInput) -> syn::Result<ApiAttributes> {
+    let mut service = None;
+    let mut name = None;
+    let mut reply = None;
+    let mut timeout_ms = None;
+    let mut version = None;
+    let mut idempotent = None;
+
+    let mut found = false;
+    for attr in input
+        .attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("api"))
+    {
+        found = true;
+        attr.parse_nested_meta(|meta| {
+            let key = meta
+                .path
+                .get_ident()
+                .map(ToString::to_string)
+                .unwrap_or_default();
+            let duplicate = match key.as_str() {
+                "service" => service.replace(meta.value()?.parse()?).is_some(),
+                "name" => name.replace(meta.value()?.parse()?).is_some(),
+                "reply" => reply.replace(meta.value()?.parse()?).is_some(),
+                "timeout_ms" => timeout_ms.replace(meta.value()?.parse()?).is_some(),
+                "version" => version.replace(meta.value()?.parse()?).is_some(),
+                "idempotent" => idempotent.replace(meta.value()?.parse()?).is_some(),
+                _ => {
+                    return Err(meta.error(
+                        "unknown `api` attribute, expected one of `service`, `name`, `reply`, \
+                         `timeout_ms`, `version` or `idempotent`",
+                    ))
+                }
+            };
+            if duplicate {
+                return Err(meta.error(format!("duplicate `{key}` in `api` attribute")));
+            }
+            Ok(())
+        })?;
+    }
+
+    if !found {
+        return Err(syn::Error::new(
+            Span::call_site(),
+            "missing `#[api(service = \"...\", name = \"...\", reply = ...)]` attribute",
+        ));
+    }
+    let missing = |key| {
+        syn::Error::new(
+            Span::call_site(),
+            format!("missing `{key}` in `api` attribute"),
+        )
+    };
+    Ok(ApiAttributes {
+        service: service.ok_or_else(|| missing("service"))?,
+        name: name.ok_or_else(|| missing("name"))?,
+        reply: reply.ok_or_else(|| missing("reply"))?,
+        timeout_ms,
+        version,
+        idempotent,
+    })
+}
+
+fn expand(input: DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let ApiAttributes {
+        service,
+        name,
+        mut reply,
+        timeout_ms,
+        version,
+        idempotent,
+    } = parse_attributes(&input)?;
+
+    let mut lifetimes = input.generics.lifetimes();
+    let lifetime = lifetimes.next().map(|param| param.lifetime.clone());
+    if let Some(extra) = lifetimes.next() {
+        return Err(syn::Error::new(
+            extra.span(),
+            "`Api` can only be derived for types with at most one lifetime parameter",
+        ));
+    }
+
+    // serde rejects request types with a lifetime called `'de`, so this cannot shadow the impl's.
+    let de = Lifetime::new("'de", Span::call_site());
+    if let Some(lifetime) = lifetime {
+        Rebind { lifetime, to: &de }.visit_type_mut(&mut reply);
+    }
+    let request_args = input.generics.params.iter().map(|param| match param {
+        GenericParam::Lifetime(_) => GenericArgument::Lifetime(de.clone()),
+        GenericParam::Type(param) => {
+            let ident = &param.ident;
+            GenericArgument::Type(syn::parse_quote!(#ident))
+        }
+        GenericParam::Const(param) => {
+            let ident = &param.ident;
+            GenericArgument::Const(syn::parse_quote!(#ident))
+        }
+    });
+
+    let ident = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let request = if input.generics.params.is_empty() {
+        quote!(#ident)
+    } else {
+        quote!(#ident<#(#request_args),*>)
+    };
+    let timeout = timeout_ms.map(|millis| {
+        quote! {
+            const TIMEOUT: ::core::option::Option<::std::time::Duration> =
+                ::core::option::Option::Some(::std::time::Duration::from_millis(#millis));
+        }
+    });
+    let version = version.map(|version| quote!(const VERSION: u32 = #version;));
+    let idempotent = idempotent.map(|idempotent| quote!(const IDEMPOTENT: bool = #idempotent;));
+
+    Ok(quote! {
+        impl #impl_generics ::serde_handler::api::Api for #ident #ty_generics #where_clause {
+            type Reply<#de> = #reply;
+            type Request<#de> = #request;
+
+            const NAME: &'static str = #name;
+            const SERVICE: &'static str = #service;
+            #timeout
+            #version
+            #idempotent
+        }
+    })
+}
+
+/// Replaces `lifetime` with `to`.
+struct Rebind<'a> {
+    lifetime: Lifetime,
+    to: &'a Lifetime,
+}
+
+impl VisitMut for Rebind<'_> {
+    fn visit_lifetime_mut(&mut self, lifetime: &mut Lifetime) {
+        if *lifetime == self.lifetime {
+            *lifetime = self.to.clone();
+        }
+    }
+}
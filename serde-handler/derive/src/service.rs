@@ -0,0 +1,319 @@
+use proc_macro2::{Span, TokenStream};
+use quote::{format_ident, quote};
+use syn::{
+    parse::{Parse, ParseStream},
+    spanned::Spanned,
+    visit_mut::{self, VisitMut},
+    Attribute, FnArg, Ident, ItemTrait, Lifetime, LitBool, LitStr, Pat, ReturnType, TraitItem,
+    TraitItemFn, Type, TypeReference,
+};
+
+/// The arguments of `#[service(...)]`.
+pub(crate) struct ServiceArgs {
+    name: Option<LitStr>,
+}
+
+impl Parse for ServiceArgs {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let mut name = None;
+        if !input.is_empty() {
+            let key: Ident = input.parse()?;
+            if key != "name" {
+                return Err(syn::Error::new(
+                    key.span(),
+                    "unknown `service` attribute, expected `name`",
+                ));
+            }
+            input.parse::<syn::Token![=]>()?;
+            name = Some(input.parse()?);
+        }
+        Ok(Self { name })
+    }
+}
+
+/// An API of the service, generated from one of the trait's methods.
+struct Method<'a> {
+    method: &'a TraitItemFn,
+    /// The name of the generated `Api` type.
+    api: Ident,
+    /// The names and types of the request fields, with elided lifetimes bound to `'a`.
+    fields: Vec<(&'a Ident, Type)>,
+    /// Whether any of the `fields` borrows from the request data.
+    borrows: bool,
+    reply: Type,
+    /// The `idempotent` of the method's `#[api(...)]` attribute.
+    idempotent: Option<LitBool>,
+}
+
+impl<'a> Method<'a> {
+    fn new(trait_ident: &Ident, method: &'a TraitItemFn) -> syn::Result<Self> {
+        let sig = &method.sig;
+        if !sig.generics.params.is_empty() || sig.generics.where_clause.is_some() {
+            return Err(syn::Error::new(
+                sig.generics.span(),
+                "service methods cannot be generic",
+            ));
+        }
+        if let Some(asyncness) = &sig.asyncness {
+            return Err(syn::Error::new(
+                asyncness.span(),
+                "service methods cannot be `async`",
+            ));
+        }
+
+        let mut inputs = sig.inputs.iter();
+        match inputs.next() {
+            Some(FnArg::Receiver(receiver)) if receiver.reference.is_some() => {}
+            _ => {
+                return Err(syn::Error::new(
+                    sig.span(),
+                    "service methods must take `&self` or `&mut self`",
+                ))
+            }
+        }
+        let mut borrows = false;
+        let fields = inputs
+            .map(|arg| {
+                let FnArg::Typed(arg) = arg else {
+                    unreachable!("only the first argument can be a receiver")
+                };
+                let Pat::Ident(pat) = &*arg.pat else {
+                    return Err(syn::Error::new(
+                        arg.pat.span(),
+                        "service method arguments must be plain identifiers",
+                    ));
+                };
+                let mut ty = (*arg.ty).clone();
+                let mut bind = BindLifetimes::default();
+                bind.visit_type_mut(&mut ty);
+                borrows |= bind.found;
+                Ok((&pat.ident, ty))
+            })
+            .collect::<syn::Result<_>>()?;
+
+        let reply = match &sig.output {
+            ReturnType::Default => syn::parse_quote!(()),
+            ReturnType::Type(_, ty) => (**ty).clone(),
+        };
+        Ok(Self {
+            method,
+            api: format_ident!("{trait_ident}{}", camel_case(&sig.ident.to_string())),
+            fields,
+            borrows,
+            reply,
+            idempotent: parse_idempotent(&method.attrs)?,
+        })
+    }
+}
+
+/// Parses the `#[api(idempotent = ...)]` attributes of a method.
+fn parse_idempotent(attrs: &[Attribute]) -> syn::Result<Option<LitBool>> {
+    let mut idempotent = None;
+    for attr in attrs.iter().filter(|attr| attr.path().is_ident("api")) {
+        attr.parse_nested_meta(|meta| {
+            if !meta.path.is_ident("idempotent") {
+                return Err(meta.error("unknown `api` attribute, expected `idempotent`"));
+            }
+            if idempotent.replace(meta.value()?.parse()?).is_some() {
+                return Err(meta.error("duplicate `idempotent` in `api` attribute"));
+            }
+            Ok(())
+        })?;
+    }
+    Ok(idempotent)
+}
+
+/// Binds all elided lifetimes of a request field's type to the `'a` of the generated `Api` type.
+///
+/// Methods cannot be generic, so the only other lifetime a field can have is `'static`.
+#[derive(Default)]
+struct BindLifetimes {
+    found: bool,
+}
+
+impl VisitMut for BindLifetimes {
+    fn visit_type_reference_mut(&mut self, reference: &mut TypeReference) {
+        if reference.lifetime.is_none() {
+            reference.lifetime = Some(Lifetime::new("'_", reference.and_token.span));
+        }
+        visit_mut::visit_type_reference_mut(self, reference);
+    }
+
+    fn visit_lifetime_mut(&mut self, lifetime: &mut Lifetime) {
+        if lifetime.ident == "_" {
+            self.found = true;
+            *lifetime = Lifetime::new("'a", lifetime.span());
+        }
+    }
+}
+
+fn camel_case(name: &str) -> String {
+    name.split('_')
+        .filter(|part| !part.is_empty())
+        .map(|part| {
+            let mut chars = part.chars();
+            chars
+                .next()
+                .map(|first| first.to_uppercase().chain(chars).collect::<String>())
+                .unwrap_or_default()
+        })
+        .collect()
+}
+
+fn snake_case(name: &str) -> String {
+    let mut snake = String::new();
+    for (i, c) in name.char_indices() {
+        if c.is_uppercase() && i > 0 {
+            snake.push('_');
+        }
+        snake.extend(c.to_lowercase());
+    }
+    snake
+}
+
+pub(crate) fn expand(args: ServiceArgs, item: ItemTrait) -> syn::Result<TokenStream> {
+    if !item.generics.params.is_empty() || item.generics.where_clause.is_some() {
+        return Err(syn::Error::new(
+            item.generics.span(),
+            "service traits cannot be generic",
+        ));
+    }
+    let trait_ident = &item.ident;
+    let methods = item
+        .items
+        .iter()
+        .map(|trait_item| match trait_item {
+            TraitItem::Fn(method) => Method::new(trait_ident, method),
+            _ => Err(syn::Error::new(
+                trait_item.span(),
+                "service traits can only contain methods",
+            )),
+        })
+        .collect::<syn::Result<Vec<_>>>()?;
+
+    let vis = &item.vis;
+    let service = args
+        .name
+        .unwrap_or_else(|| LitStr::new(&snake_case(&trait_ident.to_string()), trait_ident.span()));
+    let client = format_ident!("{trait_ident}Client");
+    let router = format_ident!("{}_router", snake_case(&trait_ident.to_string()));
+
+    let apis = methods.iter().map(|method| {
+        let Method {
+            api,
+            fields,
+            borrows,
+            reply,
+            idempotent,
+            ..
+        } = method;
+        let name = LitStr::new(&method.method.sig.ident.to_string(), Span::call_site());
+        let (field_names, field_types): (Vec<_>, Vec<_>) = fields.iter().cloned().unzip();
+        let (generics, request) = if *borrows {
+            (quote!(<'a>), quote!(#api<'de>))
+        } else {
+            (quote!(), quote!(#api))
+        };
+        let idempotent = idempotent
+            .as_ref()
+            .map(|idempotent| quote!(const IDEMPOTENT: bool = #idempotent;));
+        let doc = format!(
+            "The request of [`{trait_ident}::{}`].",
+            method.method.sig.ident
+        );
+        quote! {
+            #[doc = #doc]
+            #[derive(
+                ::serde_handler::__private::serde::Serialize,
+                ::serde_handler::__private::serde::Deserialize,
+            )]
+            #[serde(crate = "::serde_handler::__private::serde")]
+            #vis struct #api #generics {
+                #(#vis #field_names: #field_types,)*
+            }
+
+            impl #generics ::serde_handler::api::Api for #api #generics {
+                type Reply<'de> = #reply;
+                type Request<'de> = #request;
+
+                const NAME: &'static str = #name;
+                const SERVICE: &'static str = #service;
+                #idempotent
+            }
+        }
+    });
+
+    let client_methods = methods.iter().map(|method| {
+        let Method {
+            api, fields, reply, ..
+        } = method;
+        let sig = &method.method.sig;
+        let (ident, inputs) = (&sig.ident, sig.inputs.iter().skip(1));
+        let field_names = fields.iter().map(|(name, _)| name);
+        let doc = format!("Calls [`{trait_ident}::{ident}`] on the service.");
+        quote! {
+            #[doc = #doc]
+            #vis fn #ident(&self, #(#inputs),*)
+                -> ::core::result::Result<#reply, ::serde_handler::channel::Error>
+            {
+                self.requester.request(#api { #(#field_names),* })
+            }
+        }
+    });
+
+    let handlers = methods.iter().map(|method| {
+        let Method { api, fields, .. } = method;
+        let ident = &method.method.sig.ident;
+        let field_names = fields.iter().map(|(name, _)| name);
+        quote! {
+            .register_stateful_handler::<#api, _>(|service: &mut S, request| {
+                service.#ident(#(request.#field_names),*)
+            })
+        }
+    });
+
+    let client_doc = format!("A typed client for the [`{trait_ident}`] service.");
+    let router_doc = format!(
+        "Serves the APIs of the [`{trait_ident}`] service with `service`, which is the state of \
+         the router."
+    );
+    // The `api` attributes of the methods only configure the generated APIs.
+    let mut item = item.clone();
+    for trait_item in &mut item.items {
+        if let TraitItem::Fn(method) = trait_item {
+            method.attrs.retain(|attr| !attr.path().is_ident("api"));
+        }
+    }
+    Ok(quote! {
+        #item
+
+        #(#apis)*
+
+        #[doc = #client_doc]
+        #vis struct #client<T = ::serde_handler::channel::Local> {
+            requester: ::serde_handler::channel::Requester<T>,
+        }
+
+        impl<T: ::serde_handler::transport::Transport> #client<T> {
+            /// Sends the requests of this client through `requester`.
+            #vis fn new(requester: ::serde_handler::channel::Requester<T>) -> Self {
+                Self { requester }
+            }
+
+            /// Returns the underlying `Requester`.
+            #vis fn into_inner(self) -> ::serde_handler::channel::Requester<T> {
+                self.requester
+            }
+
+            #(#client_methods)*
+        }
+
+        #[doc = #router_doc]
+        #vis fn #router<S: #trait_ident + 'static>(service: S)
+            -> ::serde_handler::api::ApiRouter<S>
+        {
+            ::serde_handler::api::ApiRouter::with_state(service)
+                #(#handlers)*
+        }
+    })
+}
@@ -0,0 +1,78 @@
+//! Calls the APIs of a running service by name, without their Rust types.
+//!
+//! Usage: `serde-handler-cli <address> <service>`, where `address` is either a TCP address like
+//! `127.0.0.1:4000` or the path of a Unix domain socket that an `ApiRouter` is served on.
+//!
+//! Each line read from stdin is a request of the form `name {json}`, e.g. `upper "hello"`. The
+//! JSON may be left out to send `null`. The reply (or error) is printed for every request.
+
+use std::{
+    env,
+    io::{self, BufRead},
+    process,
+};
+
+use serde_handler::{
+    channel::{self, Requester},
+    transport::Transport,
+};
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let [_, address, service] = args.as_slice() else {
+        eprintln!("Usage: serde-handler-cli <address> <service>");
+        process::exit(2);
+    };
+
+    let result = if address.parse::<std::net::SocketAddr>().is_ok() {
+        channel::connect_tcp(address.as_str()).map(|requester| run(requester, service))
+    } else {
+        connect_unix(address).map(|requester| run(requester, service))
+    };
+    if let Err(e) = result {
+        eprintln!("{e}");
+        process::exit(1);
+    }
+}
+
+#[cfg(unix)]
+fn connect_unix(path: &str) -> Result<Requester<std::os::unix::net::UnixStream>, String> {
+    channel::connect_unix(path)
+}
+
+#[cfg(not(unix))]
+fn connect_unix(path: &str) -> Result<Requester, String> {
+    Err(format!("'{path}' is not a TCP address"))
+}
+
+/// Sends the requests read from stdin to `service` until stdin is closed.
+fn run<T: Transport>(requester: Requester<T>, service: &str) {
+    let stdin = io::stdin();
+    for line in stdin.lock().lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(e) => {
+                eprintln!("Input error: {e}");
+                continue;
+            }
+        };
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let (name, request) = line
+            .split_once(char::is_whitespace)
+            .unwrap_or((line, "null"));
+        let request = match serde_json::from_str(request) {
+            Ok(request) => request,
+            Err(e) => {
+                eprintln!("Invalid JSON for '{name}': {e}");
+                continue;
+            }
+        };
+        match requester.call_raw(service, name, request) {
+            Ok(reply) => println!("{reply}"),
+            Err(e) => eprintln!("Error: {e}"),
+        }
+    }
+}
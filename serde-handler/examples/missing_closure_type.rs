@@ -1,11 +1,22 @@
-use std::collections::HashMap;
+//! Routing by type-erasing each handler into a closure over the request bytes.
+//!
+//! Does not compile, since the closure passed to `Box::new` needs an annotated argument type to
+//! accept request data of any lifetime.
+//!
+//! One of the historical steps towards [`serde_handler::api`], kept to show how it came about.
+//! Run with `cargo run --example missing_closure_type --features missing_closure_type`.
+
+use std::{
+    collections::HashMap,
+    io::{self, BufRead},
+    thread,
+};
 
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
-use crate::{
-    channel::{Message, Responder},
-    Result,
-};
+use serde_handler::channel::{self, Kind, Message, Responder};
+
+type Result<T, E = String> = std::result::Result<T, E>;
 
 pub trait Api: Serialize {
     /// The service whose API is extended with this implementation.
@@ -52,12 +63,20 @@ impl ApiRouter {
         self
     }
 
-    /// Perpetually waits for incoming requests on `socket` and handles them with the handler
+    /// Waits for incoming requests on `socket` and handles them with the handler
     /// registered for their route (see [register_handler](Api::register_handler)), sending
-    /// back the computed reply.
+    /// back the computed reply, until a [`Kind::Shutdown`] message is received.
     pub fn serve_on(mut self, socket: Responder) -> Result<()> {
         loop {
-            let Message { api_name, data } = socket.next_request()?;
+            let Message {
+                kind,
+                api_name,
+                data,
+                ..
+            } = socket.next_request()?;
+            if kind == Kind::Shutdown {
+                return Ok(());
+            }
 
             let reply = match self
                 .handlers
@@ -71,6 +90,8 @@ impl ApiRouter {
                     let error_response = Message {
                         api_name,
                         data: error_message,
+                        kind: Kind::Error,
+                        ..Default::default()
                     };
                     if let Err(e) = socket.send_response(error_response) {
                         eprintln!("Failed to reply to invalid request: {e}",);
@@ -82,6 +103,8 @@ impl ApiRouter {
             let response = Message {
                 api_name,
                 data: reply,
+                kind: Kind::Reply,
+                ..Default::default()
             };
             socket.send_response(response)?;
         }
@@ -119,3 +142,57 @@ impl BoxedHandler {
         Self(Box::new(handler))
     }
 }
+
+const TEXT_SERVICE: &str = "text";
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct UppercaseRequest<'a>(&'a str);
+
+impl Api for UppercaseRequest<'_> {
+    type Reply = String;
+    type Request<'de> = UppercaseRequest<'de>;
+
+    const NAME: &'static str = "upper";
+    const SERVICE: &'static str = TEXT_SERVICE;
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct LowercaseRequest<'a>(&'a str);
+
+impl Api for LowercaseRequest<'_> {
+    type Reply = String;
+    type Request<'de> = LowercaseRequest<'de>;
+
+    const NAME: &'static str = "lower";
+    const SERVICE: &'static str = TEXT_SERVICE;
+}
+
+fn main() {
+    let (requester, responder) = channel::new_pair();
+
+    thread::spawn(|| {
+        ApiRouter::new()
+            .register_handler::<UppercaseRequest, _>(|request| request.0.to_uppercase())
+            .register_handler::<LowercaseRequest, _>(|request| request.0.to_lowercase())
+            .serve_on(responder)
+            .expect("to run forever");
+    });
+
+    // Repeatedly read lines from the terminal and send them off as requests
+    let stdin = io::stdin();
+    for line in stdin.lock().lines() {
+        match line {
+            Ok(input) => {
+                let request = Message {
+                    service: TEXT_SERVICE.to_string(),
+                    api_name: UppercaseRequest::NAME.to_string(),
+                    data: serde_json::to_vec(&UppercaseRequest(&input)).unwrap(),
+                    ..Default::default()
+                };
+                let response = requester.send_request(request).unwrap();
+                println!("Uppercase: {}", String::from_utf8_lossy(&response.data));
+            }
+            Err(e) => eprintln!("Input error: {e}"),
+        }
+    }
+}
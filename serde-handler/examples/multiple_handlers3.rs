@@ -0,0 +1,169 @@
+//! Like `multiple_handlers2`, with the `HandlerOn` bound written in terms of `Self::Api`.
+//!
+//! Does not compile (E0191, E0207, E0223).
+//!
+//! One of the historical steps towards [`serde_handler::api`], kept to show how it came about.
+//! Run with `cargo run --example multiple_handlers3 --features multiple_handlers3`.
+
+use std::{
+    collections::HashMap,
+    io::{self, BufRead},
+    thread,
+};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use serde_handler::channel::{self, Kind, Message, Requester, Responder};
+
+type Result<T, E = String> = std::result::Result<T, E>;
+
+pub trait Api: Serialize {
+    /// The service whose API is extended with this implementation.
+    ///
+    /// `Request`s of the implementing type will be sent to this service.
+    const SERVICE: &'static str;
+
+    /// The unique name of the API that identifies the kind of `Request` to the `SERVICE`.
+    const NAME: &'static str;
+
+    /// The request body.
+    type Request<'de>: Serialize + Deserialize<'de>;
+
+    /// The data returned to answer a `Request`.
+    type Reply: Serialize + DeserializeOwned;
+}
+
+pub struct ApiRouter {
+    handlers: HashMap<&'static str, Box<dyn Handler>>,
+}
+
+impl ApiRouter {
+    /// Create a new `Router`.
+    ///
+    /// Unless you add additional routes via [`register_handler`](ApiRouter::register_handler), this
+    /// will respond with `InvalidRequest` to all requests.
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Self {
+        Self {
+            handlers: HashMap::new(),
+        }
+    }
+
+    /// Add a new handler for API requests of type `A`.
+    ///
+    /// This will make the router route all requests of type `A` to the given `handler` if the
+    /// request data can be successfully deserialized into [`A::Request`](Api::Request).
+    /// The `handler` may be a function name or a closure.
+    pub fn register_handler<A: Api, H: Handler<Api = A>>(mut self, handler: H) -> Self
+    where
+        H: 'static,
+    {
+        self.handlers.insert(A::NAME, handler);
+        self
+    }
+
+    /// Waits for incoming requests on `socket` and handles them with the handler
+    /// registered for their route (see [register_handler](ApiRouter::register_handler)), sending
+    /// back the computed reply, until a [`Kind::Shutdown`] message is received.
+    pub fn serve_on(mut self, socket: Responder) -> Result<()> {
+        loop {
+            let Message {
+                kind,
+                api_name,
+                data,
+                ..
+            } = socket.next_request()?;
+            if kind == Kind::Shutdown {
+                return Ok(());
+            }
+
+            let handler = self
+                .handlers
+                .get_mut(api_name.as_str())
+                .ok_or_else(|| format!("No handler for '{api_name}'",))?;
+            let reply = handler(&data)?;
+            let response = Message {
+                api_name,
+                data: reply,
+                kind: Kind::Reply,
+                ..Default::default()
+            };
+            socket.send_response(response)?;
+        }
+    }
+}
+
+/// A function that can handle [`A::Request<'de>`](Api::Request) for `'de == 'req`.
+pub trait HandlerOn<'req>: FnMut(Self::Api::Request<'req>) -> Self::Api::Reply {
+    type Api: Api;
+}
+impl<'req, A: Api, F: FnMut(A::Request<'req>) -> A::Reply> HandlerOn<'req> for F {
+    type Api = A;
+}
+
+/// A function that can handle [`A::Request<'de>`](Api::Request) for any `'de`.
+pub trait Handler: for<'req> HandlerOn<'req> {}
+impl<F: for<'req> HandlerOn<'req>> Handler for F {}
+
+fn request<'a, A: Api<Request<'a> = A>>(requester: &Requester, request: A) -> Result<A::Reply> {
+    let data = serde_json::to_vec_pretty(&request).map_err(|e| format!("Serialize error: {e}"))?;
+    let request = Message {
+        service: A::SERVICE.to_string(),
+        api_name: A::NAME.to_string(),
+        data,
+        ..Default::default()
+    };
+    let response = requester.send_request(request)?;
+    assert_eq!(response.api_name, A::NAME);
+    serde_json::from_slice(&response.data).map_err(|e| format!("Deserialize error: {e}"))
+}
+
+const TEXT_SERVICE: &str = "text";
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct UppercaseRequest<'a>(&'a str);
+
+impl Api for UppercaseRequest<'_> {
+    type Reply = String;
+    type Request<'de> = UppercaseRequest<'de>;
+
+    const NAME: &'static str = "upper";
+    const SERVICE: &'static str = TEXT_SERVICE;
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct LowercaseRequest<'a>(&'a str);
+
+impl Api for LowercaseRequest<'_> {
+    type Reply = String;
+    type Request<'de> = LowercaseRequest<'de>;
+
+    const NAME: &'static str = "lower";
+    const SERVICE: &'static str = TEXT_SERVICE;
+}
+
+fn main() {
+    let (requester, responder) = channel::new_pair();
+
+    thread::spawn(|| {
+        ApiRouter::new()
+            .register_handler::<UppercaseRequest, _>(|request| request.0.to_uppercase())
+            .register_handler::<LowercaseRequest, _>(|request| request.0.to_lowercase())
+            .serve_on(responder)
+            .expect("to run forever");
+    });
+
+    // Repeatedly read lines from the terminal and send them off as requests
+    let stdin = io::stdin();
+    for line in stdin.lock().lines() {
+        match line {
+            Ok(input) => {
+                let upper = request(&requester, UppercaseRequest(&input)).unwrap();
+                println!("Uppercase: {upper}");
+                let lower = request(&requester, LowercaseRequest(&input)).unwrap();
+                println!("Lowercase: {lower}");
+            }
+            Err(e) => eprintln!("Input error: {e}"),
+        }
+    }
+}
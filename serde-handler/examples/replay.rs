@@ -0,0 +1,92 @@
+//! Records the requests of a service to a log, and replays the log to find changed replies.
+//!
+//! Usage:
+//!
+//! - `replay record <log>` serves the `text` service with a [`RequestLog`] appending to `log`,
+//!   and sends each line read from stdin as an `upper` request.
+//! - `replay <log>` replays the requests of `log` to the `text` service and prints every reply
+//!   that differs from the recorded one. It exits with status 1 if any reply differs.
+//!
+//! To replay the log of your own service, build its router in [`text_service_router`] instead.
+
+use std::{
+    env,
+    io::{self, BufRead},
+    process, thread,
+};
+
+use serde::{Deserialize, Serialize};
+
+use serde_handler::{
+    api::*,
+    channel,
+    request_log::{self, RequestLog},
+};
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct UppercaseRequest<'a>(&'a str);
+
+impl Api for UppercaseRequest<'_> {
+    type Reply<'de> = String;
+    type Request<'de> = UppercaseRequest<'de>;
+
+    const NAME: &'static str = "upper";
+    const SERVICE: &'static str = "text";
+}
+
+fn text_service_router() -> ApiRouter {
+    ApiRouter::new().register_handler::<UppercaseRequest, _>(|req| req.0.to_uppercase())
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let result = match args.as_slice() {
+        [_, command, log] if command == "record" => record(log),
+        [_, log] => replay(log),
+        _ => {
+            eprintln!("Usage: replay [record] <log>");
+            process::exit(2);
+        }
+    };
+    if let Err(e) = result {
+        eprintln!("{e}");
+        process::exit(1);
+    }
+}
+
+/// Sends the lines read from stdin to a service that logs its requests to `log`.
+fn record(log: &str) -> Result<(), String> {
+    let log = RequestLog::open(log)?;
+    let (requester, responder) = channel::new_pair();
+    let service = thread::spawn(move || text_service_router().layer(log).serve_on(responder));
+
+    let stdin = io::stdin();
+    for line in stdin.lock().lines() {
+        let line = line.map_err(|e| format!("Input error: {e}"))?;
+        match requester.request(UppercaseRequest(&line)) {
+            Ok(reply) => println!("{reply}"),
+            Err(e) => eprintln!("Error: {e}"),
+        }
+    }
+
+    drop(requester);
+    service.join().expect("the service not to panic")
+}
+
+/// Replays the requests of `log` and prints the replies that changed.
+fn replay(log: &str) -> Result<(), String> {
+    let entries = RequestLog::read(log)?;
+    let mismatches = request_log::replay(&mut text_service_router(), &entries);
+    for mismatch in &mismatches {
+        println!("{mismatch}");
+    }
+    println!(
+        "{} of {} requests were answered differently",
+        mismatches.len(),
+        entries.len()
+    );
+    if !mismatches.is_empty() {
+        process::exit(1);
+    }
+    Ok(())
+}
@@ -5,7 +5,7 @@ use std::{
 
 use serde::{Deserialize, Serialize};
 
-use serde_handler::{channel, working::*};
+use serde_handler::{api::*, channel};
 
 const TEXT_SERVICE: &str = "text";
 
@@ -13,7 +13,7 @@ const TEXT_SERVICE: &str = "text";
 struct UppercaseRequest<'a>(&'a str);
 
 impl Api for UppercaseRequest<'_> {
-    type Reply = String;
+    type Reply<'de> = String;
     type Request<'de> = UppercaseRequest<'de>;
 
     const NAME: &'static str = "upper";
@@ -24,7 +24,7 @@ impl Api for UppercaseRequest<'_> {
 struct LowercaseRequest<'a>(&'a str);
 
 impl Api for LowercaseRequest<'_> {
-    type Reply = String;
+    type Reply<'de> = String;
     type Request<'de> = LowercaseRequest<'de>;
 
     const NAME: &'static str = "lower";
@@ -35,7 +35,7 @@ impl Api for LowercaseRequest<'_> {
 struct TrimRequest<'a>(&'a str);
 
 impl Api for TrimRequest<'_> {
-    type Reply = String;
+    type Reply<'de> = String;
     type Request<'de> = TrimRequest<'de>;
 
     const NAME: &'static str = "trim";
@@ -52,10 +52,10 @@ fn text_service_router() -> ApiRouter {
 fn main() {
     let (requester, responder) = channel::new_pair();
 
-    // Have the service run (forever)
-    thread::spawn(move || {
+    // Have the service run until there are no more requests
+    let service = thread::spawn(move || {
         let router = text_service_router();
-        router.serve_on(responder).expect("to run forever");
+        router.serve_on(responder)
     });
 
     // Repeatedly read lines from the terminal and send them off as requests
@@ -63,20 +63,26 @@ fn main() {
     for line in stdin.lock().lines() {
         match line {
             Ok(input) => {
-                // Text requests
-                let upper_request = UppercaseRequest(&input);
-                let upper = requester.request(upper_request).unwrap();
-                println!("Uppercase: {upper}");
-
-                let lower_request = LowercaseRequest(&input);
-                let lower = requester.request(lower_request).unwrap();
-                println!("Lowercase: {lower}");
-
-                let trim_request = TrimRequest(&input);
-                let trimmed = requester.request(trim_request).unwrap();
-                println!("Trimmed  : {trimmed}");
+                // Text requests, sent together in a single round trip
+                let (upper, lower, trimmed) = requester
+                    .batch()
+                    .add(UppercaseRequest(&input))
+                    .add(LowercaseRequest(&input))
+                    .add(TrimRequest(&input))
+                    .send()
+                    .unwrap();
+                println!("Uppercase: {}", upper.unwrap());
+                println!("Lowercase: {}", lower.unwrap());
+                println!("Trimmed  : {}", trimmed.unwrap());
             }
             Err(e) => eprintln!("Input error: {e}"),
         }
     }
+
+    // Hanging up shuts down the service
+    drop(requester);
+    service
+        .join()
+        .unwrap()
+        .expect("to shut down after all requests");
 }
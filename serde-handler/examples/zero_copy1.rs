@@ -0,0 +1,117 @@
+//! First attempt at requests that borrow from the request data: `serve_forever` picks the
+//! lifetime `'de`, which the data it deserializes from does not live for.
+//!
+//! Does not compile (E0597).
+//!
+//! One of the historical steps towards [`serde_handler::api`], kept to show how it came about.
+//! Run with `cargo run --example zero_copy1 --features zero_copy1`.
+
+use std::{
+    io::{self, BufRead},
+    thread,
+};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use serde_handler::channel::{self, Kind, Message, Requester, Responder};
+
+type Result<T, E = String> = std::result::Result<T, E>;
+
+pub trait Api: Serialize {
+    /// The service whose API is extended with this implementation.
+    ///
+    /// `Request`s of the implementing type will be sent to this service.
+    const SERVICE: &'static str;
+
+    /// The unique name of the API that identifies the kind of `Request` to the `SERVICE`.
+    const NAME: &'static str;
+
+    /// The request body.
+    type Request;
+
+    /// The data returned to answer a `Request`.
+    type Reply;
+}
+
+fn request<A: Api<Request = A>>(requester: &Requester, request: A) -> Result<A::Reply>
+where
+    A::Request: Serialize,
+    A::Reply: DeserializeOwned,
+{
+    let data = serde_json::to_vec_pretty(&request).map_err(|e| format!("Serialize error: {e}"))?;
+    let request = Message {
+        service: A::SERVICE.to_string(),
+        api_name: A::NAME.to_string(),
+        data,
+        ..Default::default()
+    };
+    let response = requester.send_request(request)?;
+    assert_eq!(response.api_name, A::NAME);
+    serde_json::from_slice(&response.data).map_err(|e| format!("Deserialize error: {e}"))
+}
+
+/// Waits for incoming requests on `responder` and handles them with the given `handler`,
+/// sending back the computed reply, until a [`Kind::Shutdown`] message is received.
+fn serve_forever<'de, A: Api, H>(responder: Responder, mut handler: H) -> Result<()>
+where
+    H: FnMut(A::Request) -> A::Reply,
+    A::Request: Deserialize<'de>,
+    A::Reply: Serialize,
+{
+    loop {
+        let Message {
+            kind,
+            api_name,
+            data,
+            ..
+        } = responder.next_request()?;
+        if kind == Kind::Shutdown {
+            return Ok(());
+        }
+        let data = serde_json::from_slice(&data).map_err(|e| format!("Deserialize error: {e}"))?;
+        let reply = handler(data);
+        let data =
+            serde_json::to_vec_pretty(&reply).map_err(|e| format!("Serialize error: {e}"))?;
+        let response = Message {
+            api_name,
+            data,
+            kind: Kind::Reply,
+            ..Default::default()
+        };
+        responder.send_response(response)?;
+    }
+}
+
+const TEXT_SERVICE: &str = "text";
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct UppercaseRequest<'a>(&'a str);
+
+impl<'de> Api for UppercaseRequest<'de> {
+    type Reply = String;
+    type Request = UppercaseRequest<'de>;
+
+    const NAME: &'static str = "upper";
+    const SERVICE: &'static str = TEXT_SERVICE;
+}
+
+fn main() {
+    let (requester, responder) = channel::new_pair();
+
+    thread::spawn(|| {
+        serve_forever::<UppercaseRequest, _>(responder, |request| request.0.to_uppercase())
+            .expect("to run forever");
+    });
+
+    // Repeatedly read lines from the terminal and send them off as requests
+    let stdin = io::stdin();
+    for line in stdin.lock().lines() {
+        match line {
+            Ok(input) => {
+                let upper = request(&requester, UppercaseRequest(&input)).unwrap();
+                println!("Uppercase: {upper}");
+            }
+            Err(e) => eprintln!("Input error: {e}"),
+        }
+    }
+}
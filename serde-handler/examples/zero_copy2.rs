@@ -1,11 +1,84 @@
+//! Second attempt at borrowed requests: `A::Request: for<'de> Deserialize<'de>` compiles, but is
+//! only satisfied by requests that do not borrow, so the example cannot use `UppercaseRequest`.
+//!
+//! One of the historical steps towards [`serde_handler::api`], kept to show how it came about.
+//! Run with `cargo run --example zero_copy2 --features zero_copy2`.
+
 use std::{
     io::{self, BufRead},
     thread,
 };
 
-use serde::{Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use serde_handler::channel::{self, Kind, Message, Requester, Responder};
+
+type Result<T, E = String> = std::result::Result<T, E>;
+
+pub trait Api: Serialize {
+    /// The service whose API is extended with this implementation.
+    ///
+    /// `Request`s of the implementing type will be sent to this service.
+    const SERVICE: &'static str;
 
-use serde_handler::{channel, zero_copy2::*};
+    /// The unique name of the API that identifies the kind of `Request` to the `SERVICE`.
+    const NAME: &'static str;
+
+    /// The request body.
+    type Request;
+
+    /// The data returned to answer a `Request`.
+    type Reply;
+}
+
+fn request<A: Api<Request = A>>(requester: &Requester, request: A) -> Result<A::Reply>
+where
+    A::Request: Serialize,
+    A::Reply: DeserializeOwned,
+{
+    let data = serde_json::to_vec_pretty(&request).map_err(|e| format!("Serialize error: {e}"))?;
+    let request = Message {
+        service: A::SERVICE.to_string(),
+        api_name: A::NAME.to_string(),
+        data,
+        ..Default::default()
+    };
+    let response = requester.send_request(request)?;
+    assert_eq!(response.api_name, A::NAME);
+    serde_json::from_slice(&response.data).map_err(|e| format!("Deserialize error: {e}"))
+}
+
+/// Waits for incoming requests on `responder` and handles them with the given `handler`,
+/// sending back the computed reply, until a [`Kind::Shutdown`] message is received.
+fn serve_forever<A: Api, H>(responder: Responder, mut handler: H) -> Result<()>
+where
+    H: FnMut(A::Request) -> A::Reply,
+    A::Request: for<'de> Deserialize<'de>, // works with `for<'de> Deserialize<'de>`
+    A::Reply: Serialize,
+{
+    loop {
+        let Message {
+            kind,
+            api_name,
+            data,
+            ..
+        } = responder.next_request()?;
+        if kind == Kind::Shutdown {
+            return Ok(());
+        }
+        let data = serde_json::from_slice(&data).map_err(|e| format!("Deserialize error: {e}"))?;
+        let reply = handler(data);
+        let data =
+            serde_json::to_vec_pretty(&reply).map_err(|e| format!("Serialize error: {e}"))?;
+        let response = Message {
+            api_name,
+            data,
+            kind: Kind::Reply,
+            ..Default::default()
+        };
+        responder.send_response(response)?;
+    }
+}
 
 const TEXT_SERVICE: &str = "text";
 
@@ -24,8 +97,8 @@ fn main() {
     let (requester, responder) = channel::new_pair();
 
     thread::spawn(|| {
-        responder
-            .serve_forever::<UppercaseRequest, _>(|request| request.0.to_uppercase().to_string());
+        serve_forever::<UppercaseRequest, _>(responder, |request| request.0.to_uppercase())
+            .expect("to run forever");
     });
 
     // Repeatedly read lines from the terminal and send them off as requests
@@ -33,9 +106,7 @@ fn main() {
     for line in stdin.lock().lines() {
         match line {
             Ok(input) => {
-                // Text requests
-                let upper_request = UppercaseRequest(&input);
-                let upper = requester.request(upper_request).unwrap();
+                let upper = request(&requester, UppercaseRequest(&input)).unwrap();
                 println!("Uppercase: {upper}");
             }
             Err(e) => eprintln!("Input error: {e}"),
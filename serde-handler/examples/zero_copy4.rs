@@ -1,11 +1,82 @@
+//! Like `zero_copy3`, with the handler bound named by the `HandlerOn` and `Handler` traits.
+//!
+//! One of the historical steps towards [`serde_handler::api`], kept to show how it came about.
+//! Run with `cargo run --example zero_copy4 --features zero_copy4`.
+
 use std::{
     io::{self, BufRead},
     thread,
 };
 
-use serde::{Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use serde_handler::channel::{self, Kind, Message, Requester, Responder};
+
+type Result<T, E = String> = std::result::Result<T, E>;
+
+pub trait Api: Serialize {
+    /// The service whose API is extended with this implementation.
+    ///
+    /// `Request`s of the implementing type will be sent to this service.
+    const SERVICE: &'static str;
+
+    /// The unique name of the API that identifies the kind of `Request` to the `SERVICE`.
+    const NAME: &'static str;
+
+    /// The request body.
+    type Request<'de>: Serialize + Deserialize<'de>;
+
+    /// The data returned to answer a `Request`.
+    type Reply: Serialize + DeserializeOwned;
+}
 
-use serde_handler::{channel, zero_copy4::*};
+/// A function that can handle [`A::Request<'de>`](Api::Request) for `'de == 'req`.
+pub trait HandlerOn<'req, A: Api>: FnMut(A::Request<'req>) -> A::Reply {}
+impl<'req, A: Api, F: FnMut(A::Request<'req>) -> A::Reply> HandlerOn<'req, A> for F {}
+
+/// A function that can handle [`A::Request<'de>`](Api::Request) for any `'de`.
+pub trait Handler<A: Api>: for<'req> HandlerOn<'req, A> {}
+impl<A: Api, F: for<'req> HandlerOn<'req, A>> Handler<A> for F {}
+
+fn request<'a, A: Api<Request<'a> = A>>(requester: &Requester, request: A) -> Result<A::Reply> {
+    let data = serde_json::to_vec_pretty(&request).map_err(|e| format!("Serialize error: {e}"))?;
+    let request = Message {
+        service: A::SERVICE.to_string(),
+        api_name: A::NAME.to_string(),
+        data,
+        ..Default::default()
+    };
+    let response = requester.send_request(request)?;
+    assert_eq!(response.api_name, A::NAME);
+    serde_json::from_slice(&response.data).map_err(|e| format!("Deserialize error: {e}"))
+}
+
+/// Waits for incoming requests on `responder` and handles them with the given `handler`,
+/// sending back the computed reply, until a [`Kind::Shutdown`] message is received.
+fn serve_forever<A: Api, H: Handler<A>>(responder: Responder, mut handler: H) -> Result<()> {
+    loop {
+        let Message {
+            kind,
+            api_name,
+            data,
+            ..
+        } = responder.next_request()?;
+        if kind == Kind::Shutdown {
+            return Ok(());
+        }
+        let data = serde_json::from_slice(&data).map_err(|e| format!("Deserialize error: {e}"))?;
+        let reply = handler(data);
+        let data =
+            serde_json::to_vec_pretty(&reply).map_err(|e| format!("Serialize error: {e}"))?;
+        let response = Message {
+            api_name,
+            data,
+            kind: Kind::Reply,
+            ..Default::default()
+        };
+        responder.send_response(response)?;
+    }
+}
 
 const TEXT_SERVICE: &str = "text";
 
@@ -24,8 +95,7 @@ fn main() {
     let (requester, responder) = channel::new_pair();
 
     thread::spawn(|| {
-        responder
-            .serve_forever::<UppercaseRequest, _>(|request| request.0.to_uppercase().to_string())
+        serve_forever::<UppercaseRequest, _>(responder, |request| request.0.to_uppercase())
             .expect("to run forever");
     });
 
@@ -34,9 +104,7 @@ fn main() {
     for line in stdin.lock().lines() {
         match line {
             Ok(input) => {
-                // Text requests
-                let upper_request = UppercaseRequest(&input);
-                let upper = requester.request(upper_request).unwrap();
+                let upper = request(&requester, UppercaseRequest(&input)).unwrap();
                 println!("Uppercase: {upper}");
             }
             Err(e) => eprintln!("Input error: {e}"),
@@ -0,0 +1,1240 @@
+//! Typed APIs and the services that answer them.
+//!
+//! An [`Api`] names a request type together with its service and reply. Services answer a single
+//! API with [`Responder::serve_forever`], or any number of them with an [`ApiRouter`], and
+//! requesters send them with [`Requester::request`]. Requests and replies may borrow from the data
+//! they are deserialized from.
+
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, HashMap, VecDeque},
+    marker::PhantomData,
+    rc::Rc,
+    sync::Arc,
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{
+    auth::Rule,
+    channel::{Error, Kind, Message, Overload, Requester, Responder},
+    compression::Encoding,
+    describe::{ApiDescription, ApiSchema, Describe, Description, RESERVED_SERVICE},
+    dynamic::RouterHandle,
+    event::{Event, EventBus},
+    extract::FromRequest,
+    headers::{self, Headers, PRINCIPAL, REQUEST_ID},
+    limit::{Limits, Permit},
+    metrics::{Metrics, Observation, Side, UNKNOWN},
+    middleware::{Middleware, Next, RawHandler},
+    streaming::{BoxedStream, BoxedStreamHandler, StreamHandler, StreamingApi, Streams},
+    transport::{PeerId, Transport},
+    Result,
+};
+
+#[cfg(feature = "derive")]
+pub use serde_handler_derive::{service, Api};
+
+pub trait Api: Serialize {
+    /// The service whose API is extended with this implementation.
+    ///
+    /// `Request`s of the implementing type will be sent to this service.
+    const SERVICE: &'static str;
+
+    /// The unique name of the API that identifies the kind of `Request` to the `SERVICE`.
+    const NAME: &'static str;
+
+    /// The request body.
+    type Request<'de>: Serialize + Deserialize<'de>;
+
+    /// The data returned to answer a `Request`.
+    ///
+    /// Like the `Request`, the reply may borrow from the data it is deserialized from. Handlers
+    /// can return replies that borrow from their request, and requesters can read borrowed
+    /// replies without copying them with [`Requester::request_borrowed`].
+    type Reply<'de>: Serialize + Deserialize<'de>;
+
+    /// How long [`Requester::request`] waits for a `Reply` by default.
+    ///
+    /// Requests are sent with a deadline this far in the future, after which the service no
+    /// longer handles them. `None` means to wait indefinitely.
+    const TIMEOUT: Option<Duration> = None;
+
+    /// The version of the `Request` and `Reply` layout, which is sent along with every request.
+    ///
+    /// Increase it when changing either in an incompatible way, and register an adapter from the
+    /// old version (see [`ApiRouter::register_adapter`]) to keep serving older requesters.
+    const VERSION: u32 = 1;
+
+    /// Whether handling the same `Request` several times has the same effect as handling it once.
+    ///
+    /// Only requests for idempotent APIs are retried by a [`Requester`] with a
+    /// [`RetryPolicy`](crate::retry::RetryPolicy). Requests for other APIs carry a
+    /// [`REQUEST_ID`], which routers use to answer a request that is sent again without handling
+    /// it again.
+    const IDEMPOTENT: bool = false;
+
+    /// The JSON Schemas of `Request` and `Reply`, which routers include in their
+    /// [`Description`].
+    ///
+    /// Usually implemented as `Some(ApiSchema::of::<Self, Self::Reply<'static>>())`.
+    fn schema() -> Option<ApiSchema> {
+        None
+    }
+}
+
+/// Routes requests to the handlers registered for them.
+///
+/// Handlers are keyed by both [`Api::SERVICE`] and [`Api::NAME`], so a single router can host
+/// several services whose APIs share names. Each API can have handlers for several
+/// [`Api::VERSION`]s.
+///
+/// Every router also answers the [`Describe`] API of the reserved
+/// [`RESERVED_SERVICE`](crate::describe::RESERVED_SERVICE) with a description of its routes. Like
+/// any other API, it can be restricted with [`authorize::<Describe>`](ApiRouter::authorize) and
+/// [`limit::<Describe>`](ApiRouter::limit).
+///
+/// A router owns an application state `S`, such as a database connection or a cache, that it
+/// lends to its handlers (see [`with_state`](ApiRouter::with_state)).
+pub struct ApiRouter<S = ()> {
+    services: HashMap<&'static str, HashMap<&'static str, Versions<S>>>,
+    state: S,
+    middleware: Vec<Box<dyn Middleware>>,
+    on_start: Vec<Box<dyn FnOnce()>>,
+    on_stop: Vec<Box<dyn FnOnce()>>,
+    handle: RouterHandle,
+    limits: HashMap<(&'static str, &'static str), Limits>,
+    rules: HashMap<(&'static str, &'static str), Rule>,
+    /// The replies to the last requests with a [`REQUEST_ID`], by the ID and the principal of the
+    /// request, oldest first.
+    replied: VecDeque<(RepliedKey, Message)>,
+    metrics: Option<Arc<dyn Metrics>>,
+    events: Option<EventBus>,
+}
+
+/// The [`REQUEST_ID`] and [`PRINCIPAL`] of a request that was replied to.
+type RepliedKey = (String, Option<String>);
+
+/// How many replies to requests with a [`REQUEST_ID`] a router remembers, to answer them again.
+const REMEMBERED_REPLIES: usize = 256;
+
+impl ApiRouter {
+    /// Create a new `Router`.
+    ///
+    /// Unless you add additional routes via [`register_handler`](ApiRouter::register_handler), this
+    /// will respond with an error to all requests.
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Self {
+        Self::with_state(())
+    }
+}
+
+impl<S: 'static> ApiRouter<S> {
+    /// Create a new `Router` that owns `state`.
+    ///
+    /// Handlers registered with
+    /// [`register_stateful_handler`](ApiRouter::register_stateful_handler) get mutable access to
+    /// the state, and ones registered with [`register_handler_with`](ApiRouter::register_handler_with)
+    /// can extract a copy of it with [`State`](crate::extract::State).
+    pub fn with_state(state: S) -> Self {
+        Self {
+            services: HashMap::new(),
+            state,
+            middleware: Vec::new(),
+            on_start: Vec::new(),
+            on_stop: Vec::new(),
+            handle: RouterHandle::default(),
+            limits: HashMap::new(),
+            rules: HashMap::new(),
+            replied: VecDeque::new(),
+            metrics: None,
+            events: None,
+        }
+    }
+
+    /// Add a new handler for API requests of type `A`.
+    ///
+    /// This will make the router route all requests of type `A` to the given `handler` if the
+    /// request data can be successfully deserialized into [`A::Request`](Api::Request).
+    /// The `handler` may be a function name or a closure.
+    ///
+    /// # Panics
+    ///
+    /// If a handler or adapter for `A` (that is, for [`A::VERSION`](Api::VERSION) of
+    /// [`A::NAME`](Api::NAME) in [`A::SERVICE`](Api::SERVICE)) has already been registered, or if
+    /// `A` belongs to the reserved service of [`Describe`].
+    pub fn register_handler<A: Api, H: Handler<A> + 'static>(mut self, mut handler: H) -> Self {
+        let route = Route::Handler(BoxedHandler::new::<A, _>(move |_, _, request| {
+            Ok(handler(request))
+        }));
+        self.insert(A::SERVICE, A::NAME, A::VERSION, route);
+        self
+    }
+
+    /// Add a new handler for API requests of type `A` that also gets mutable access to the
+    /// router's state.
+    ///
+    /// # Panics
+    ///
+    /// Like [`register_handler`](ApiRouter::register_handler).
+    pub fn register_stateful_handler<A: Api, H: StatefulHandler<A, S> + 'static>(
+        mut self,
+        mut handler: H,
+    ) -> Self {
+        let route = Route::Handler(BoxedHandler::new::<A, _>(move |state, _, request| {
+            Ok(handler(state, request))
+        }));
+        self.insert(A::SERVICE, A::NAME, A::VERSION, route);
+        self
+    }
+
+    /// Add a new handler for API requests of type `A` that also gets the values `E` extracted
+    /// from the request and the router's state, such as the [`Context`](crate::extract::Context)
+    /// of the request.
+    ///
+    /// `E` can be any [`FromRequest`] type, including tuples of them. Requests for which the
+    /// extraction fails are answered with its error.
+    ///
+    /// # Panics
+    ///
+    /// Like [`register_handler`](ApiRouter::register_handler).
+    pub fn register_handler_with<A, E, H>(mut self, mut handler: H) -> Self
+    where
+        A: Api,
+        E: FromRequest<S>,
+        H: HandlerWith<A, E> + 'static,
+    {
+        let route = Route::Handler(BoxedHandler::new::<A, _>(move |state, message, request| {
+            let extracted = E::from_request(message, state)?;
+            Ok(handler(extracted, request))
+        }));
+        self.insert(A::SERVICE, A::NAME, A::VERSION, route);
+        self
+    }
+
+    /// Add a new handler for requests of type `A` that answers each request with a stream of
+    /// replies.
+    ///
+    /// Middleware only runs around the start of the stream, i.e. around calling `handler`. The
+    /// replies are then produced on a thread of their own, while the router goes on answering
+    /// other requests. [`Limits`] of `A` count the stream as being handled until it ends.
+    ///
+    /// # Panics
+    ///
+    /// Like [`register_handler`](ApiRouter::register_handler).
+    pub fn register_stream_handler<A, I, H>(mut self, handler: H) -> Self
+    where
+        A: StreamingApi,
+        I: IntoIterator<Item = A::Reply<'static>, IntoIter: Send + 'static>,
+        H: StreamHandler<A, I> + 'static,
+    {
+        let route = Route::Stream(BoxedStreamHandler::from_handler(handler));
+        self.insert(A::SERVICE, A::NAME, A::VERSION, route);
+        self
+    }
+
+    /// Serve requests for `Old`, an older version of the API `New`, by adapting them into requests
+    /// for `New`.
+    ///
+    /// Requests for [`Old::VERSION`](Api::VERSION) are turned into requests for
+    /// [`New::VERSION`](Api::VERSION) with `adapt` and handled by whatever is registered for that
+    /// version, which may itself be an adapter. The reply is converted back with `From`.
+    ///
+    /// # Panics
+    ///
+    /// If `Old` and `New` are not the same API of the same service, or if a handler or adapter
+    /// for `Old` has already been registered.
+    pub fn register_adapter<Old: Api, New: Api, F: Adapt<Old, New> + 'static>(
+        mut self,
+        adapt: F,
+    ) -> Self
+    where
+        for<'de> Old::Reply<'de>: From<New::Reply<'de>>,
+    {
+        assert!(
+            Old::SERVICE == New::SERVICE && Old::NAME == New::NAME,
+            "Cannot adapt '{}' in service '{}' to '{}' in service '{}'",
+            Old::NAME,
+            Old::SERVICE,
+            New::NAME,
+            New::SERVICE,
+        );
+        let adapt = move |request_data: &[u8], next: &mut RawHandler<'_>| -> Result<Vec<u8>> {
+            let request: Old::Request<'_> = serde_json::from_slice(request_data)
+                .map_err(|e| format!("Deserialize error: {e}"))?;
+            let request =
+                serde_json::to_vec(&adapt(request)).map_err(|e| format!("Serialize error: {e}"))?;
+            let reply_data = next(&request)?;
+            let reply: New::Reply<'_> = serde_json::from_slice(&reply_data)
+                .map_err(|e| format!("Deserialize error: {e}"))?;
+            let reply = serde_json::to_vec(&Old::Reply::from(reply))
+                .map_err(|e| format!("Serialize error: {e}"))?;
+            Ok(reply)
+        };
+        let route = Route::Adapter {
+            target: New::VERSION,
+            adapt: Rc::new(adapt),
+            schema: Old::schema(),
+        };
+        self.insert(Old::SERVICE, Old::NAME, Old::VERSION, route);
+        self
+    }
+
+    /// Wrap every handler of this router in `middleware`.
+    ///
+    /// Middleware runs in the order it was added, so the first layer sees each request first and
+    /// its reply last. Layers apply to all handlers, including ones registered after the layer.
+    pub fn layer<M: Middleware + 'static>(mut self, middleware: M) -> Self {
+        self.middleware.push(Box::new(middleware));
+        self
+    }
+
+    /// Limit the requests for `A` (for all of its versions) to `limits`.
+    ///
+    /// Requests that exceed a limit are answered with [`Error::Overloaded`] without being
+    /// handled. Replaces any limits that were set for `A` before.
+    pub fn limit<A: Api>(mut self, limits: Limits) -> Self {
+        self.limits.insert((A::SERVICE, A::NAME), limits);
+        self
+    }
+
+    /// Only handle the requests for `A` (for all of its versions) that `rule` allows, given the
+    /// principal they were authenticated as by the [`Responder`]'s
+    /// [authenticator](Responder::with_authenticator).
+    ///
+    /// Requests that are not allowed are answered with [`Error::Unauthorized`] without being
+    /// handled. APIs without a rule can be requested by anyone. Replaces any rule that was set for
+    /// `A` before.
+    pub fn authorize<A: Api>(mut self, rule: Rule) -> Self {
+        self.rules.insert((A::SERVICE, A::NAME), rule);
+        self
+    }
+
+    /// Only deliver the events of `E` that `rule` allows to the [event bus](Self::with_events),
+    /// given the principal they were authenticated as, like [`authorize`](Self::authorize) does
+    /// for requests.
+    ///
+    /// Events that are not allowed are dropped. Replaces any rule that was set for `E` before.
+    pub fn authorize_event<E: Event>(mut self, rule: Rule) -> Self {
+        self.rules.insert((E::SERVICE, E::NAME), rule);
+        self
+    }
+
+    /// Measure every request this router handles, including the ones in a [`Kind::Batch`], with
+    /// `metrics`.
+    ///
+    /// Requests that are answered without running a handler, e.g. because of a limit, are
+    /// measured with their error. Requests for routes this router does not serve are measured
+    /// as requests for the API [`UNKNOWN`](crate::metrics::UNKNOWN) in the service of that name.
+    /// Requests for streaming APIs are not measured.
+    pub fn with_metrics(mut self, metrics: impl Metrics + 'static) -> Self {
+        self.metrics = Some(Arc::new(metrics));
+        self
+    }
+
+    /// Deliver the [`Kind::Event`]s this router receives to the subscribers of `events`, in
+    /// between handling requests.
+    ///
+    /// Routers without an event bus drop the events they receive.
+    pub fn with_events(mut self, events: EventBus) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    /// Run `hook` when [`serve_on`](ApiRouter::serve_on) starts, before handling any requests.
+    pub fn on_start(mut self, hook: impl FnOnce() + 'static) -> Self {
+        self.on_start.push(Box::new(hook));
+        self
+    }
+
+    /// Run `hook` when [`serve_on`](ApiRouter::serve_on) stops, whether because of a shutdown or
+    /// an error.
+    pub fn on_stop(mut self, hook: impl FnOnce() + 'static) -> Self {
+        self.on_stop.push(Box::new(hook));
+        self
+    }
+
+    /// Add all routes of `other` to this router, so that both can be served on one
+    /// [`Responder`]. Middleware and hooks of `other` are not kept, but its handlers keep using
+    /// its state.
+    ///
+    /// Handlers registered through a [`handle`](ApiRouter::handle) of `other` are moved to this
+    /// router, and its handles no longer have an effect. The [`limit`](ApiRouter::limit)s and
+    /// [authorization rules](ApiRouter::authorize) of `other` are kept, unless this router has
+    /// its own for the same API.
+    ///
+    /// # Panics
+    ///
+    /// If both routers have a handler for the same API of the same service.
+    pub fn merge<O: 'static>(mut self, other: ApiRouter<O>) -> Self {
+        self.handle.absorb(&other.handle);
+        for (api, limits) in other.limits {
+            self.limits.entry(api).or_insert(limits);
+        }
+        for (api, rule) in other.rules {
+            self.rules.entry(api).or_insert(rule);
+        }
+        let state = Rc::new(RefCell::new(other.state));
+        for (service, handlers) in other.services {
+            for (api_name, versions) in handlers {
+                for (version, route) in versions {
+                    self.insert(service, api_name, version, Route::with_state(route, &state));
+                }
+            }
+        }
+        self
+    }
+
+    /// Returns a handle that registers, replaces and removes handlers of this router, also while
+    /// it is serving, and lists its routes.
+    ///
+    /// All handles of a router share its routes. See [`RouterHandle`] for details.
+    pub fn handle(&self) -> RouterHandle {
+        self.handle.clone()
+    }
+
+    /// Returns whether this router has any handler for `service`.
+    pub fn serves(&self, service: &str) -> bool {
+        service == RESERVED_SERVICE
+            || self.services.contains_key(service)
+            || self.handle.serves(service)
+    }
+
+    /// Describes all services and APIs of this router, as returned by the [`Describe`] API.
+    pub fn describe(&self) -> Description {
+        let mut services: BTreeMap<_, BTreeMap<_, _>> = BTreeMap::new();
+        let apis = self.services.iter().flat_map(|(service, handlers)| {
+            handlers.iter().map(move |(api_name, versions)| {
+                let (_, newest) = versions.last_key_value().expect("APIs have a route");
+                let description = ApiDescription {
+                    versions: versions.keys().copied().collect(),
+                    streaming: matches!(newest, Route::Stream(_)),
+                    schema: newest.schema().cloned(),
+                };
+                (*service, *api_name, description)
+            })
+        });
+        let describe = ApiDescription {
+            versions: vec![Describe::VERSION],
+            streaming: false,
+            schema: Describe::schema(),
+        };
+        for (service, api_name, description) in
+            apis.chain([(Describe::SERVICE, Describe::NAME, describe)])
+        {
+            services
+                .entry(service.to_string())
+                .or_default()
+                .insert(api_name.to_string(), description);
+        }
+        // Handlers registered through a handle add versions, and may replace the newest one.
+        for (service, api_name, dynamic) in self.handle.describe() {
+            let description = services
+                .entry(service.to_string())
+                .or_default()
+                .entry(api_name.to_string())
+                .or_default();
+            let mut versions = std::mem::take(&mut description.versions);
+            versions.extend(&dynamic.versions);
+            versions.sort_unstable();
+            versions.dedup();
+            if versions.last() == dynamic.versions.last() {
+                *description = dynamic;
+            }
+            description.versions = versions;
+        }
+        Description { services }
+    }
+
+    fn insert(
+        &mut self,
+        service: &'static str,
+        api_name: &'static str,
+        version: u32,
+        route: Route<S>,
+    ) {
+        if service == RESERVED_SERVICE {
+            panic!("Service '{RESERVED_SERVICE}' is reserved");
+        }
+        let versions = self
+            .services
+            .entry(service)
+            .or_default()
+            .entry(api_name)
+            .or_default();
+        if versions.insert(version, route).is_some() {
+            panic!(
+                "Duplicate handler for '{api_name}' in service '{service}' at version {version}"
+            );
+        }
+        self.handle.add_fixed(service, api_name, version);
+    }
+
+    /// Handles `request` with the handler registered for its route, returning the serialized
+    /// reply or stream of replies.
+    fn dispatch(&mut self, request: &Message) -> Result<Dispatched, Error> {
+        let Message {
+            service, api_name, ..
+        } = request;
+        if let Some(rule) = for_api(&self.rules, service, api_name) {
+            if !rule.allows(request) {
+                let error = Error::Unauthorized(format!(
+                    "Not allowed to request '{api_name}' in service '{service}'"
+                ));
+                return Err(reject(&mut self.middleware, request, error));
+            }
+        }
+        if service == RESERVED_SERVICE && api_name == Describe::NAME {
+            let _permit = permit(&self.limits, service, api_name)
+                .map_err(|e| reject(&mut self.middleware, request, e))?;
+            let description = serde_json::to_vec(&self.describe())
+                .map_err(|e| format!("Serialize error: {e}"))?;
+            let mut handler = move |_: &[u8]| Ok(description.clone());
+            let reply = Next::new(&mut self.middleware, &mut handler).run(request)?;
+            return Ok(Dispatched::Reply(reply));
+        }
+        if !self.serves(service) {
+            let error = format!("Unknown service '{service}'").into();
+            return Err(reject(&mut self.middleware, request, error));
+        }
+        let mut versions = self
+            .services
+            .get_mut(service.as_str())
+            .and_then(|handlers| handlers.get_mut(api_name.as_str()));
+        let mut supported = self.handle.versions(service, api_name);
+        supported.extend(versions.iter().flat_map(|versions| versions.keys()));
+        supported.sort_unstable();
+        supported.dedup();
+        let Some(&newest) = supported.last() else {
+            let error = format!("No handler for '{api_name}' in service '{service}'").into();
+            return Err(reject(&mut self.middleware, request, error));
+        };
+        let version = match request.version {
+            0 => newest,
+            version if supported.contains(&version) => version,
+            requested => {
+                let error = Error::IncompatibleVersion {
+                    requested,
+                    supported,
+                };
+                return Err(reject(&mut self.middleware, request, error));
+            }
+        };
+        let permit = permit(&self.limits, service, api_name)
+            .map_err(|e| reject(&mut self.middleware, request, e))?;
+        // Handlers registered through a handle take precedence.
+        if let Some(handler) = self.handle.get(service, api_name, version) {
+            if request.kind == Kind::StreamRequest {
+                let error = Error::Other(format!(
+                    "'{api_name}' in service '{service}' is not a streaming API"
+                ));
+                return Err(reject(&mut self.middleware, request, error));
+            }
+            let mut handler = |request_data: &[u8]| handler.call(request_data);
+            let reply = Next::new(&mut self.middleware, &mut handler).run(request)?;
+            return Ok(Dispatched::Reply(reply));
+        }
+        let versions = versions
+            .as_mut()
+            .expect("versions without a dynamic handler are routes of the router");
+        let streaming = matches!(versions.get(&version), Some(Route::Stream(_)));
+        if streaming != (request.kind == Kind::StreamRequest) {
+            let expected = if streaming {
+                "a streaming"
+            } else {
+                "not a streaming"
+            };
+            let error = Error::Other(format!(
+                "'{api_name}' in service '{service}' is {expected} API"
+            ));
+            return Err(reject(&mut self.middleware, request, error));
+        }
+        if let Some(Route::Stream(handler)) = versions.get_mut(&version) {
+            let mut stream = None;
+            let mut start = |request_data: &[u8]| -> Result<Vec<u8>> {
+                stream = Some((handler.handle)(request_data)?);
+                Ok(Vec::new())
+            };
+            let reply = Next::new(&mut self.middleware, &mut start).run(request)?;
+            // Middleware may answer without starting the stream.
+            return Ok(match stream {
+                Some(stream) => Dispatched::Stream(stream, permit),
+                None => Dispatched::Reply(reply),
+            });
+        }
+        let state = &mut self.state;
+        let mut handler =
+            |request_data: &[u8]| call_version(versions, state, request, version, request_data);
+        let reply = Next::new(&mut self.middleware, &mut handler).run(request)?;
+        Ok(Dispatched::Reply(reply))
+    }
+
+    /// Waits for incoming requests on `socket` and handles them with the handler registered for
+    /// their route (see [register_handler](ApiRouter::register_handler)), sending back the
+    /// computed reply.
+    ///
+    /// Requests that cannot be handled, e.g. because they are for a service or API this router
+    /// does not know, are answered with a [`Kind::Error`] reply. This includes requests whose
+    /// [`deadline`](Message::deadline) has passed by the time they would be handled, which are
+    /// answered with [`Error::DeadlineExceeded`] without running their handler.
+    ///
+    /// Serving stops with `Ok(())` when `socket` receives a [`Kind::Shutdown`] message, i.e. once
+    /// all requesters have hung up or the socket's
+    /// [`shutdown_handle`](Responder::shutdown_handle) was used. The request that is being
+    /// handled at that point is always answered first.
+    ///
+    /// The requests of a [`Kind::Batch`] are handled in order, each like a single request, and
+    /// answered with one reply that holds the replies or errors of all of them.
+    ///
+    /// A request with the [`REQUEST_ID`] of one of the last requests that were handled, e.g.
+    /// because it was sent again after the connection broke, is answered with the same reply
+    /// without running its handler again.
+    ///
+    /// With a [`Server`](crate::transport::Server) transport, this serves every client that
+    /// connects to the server's socket. Replies that cannot be sent, e.g. because their client
+    /// hung up, are dropped without stopping the router.
+    pub fn serve_on<T: Transport>(mut self, socket: Responder<T>) -> Result<()> {
+        for hook in self.on_start.drain(..) {
+            hook();
+        }
+        let result = self.serve_until_shutdown(&socket);
+        for hook in self.on_stop.drain(..) {
+            hook();
+        }
+        result
+    }
+
+    fn serve_until_shutdown<T: Transport>(&mut self, socket: &Responder<T>) -> Result<()> {
+        let queue_limits = self
+            .limits
+            .values()
+            .any(|limits| limits.queue_limit().is_some());
+        let mut queued = VecDeque::new();
+        let mut streams = Streams::new();
+        loop {
+            if !streams.is_empty() {
+                // Requests that are waiting in the queue are not held up by streams.
+                let timeout = if queued.is_empty() {
+                    STREAM_POLL_INTERVAL
+                } else {
+                    Duration::ZERO
+                };
+                streams.forward(socket, timeout);
+            }
+            let request = match queued.pop_front() {
+                Some((peer, request)) => {
+                    // Replies go to the requester that sent the queued request.
+                    socket.set_current_peer(peer);
+                    request
+                }
+                None if streams.is_empty() => socket.next_request()?,
+                None => match socket.try_next_request()? {
+                    Some(request) => request,
+                    None => continue,
+                },
+            };
+            // Requests only wait in a queue if some API limits its length.
+            if queue_limits {
+                self.fill_queue(socket, &mut queued)?;
+            }
+            if let Some(reply) = self.replied_before(&request) {
+                socket.send_reply(reply);
+                continue;
+            }
+            // Requests made by the handler carry on the correlation ID of this request.
+            let _correlation = headers::enter(&request.headers);
+            let observation = self.observe(&request);
+            let response = match request.kind {
+                Kind::Request | Kind::StreamRequest | Kind::Batch if request.is_expired() => {
+                    request.error(reject(
+                        &mut self.middleware,
+                        &request,
+                        Error::DeadlineExceeded,
+                    ))
+                }
+                Kind::Request | Kind::StreamRequest => match self.dispatch(&request) {
+                    Ok(Dispatched::Reply(reply)) => request.reply(reply),
+                    Ok(Dispatched::Stream(stream, permit)) => {
+                        streams.start(socket.current_peer(), &request, stream, permit);
+                        continue;
+                    }
+                    Err(e) => request.error(e),
+                },
+                Kind::Batch => match Message::decode_batch(&request.data) {
+                    Ok(requests) => {
+                        let replies: Vec<_> = requests
+                            .into_iter()
+                            .map(|mut batched| {
+                                // The batch was authenticated as a whole.
+                                match request.headers.get(PRINCIPAL) {
+                                    Some(principal) => batched.headers.insert(PRINCIPAL, principal),
+                                    None => batched.headers.remove(PRINCIPAL),
+                                };
+                                self.handle_single(&batched)
+                            })
+                            .collect();
+                        request.reply(Message::encode_batch(&replies))
+                    }
+                    Err(e) => request.error(format!("Invalid batch: {e}")),
+                },
+                // Events are never answered.
+                Kind::Event => {
+                    let allowed = for_api(&self.rules, &request.service, &request.api_name)
+                        .is_none_or(|rule| rule.allows(&request));
+                    if let Some(events) = self.events.as_mut().filter(|_| allowed) {
+                        events.deliver(&request);
+                    }
+                    continue;
+                }
+                Kind::Shutdown => return Ok(()),
+                // A stream may have ended already when it is cancelled.
+                Kind::Cancel => {
+                    streams.cancel(socket, socket.current_peer(), request.id);
+                    continue;
+                }
+                kind => request.error(format!("Expected a request, found {kind:?}")),
+            };
+            if let Some(observation) = observation {
+                observation.finish_with(&response);
+            }
+            self.remember_reply(&request, &response);
+            socket.send_reply(response);
+        }
+    }
+
+    /// Moves the requests that have already arrived on `socket` to `queued`, together with the
+    /// requester that sent them, answering the ones that exceed the
+    /// [`max_queued`](Limits::max_queued) limit of their API right away.
+    ///
+    /// Afterwards, replies go to the requester of the request that is being handled again.
+    fn fill_queue<T: Transport>(
+        &mut self,
+        socket: &Responder<T>,
+        queued: &mut VecDeque<(Option<PeerId>, Message)>,
+    ) -> Result<()> {
+        let current = socket.current_peer();
+        // Nothing after a shutdown is handled anymore.
+        while !queued
+            .back()
+            .is_some_and(|(_, last)| last.kind == Kind::Shutdown)
+        {
+            let Some(request) = socket.try_next_request()? else {
+                break;
+            };
+            let limit = for_api(&self.limits, &request.service, &request.api_name)
+                .and_then(Limits::queue_limit);
+            if let Some(limit) = limit {
+                let waiting = queued
+                    .iter()
+                    .filter(|(_, waiting)| {
+                        waiting.service == request.service && waiting.api_name == request.api_name
+                    })
+                    .count();
+                if waiting >= limit {
+                    let error = Error::Overloaded {
+                        limit: Overload::Queue,
+                        retry_after: None,
+                    };
+                    let error = reject(&mut self.middleware, &request, error);
+                    socket.send_reply(request.error(error));
+                    continue;
+                }
+            }
+            queued.push_back((socket.current_peer(), request));
+        }
+        socket.set_current_peer(current);
+        Ok(())
+    }
+
+    /// The reply to `request`, if it has a [`REQUEST_ID`] and was already handled.
+    fn replied_before(&self, request: &Message) -> Option<Message> {
+        let request_id = request.headers.get(REQUEST_ID)?;
+        if request.kind != Kind::Request {
+            return None;
+        }
+        // Another principal may not see the reply, even if it knows the ID.
+        let principal = request.headers.get(PRINCIPAL);
+        let (_, reply) = self.replied.iter().find(|((id, replied_principal), _)| {
+            id == request_id && replied_principal.as_deref() == principal
+        })?;
+        Some(Message {
+            id: request.id,
+            ..reply.clone()
+        })
+    }
+
+    /// Remembers `reply` to `request`, if it has a [`REQUEST_ID`] and was handled.
+    fn remember_reply(&mut self, request: &Message, reply: &Message) {
+        let Some(request_id) = request.headers.get(REQUEST_ID) else {
+            return;
+        };
+        // Requests that failed, e.g. because of a limit, are handled when they are sent again.
+        if request.kind != Kind::Request || reply.kind != Kind::Reply {
+            return;
+        }
+        if self.replied.len() == REMEMBERED_REPLIES {
+            self.replied.pop_front();
+        }
+        let principal = request.headers.get(PRINCIPAL).map(str::to_string);
+        self.replied
+            .push_back(((request_id.to_string(), principal), reply.clone()));
+    }
+
+    /// Handles a request on its own, outside of the loop of [`serve_on`](ApiRouter::serve_on),
+    /// returning its reply or error. This is how the requests of a [`Kind::Batch`] are handled.
+    pub(crate) fn handle_single(&mut self, request: &Message) -> Message {
+        let _correlation = headers::enter(&request.headers);
+        let observation = self.observe(request);
+        let response = match request.kind {
+            Kind::Request if request.is_expired() => request.error(reject(
+                &mut self.middleware,
+                request,
+                Error::DeadlineExceeded,
+            )),
+            Kind::Request => match self.dispatch(request) {
+                Ok(Dispatched::Reply(reply)) => request.reply(reply),
+                Ok(Dispatched::Stream(..)) => unreachable!("only stream requests start streams"),
+                Err(e) => request.error(e),
+            },
+            Kind::StreamRequest => request.error(format!(
+                "'{}' in service '{}' cannot be requested in a batch, as it is a streaming API",
+                request.api_name, request.service
+            )),
+            kind => request.error(format!("Expected a request in the batch, found {kind:?}")),
+        };
+        if let Some(observation) = observation {
+            observation.finish_with(&response);
+        }
+        response
+    }
+
+    /// Starts measuring `request`, if this router has metrics and it is not for a stream.
+    fn observe(&self, request: &Message) -> Option<Observation> {
+        let metrics = self.metrics.as_ref()?;
+        if request.kind != Kind::Request {
+            return None;
+        }
+        Some(if self.routes(&request.service, &request.api_name) {
+            Observation::start(metrics, Side::Server, request)
+        } else {
+            Observation::start_as(metrics, Side::Server, request, UNKNOWN, UNKNOWN)
+        })
+    }
+
+    /// Whether this router has a route for `api_name` in `service`, in any version.
+    fn routes(&self, service: &str, api_name: &str) -> bool {
+        (service == RESERVED_SERVICE && api_name == Describe::NAME)
+            || self
+                .services
+                .get(service)
+                .is_some_and(|apis| apis.contains_key(api_name))
+            || !self.handle.versions(service, api_name).is_empty()
+    }
+}
+
+/// The limits or rule for `api_name` in `service`, if any.
+fn for_api<'a, V>(
+    by_api: &'a HashMap<(&'static str, &'static str), V>,
+    service: &'a str,
+    api_name: &'a str,
+) -> Option<&'a V> {
+    // Looking up `&'static str` keys with shorter lived ones needs a shorter lived map.
+    let by_api: &HashMap<(&str, &str), V> = by_api;
+    by_api.get(&(service, api_name))
+}
+
+/// Takes a permit from the `limits` of `api_name` in `service`, if it has any.
+fn permit(
+    limits: &HashMap<(&'static str, &'static str), Limits>,
+    service: &str,
+    api_name: &str,
+) -> Result<Option<Permit>, Error> {
+    for_api(limits, service, api_name)
+        .map(Limits::acquire)
+        .transpose()
+}
+
+/// Tells every `middleware` that `request` is answered with `error` without reaching them.
+fn reject(middleware: &mut [Box<dyn Middleware>], request: &Message, error: Error) -> Error {
+    for middleware in middleware {
+        middleware.rejected(request, &error);
+    }
+    error
+}
+
+enum Dispatched {
+    Reply(Vec<u8>),
+    Stream(BoxedStream, Option<Permit>),
+}
+
+/// How long serving waits for the replies of streams before it looks for requests again.
+const STREAM_POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// The handlers and adapters for the versions of an API.
+type Versions<S> = BTreeMap<u32, Route<S>>;
+
+/// Turns requests for one version into requests for the next, passing them on to `next`.
+type Adapter = dyn Fn(&[u8], &mut RawHandler<'_>) -> Result<Vec<u8>>;
+
+enum Route<S> {
+    Handler(BoxedHandler<S>),
+    Stream(BoxedStreamHandler),
+    Adapter {
+        target: u32,
+        adapt: Rc<Adapter>,
+        schema: Option<ApiSchema>,
+    },
+}
+
+impl<S: 'static> Route<S> {
+    /// Turns a route of a router with the state `O` into one for a router with the state `S`,
+    /// that uses `state` instead.
+    fn with_state<O: 'static>(route: Route<O>, state: &Rc<RefCell<O>>) -> Self {
+        match route {
+            Route::Handler(BoxedHandler { mut handle, schema }) => {
+                let state = Rc::clone(state);
+                Route::Handler(BoxedHandler {
+                    handle: Box::new(move |_, message, request_data| {
+                        handle(&mut state.borrow_mut(), message, request_data)
+                    }),
+                    schema,
+                })
+            }
+            Route::Stream(handler) => Route::Stream(handler),
+            Route::Adapter {
+                target,
+                adapt,
+                schema,
+            } => Route::Adapter {
+                target,
+                adapt,
+                schema,
+            },
+        }
+    }
+}
+
+impl<S> Route<S> {
+    fn schema(&self) -> Option<&ApiSchema> {
+        match self {
+            Route::Handler(handler) => handler.schema.as_ref(),
+            Route::Stream(handler) => handler.schema.as_ref(),
+            Route::Adapter { schema, .. } => schema.as_ref(),
+        }
+    }
+}
+
+/// Handles `request_data` of the `message` for `version`, following adapters to the version that
+/// handles it.
+fn call_version<S>(
+    versions: &mut Versions<S>,
+    state: &mut S,
+    message: &Message,
+    version: u32,
+    request_data: &[u8],
+) -> Result<Vec<u8>> {
+    match versions.get_mut(&version) {
+        Some(Route::Handler(handler)) => (handler.handle)(state, message, request_data),
+        Some(Route::Stream(_)) => Err(format!("Version {version} is a streaming API")),
+        Some(Route::Adapter { target, adapt, .. }) => {
+            let (target, adapt) = (*target, Rc::clone(adapt));
+            adapt(request_data, &mut |request_data: &[u8]| {
+                call_version(versions, state, message, target, request_data)
+            })
+        }
+        None => Err(format!("No handler for version {version}")),
+    }
+}
+
+/// A function that can handle [`A::Request<'de>`](Api::Request) for `'de == 'req`.
+///
+/// The reply may borrow from the request.
+pub trait HandlerOn<'req, A: Api>: FnMut(A::Request<'req>) -> A::Reply<'req> {}
+impl<'req, A: Api, F: FnMut(A::Request<'req>) -> A::Reply<'req>> HandlerOn<'req, A> for F {}
+
+/// A function that can handle [`A::Request<'de>`](Api::Request) for any `'de`.
+pub trait Handler<A: Api>: for<'req> HandlerOn<'req, A> {}
+impl<A: Api, F: for<'req> HandlerOn<'req, A>> Handler<A> for F {}
+
+/// A function that can handle [`A::Request<'de>`](Api::Request) for `'de == 'req` with mutable
+/// access to the state `S` of its router.
+pub trait StatefulHandlerOn<'req, A: Api, S>:
+    FnMut(&mut S, A::Request<'req>) -> A::Reply<'req>
+{
+}
+impl<'req, A: Api, S, F: FnMut(&mut S, A::Request<'req>) -> A::Reply<'req>>
+    StatefulHandlerOn<'req, A, S> for F
+{
+}
+
+/// A function that can handle [`A::Request<'de>`](Api::Request) for any `'de` with mutable access
+/// to the state `S` of its router.
+pub trait StatefulHandler<A: Api, S>: for<'req> StatefulHandlerOn<'req, A, S> {}
+impl<A: Api, S, F: for<'req> StatefulHandlerOn<'req, A, S>> StatefulHandler<A, S> for F {}
+
+/// A function that can handle [`A::Request<'de>`](Api::Request) for `'de == 'req`, given the
+/// values `E` extracted for the request.
+pub trait HandlerWithOn<'req, A: Api, E>: FnMut(E, A::Request<'req>) -> A::Reply<'req> {}
+impl<'req, A: Api, E, F: FnMut(E, A::Request<'req>) -> A::Reply<'req>> HandlerWithOn<'req, A, E>
+    for F
+{
+}
+
+/// A function that can handle [`A::Request<'de>`](Api::Request) for any `'de`, given the values
+/// `E` extracted for the request.
+pub trait HandlerWith<A: Api, E>: for<'req> HandlerWithOn<'req, A, E> {}
+impl<A: Api, E, F: for<'req> HandlerWithOn<'req, A, E>> HandlerWith<A, E> for F {}
+
+/// A function that can turn [`Old::Request<'de>`](Api::Request) into
+/// [`New::Request<'de>`](Api::Request) for `'de == 'req`.
+pub trait AdaptOn<'req, Old: Api, New: Api>: Fn(Old::Request<'req>) -> New::Request<'req> {}
+impl<'req, Old: Api, New: Api, F: Fn(Old::Request<'req>) -> New::Request<'req>>
+    AdaptOn<'req, Old, New> for F
+{
+}
+
+/// A function that can turn [`Old::Request<'de>`](Api::Request) into
+/// [`New::Request<'de>`](Api::Request) for any `'de`.
+pub trait Adapt<Old: Api, New: Api>: for<'req> AdaptOn<'req, Old, New> {}
+impl<Old: Api, New: Api, F: for<'req> AdaptOn<'req, Old, New>> Adapt<Old, New> for F {}
+
+/// Any of the handlers above, with access to the router's state and the request message.
+trait RawHandlerOn<'req, A: Api, S>:
+    FnMut(&mut S, &Message, A::Request<'req>) -> Result<A::Reply<'req>>
+{
+}
+impl<'req, A: Api, S, F: FnMut(&mut S, &Message, A::Request<'req>) -> Result<A::Reply<'req>>>
+    RawHandlerOn<'req, A, S> for F
+{
+}
+
+type BoxedRequestHandler<S> = Box<dyn FnMut(&mut S, &Message, &[u8]) -> Result<Vec<u8>>>;
+struct BoxedHandler<S> {
+    handle: BoxedRequestHandler<S>,
+    schema: Option<ApiSchema>,
+}
+
+impl<S> BoxedHandler<S> {
+    fn new<A, H>(mut handler: H) -> Self
+    where
+        A: Api,
+        H: for<'req> RawHandlerOn<'req, A, S> + 'static,
+    {
+        let handler =
+            move |state: &mut S, message: &Message, request_data: &[u8]| -> Result<Vec<u8>> {
+                #[cfg(feature = "tracing")]
+                let _span = tracing::info_span!(
+                    "request",
+                    service = A::SERVICE,
+                    api = A::NAME,
+                    version = A::VERSION
+                )
+                .entered();
+                let request: A::Request<'_> = {
+                    #[cfg(feature = "tracing")]
+                    let _span =
+                        tracing::debug_span!("deserialize", bytes = request_data.len()).entered();
+                    serde_json::from_slice(request_data)
+                        .map_err(|e| format!("Deserialize error: {e}"))?
+                };
+                let reply = {
+                    #[cfg(feature = "tracing")]
+                    let _span = tracing::debug_span!("handler").entered();
+                    handler(state, message, request)?
+                };
+                #[cfg(feature = "tracing")]
+                let _span = tracing::debug_span!("serialize").entered();
+                let reply =
+                    serde_json::to_vec(&reply).map_err(|e| format!("Serialize error: {e}"))?;
+                Ok(reply)
+            };
+        Self {
+            handle: Box::new(handler),
+            schema: A::schema(),
+        }
+    }
+}
+
+impl<T: Transport> Responder<T> {
+    /// Waits for incoming requests on `self` and handles the ones for `A` with the given
+    /// `handler`, sending back the computed reply, until a [`Kind::Shutdown`] message is received.
+    ///
+    /// This is a shorthand for serving an [`ApiRouter`] with only `handler` registered, so
+    /// requests for any other API are answered with a [`Kind::Error`] reply (see
+    /// [`ApiRouter::serve_on`]).
+    pub fn serve_forever<A: Api, H: Handler<A> + 'static>(self, handler: H) -> Result<()> {
+        ApiRouter::new()
+            .register_handler::<A, _>(handler)
+            .serve_on(self)
+    }
+}
+
+impl<T: Transport> Requester<T> {
+    /// Sends `request` to its service and waits for the reply.
+    ///
+    /// If [`A::TIMEOUT`](Api::TIMEOUT) is set, this gives up with [`Error::Timeout`] once it has
+    /// passed.
+    pub fn request<'a, A>(&self, request: A) -> Result<A::Reply<'static>, Error>
+    where
+        A: Api<Request<'a> = A>,
+        A::Reply<'static>: DeserializeOwned,
+    {
+        let deadline = A::TIMEOUT.map(|timeout| SystemTime::now() + timeout);
+        self.request_with_deadline(request, deadline)
+    }
+
+    /// Like [`request`](Requester::request), but waits at most `timeout` instead of
+    /// [`A::TIMEOUT`](Api::TIMEOUT).
+    pub fn request_with_timeout<'a, A>(
+        &self,
+        request: A,
+        timeout: Duration,
+    ) -> Result<A::Reply<'static>, Error>
+    where
+        A: Api<Request<'a> = A>,
+        A::Reply<'static>: DeserializeOwned,
+    {
+        self.request_with_deadline(request, Some(SystemTime::now() + timeout))
+    }
+
+    /// Like [`request`](Requester::request), but waits until `deadline` instead of using
+    /// [`A::TIMEOUT`](Api::TIMEOUT).
+    ///
+    /// This allows a handler that makes requests of its own to pass on the deadline of the
+    /// request it is handling.
+    pub fn request_with_deadline<'a, A>(
+        &self,
+        request: A,
+        deadline: Option<SystemTime>,
+    ) -> Result<A::Reply<'static>, Error>
+    where
+        A: Api<Request<'a> = A>,
+        A::Reply<'static>: DeserializeOwned,
+    {
+        let response = self.send_api_request(request, deadline, Headers::new())?;
+        let reply = serde_json::from_slice(&response.data)
+            .map_err(|e| format!("Deserialize error: {e}"))?;
+        Ok(reply)
+    }
+
+    /// Like [`request`](Requester::request), but returns a guard that owns the serialized reply
+    /// and lends out [`A::Reply`](Api::Reply)s borrowed from it.
+    ///
+    /// This avoids copying large replies such as long strings. Note that JSON strings can only
+    /// be borrowed if they contain no escape sequences.
+    pub fn request_borrowed<'a, A: Api<Request<'a> = A>>(
+        &self,
+        request: A,
+    ) -> Result<ReplyGuard<A>, Error> {
+        let deadline = A::TIMEOUT.map(|timeout| SystemTime::now() + timeout);
+        let response = self.send_api_request(request, deadline, Headers::new())?;
+        Ok(ReplyGuard {
+            data: response.data,
+            api: PhantomData,
+        })
+    }
+
+    /// Like [`request`](Requester::request), but sends `headers` in addition to the headers of
+    /// this requester, taking precedence over them.
+    pub fn request_with_headers<'a, A>(
+        &self,
+        request: A,
+        headers: Headers,
+    ) -> Result<A::Reply<'static>, Error>
+    where
+        A: Api<Request<'a> = A>,
+        A::Reply<'static>: DeserializeOwned,
+    {
+        let deadline = A::TIMEOUT.map(|timeout| SystemTime::now() + timeout);
+        let response = self.send_api_request(request, deadline, headers)?;
+        let reply = serde_json::from_slice(&response.data)
+            .map_err(|e| format!("Deserialize error: {e}"))?;
+        Ok(reply)
+    }
+
+    fn send_api_request<'a, A: Api<Request<'a> = A>>(
+        &self,
+        request: A,
+        deadline: Option<SystemTime>,
+        headers: Headers,
+    ) -> Result<Message, Error> {
+        let data = serde_json::to_vec(&request).map_err(|e| format!("Serialize error: {e}"))?;
+        // All attempts share their headers, and with them the correlation and request IDs.
+        let mut headers = headers;
+        self.add_headers(&mut headers);
+        if !A::IDEMPOTENT && !headers.contains(REQUEST_ID) {
+            headers.insert(REQUEST_ID, headers::new_request_id());
+        }
+        let request = Message {
+            kind: Kind::Request,
+            // Assigned by `send_request`
+            id: 0,
+            version: A::VERSION,
+            deadline,
+            service: A::SERVICE.to_string(),
+            api_name: A::NAME.to_string(),
+            headers,
+            encoding: Encoding::Identity,
+            data,
+        };
+        let retry = self.retry_policy().filter(|_| A::IDEMPOTENT);
+        let mut attempt = 1;
+        let response = loop {
+            let error = match self.send_request(request.clone()) {
+                Ok(response) => break response,
+                Err(error) => error,
+            };
+            let Some(retry) = retry.filter(|retry| attempt < retry.max_attempts()) else {
+                return Err(error);
+            };
+            if !error.is_transient() {
+                return Err(error);
+            }
+            attempt += 1;
+            let delay = match error {
+                Error::Overloaded {
+                    retry_after: Some(retry_after),
+                    ..
+                } => retry.delay(attempt).max(retry_after),
+                _ => retry.delay(attempt),
+            };
+            // Retrying is pointless if the reply could not arrive in time anyway.
+            if deadline.is_some_and(|deadline| SystemTime::now() + delay >= deadline) {
+                return Err(error);
+            }
+            thread::sleep(delay);
+        };
+        if response.service != A::SERVICE || response.api_name != A::NAME {
+            return Err(Error::Other(format!(
+                "Expected a reply for '{}' in service '{}', but received one for '{}' in service '{}'",
+                A::NAME,
+                A::SERVICE,
+                response.api_name,
+                response.service
+            )));
+        }
+        Ok(response)
+    }
+}
+
+/// The serialized reply to a request for `A`, see [`Requester::request_borrowed`].
+pub struct ReplyGuard<A> {
+    data: Vec<u8>,
+    api: PhantomData<fn() -> A>,
+}
+
+impl<A: Api> ReplyGuard<A> {
+    /// Deserializes the reply, borrowing from the guard where the reply type allows it.
+    pub fn reply(&self) -> Result<A::Reply<'_>, Error> {
+        let reply =
+            serde_json::from_slice(&self.data).map_err(|e| format!("Deserialize error: {e}"))?;
+        Ok(reply)
+    }
+
+    /// Returns the serialized reply.
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.data
+    }
+}
@@ -0,0 +1,199 @@
+//! Authenticating requesters and authorizing their requests.
+//!
+//! A [`Responder`](crate::channel::Responder::with_authenticator) with an [`Authenticator`]
+//! checks every request it receives before it is handled, and answers the ones it rejects with
+//! [`Error::Unauthorized`](crate::channel::Error::Unauthorized). Accepted requests carry the
+//! principal they were authenticated as in the [`PRINCIPAL`] header.
+//!
+//! Routers decide which principals may request an API with a [`Rule`], see
+//! [`ApiRouter::authorize`](crate::api::ApiRouter::authorize).
+//!
+//! Authentication does not hide requests and replies from anyone who can read the connection,
+//! and does not stop them from sending a captured request again. See the `noise` module for
+//! encrypted connections.
+
+use std::sync::Arc;
+
+#[cfg(feature = "hmac")]
+use std::collections::HashMap;
+
+#[cfg(feature = "hmac")]
+use hmac::Mac;
+
+#[cfg(feature = "hmac")]
+use crate::headers::{KEY_ID, SIGNATURE};
+use crate::{
+    channel::Message,
+    headers::{AUTHORIZATION, PRINCIPAL},
+};
+
+/// Decides who sent a request.
+pub trait Authenticator: Send + Sync {
+    /// Returns the principal that sent `request`, or why it cannot be trusted.
+    fn authenticate(&self, request: &Message) -> Result<String, String>;
+}
+
+impl<F: Fn(&Message) -> Result<String, String> + Send + Sync> Authenticator for F {
+    fn authenticate(&self, request: &Message) -> Result<String, String> {
+        self(request)
+    }
+}
+
+/// Authenticates requests by the bearer token in their [`AUTHORIZATION`] header.
+///
+/// Requesters send their token with
+/// [`Requester::with_token`](crate::channel::Requester::with_token).
+#[derive(Debug, Clone, Default)]
+pub struct Tokens {
+    /// The tokens and the principals they authenticate.
+    tokens: Vec<(String, String)>,
+}
+
+impl Tokens {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Authenticate requests with `token` as `principal`.
+    pub fn token(mut self, token: impl Into<String>, principal: impl Into<String>) -> Self {
+        self.tokens.push((token.into(), principal.into()));
+        self
+    }
+}
+
+impl Authenticator for Tokens {
+    fn authenticate(&self, request: &Message) -> Result<String, String> {
+        let token = request
+            .headers
+            .get(AUTHORIZATION)
+            .ok_or("Missing token")?
+            .strip_prefix("Bearer ")
+            .ok_or("Expected a bearer token")?;
+        // Every token is compared, so the time taken does not tell which one was close.
+        let mut principal = None;
+        for (known, known_principal) in &self.tokens {
+            if constant_time_eq(known.as_bytes(), token.as_bytes()) {
+                principal = Some(known_principal);
+            }
+        }
+        principal
+            .cloned()
+            .ok_or_else(|| "Unknown token".to_string())
+    }
+}
+
+/// Authenticates requests by an HMAC-SHA256 signature of the whole [encoded](Message::encode)
+/// request, made with a secret that is shared with the requester.
+///
+/// Requesters sign their requests with
+/// [`Requester::with_hmac_key`](crate::channel::Requester::with_hmac_key), which sends the ID of
+/// the key in the [`KEY_ID`] header and the signature in the [`SIGNATURE`] header. Requests are
+/// authenticated as the ID of their key.
+///
+/// This requires the `hmac` feature.
+#[cfg(feature = "hmac")]
+#[derive(Debug, Clone, Default)]
+pub struct Hmac {
+    keys: HashMap<String, Vec<u8>>,
+}
+
+#[cfg(feature = "hmac")]
+impl Hmac {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Accept requests signed with `secret`, under the key ID `id`.
+    pub fn key(mut self, id: impl Into<String>, secret: impl Into<Vec<u8>>) -> Self {
+        self.keys.insert(id.into(), secret.into());
+        self
+    }
+}
+
+#[cfg(feature = "hmac")]
+impl Authenticator for Hmac {
+    fn authenticate(&self, request: &Message) -> Result<String, String> {
+        let key_id = request.headers.get(KEY_ID).ok_or("Missing key ID")?;
+        let signature = request.headers.get(SIGNATURE).ok_or("Missing signature")?;
+        let signature = from_hex(signature).ok_or("Invalid signature")?;
+        let secret = self.keys.get(key_id).ok_or("Unknown key")?;
+        mac(secret, request)
+            .verify_slice(&signature)
+            .map_err(|_| "Invalid signature".to_string())?;
+        Ok(key_id.to_string())
+    }
+}
+
+/// Signs `request` with `secret`, see [`Hmac`].
+#[cfg(feature = "hmac")]
+pub(crate) fn sign(request: &mut Message, key_id: &str, secret: &[u8]) {
+    request.headers.insert(KEY_ID, key_id);
+    let signature = mac(secret, request).finalize().into_bytes();
+    request.headers.insert(SIGNATURE, to_hex(&signature));
+}
+
+/// The MAC of `request` without its signature.
+#[cfg(feature = "hmac")]
+fn mac(secret: &[u8], request: &Message) -> hmac::Hmac<sha2::Sha256> {
+    let mut unsigned = request.clone();
+    unsigned.headers.remove(SIGNATURE);
+    let mut mac =
+        hmac::Hmac::<sha2::Sha256>::new_from_slice(secret).expect("HMAC accepts keys of any size");
+    mac.update(&unsigned.encode());
+    mac
+}
+
+#[cfg(feature = "hmac")]
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+#[cfg(feature = "hmac")]
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+/// Which principals may request an API.
+///
+/// Requests that a rule does not allow are answered with
+/// [`Error::Unauthorized`](crate::channel::Error::Unauthorized) without being handled.
+#[derive(Clone)]
+pub struct Rule(Arc<Allow>);
+
+/// Whether to allow a request, given its principal.
+type Allow = dyn Fn(Option<&str>) -> bool + Send + Sync;
+
+impl Rule {
+    /// Allow the requests for which `allow` returns `true`, given the principal they were
+    /// authenticated as, if any.
+    pub fn new(allow: impl Fn(Option<&str>) -> bool + Send + Sync + 'static) -> Self {
+        Self(Arc::new(allow))
+    }
+
+    /// Allow requests from any authenticated principal.
+    pub fn authenticated() -> Self {
+        Self::new(|principal| principal.is_some())
+    }
+
+    /// Allow requests from the given principals only.
+    pub fn principals<P: Into<String>>(principals: impl IntoIterator<Item = P>) -> Self {
+        let principals: Vec<String> = principals.into_iter().map(Into::into).collect();
+        Self::new(move |principal| principal.is_some_and(|p| principals.iter().any(|q| p == q)))
+    }
+
+    /// Whether the rule allows `request`, given the [`PRINCIPAL`] it was authenticated as.
+    #[cfg_attr(not(feature = "api"), allow(dead_code))]
+    pub(crate) fn allows(&self, request: &Message) -> bool {
+        (self.0)(request.headers.get(PRINCIPAL))
+    }
+}
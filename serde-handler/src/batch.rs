@@ -0,0 +1,210 @@
+//! Sending several requests in a single round trip.
+//!
+//! A [`Batch`] collects requests for any [`Api`]s and sends them in one [`Kind::Batch`] message.
+//! The service handles them one after the other and answers with the replies or errors of all of
+//! them in one message, so a failing request does not affect the others.
+
+use std::{marker::PhantomData, time::SystemTime};
+
+use serde::de::DeserializeOwned;
+
+use crate::{
+    api::Api,
+    channel::{response_error, Error, Kind, Message, Requester},
+    headers::Headers,
+    transport::Transport,
+};
+
+/// Requests that are sent together, see [`Requester::batch`].
+///
+/// `Q` is the tuple of the [`Api`]s that have been added so far, which determines the tuple of
+/// replies that [`send`](Batch::send) returns.
+pub struct Batch<'r, T, Q = ()> {
+    requester: &'r Requester<T>,
+    requests: Vec<Message>,
+    /// The earliest deadline from the [`Api::TIMEOUT`]s of the requests.
+    timeout_deadline: Option<SystemTime>,
+    /// The deadline set with [`with_deadline`](Batch::with_deadline).
+    deadline: Option<Option<SystemTime>>,
+    /// The first error from serializing a request, which is reported when sending.
+    error: Option<Error>,
+    apis: PhantomData<fn() -> Q>,
+}
+
+impl<'r, T: Transport, Q> Batch<'r, T, Q> {
+    /// Adds `request` to the batch.
+    ///
+    /// The batch waits for its replies until the earliest [`A::TIMEOUT`](Api::TIMEOUT) of its
+    /// requests has passed, unless [`with_deadline`](Batch::with_deadline) is used.
+    #[allow(clippy::should_implement_trait)]
+    pub fn add<'a, A: Api<Request<'a> = A>>(mut self, request: A) -> Batch<'r, T, Q::Output>
+    where
+        Q: Append<A>,
+    {
+        match serde_json::to_vec(&request) {
+            Ok(data) => self.requests.push(Message {
+                kind: Kind::Request,
+                id: self.requests.len() as u64,
+                version: A::VERSION,
+                service: A::SERVICE.to_string(),
+                api_name: A::NAME.to_string(),
+                data,
+                ..Default::default()
+            }),
+            Err(e) => {
+                let error = Error::Other(format!("Serialize error: {e}"));
+                self.error.get_or_insert(error);
+            }
+        }
+        let deadline = A::TIMEOUT.map(|timeout| SystemTime::now() + timeout);
+        Batch {
+            requester: self.requester,
+            requests: self.requests,
+            timeout_deadline: self.timeout_deadline.into_iter().chain(deadline).min(),
+            deadline: self.deadline,
+            error: self.error,
+            apis: PhantomData,
+        }
+    }
+
+    /// Waits until `deadline` for the replies, instead of using the [`Api::TIMEOUT`]s of the
+    /// requests.
+    pub fn with_deadline(mut self, deadline: Option<SystemTime>) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Sends all requests and waits for their replies.
+    ///
+    /// The outer `Result` fails if the batch as a whole could not be sent or answered, e.g.
+    /// because it timed out. Otherwise, each request has its own `Result`.
+    pub fn send(mut self) -> Result<Q::Replies, Error>
+    where
+        Q: BatchApis,
+    {
+        if let Some(error) = self.error {
+            return Err(error);
+        }
+        let deadline = self.deadline.unwrap_or(self.timeout_deadline);
+        // All requests of the batch share its correlation ID.
+        let mut headers = Headers::new();
+        self.requester.add_headers(&mut headers);
+        for request in &mut self.requests {
+            request.deadline = deadline;
+            request.headers = headers.clone();
+        }
+        let response = self.requester.send_request(Message {
+            kind: Kind::Batch,
+            deadline,
+            headers,
+            data: Message::encode_batch(&self.requests),
+            ..Default::default()
+        })?;
+        let replies = Message::decode_batch(&response.data)
+            .map_err(|e| format!("Invalid batch reply: {e}"))?;
+        if replies.len() != self.requests.len() {
+            return Err(Error::Other(format!(
+                "Expected {} replies to the batch, received {}",
+                self.requests.len(),
+                replies.len()
+            )));
+        }
+        Ok(Q::replies(replies))
+    }
+}
+
+/// A tuple of [`Api`]s to which `A` can be added.
+pub trait Append<A> {
+    /// The tuple with `A` added at the end.
+    type Output;
+}
+
+/// A tuple of [`Api`]s whose replies can be read from the reply to a [`Batch`].
+pub trait BatchApis {
+    /// The tuple of the results of the requests.
+    type Replies;
+
+    /// Deserializes the reply or error for each request, in order.
+    fn replies(replies: Vec<Message>) -> Self::Replies;
+}
+
+/// Deserializes the reply for `A` from `reply`.
+fn batched_reply<A: Api>(reply: Message) -> Result<A::Reply<'static>, Error>
+where
+    A::Reply<'static>: DeserializeOwned,
+{
+    match reply.kind {
+        Kind::Reply => {
+            let reply = serde_json::from_slice(&reply.data)
+                .map_err(|e| format!("Deserialize error: {e}"))?;
+            Ok(reply)
+        }
+        Kind::Error => Err(response_error(&reply)),
+        kind => Err(Error::Other(format!(
+            "Expected a reply, but received {kind:?} for '{}'",
+            reply.api_name
+        ))),
+    }
+}
+
+macro_rules! impl_batch_apis {
+    ($($api:ident),*) => {
+        impl<$($api: Api),*> BatchApis for ($($api,)*)
+        where
+            $(<$api as Api>::Reply<'static>: DeserializeOwned,)*
+        {
+            type Replies = ($(Result<<$api as Api>::Reply<'static>, Error>,)*);
+
+            #[allow(unused_variables, unused_mut, clippy::unused_unit)]
+            fn replies(replies: Vec<Message>) -> Self::Replies {
+                let mut replies = replies.into_iter();
+                ($(batched_reply::<$api>(replies.next().expect("one reply per request")),)*)
+            }
+        }
+    };
+}
+
+macro_rules! impl_append {
+    ($($api:ident),*) => {
+        impl<$($api,)* Next> Append<Next> for ($($api,)*) {
+            type Output = ($($api,)* Next,);
+        }
+    };
+}
+
+impl_batch_apis!();
+impl_batch_apis!(A1);
+impl_batch_apis!(A1, A2);
+impl_batch_apis!(A1, A2, A3);
+impl_batch_apis!(A1, A2, A3, A4);
+impl_batch_apis!(A1, A2, A3, A4, A5);
+impl_batch_apis!(A1, A2, A3, A4, A5, A6);
+impl_batch_apis!(A1, A2, A3, A4, A5, A6, A7);
+impl_batch_apis!(A1, A2, A3, A4, A5, A6, A7, A8);
+
+impl_append!();
+impl_append!(A1);
+impl_append!(A1, A2);
+impl_append!(A1, A2, A3);
+impl_append!(A1, A2, A3, A4);
+impl_append!(A1, A2, A3, A4, A5);
+impl_append!(A1, A2, A3, A4, A5, A6);
+impl_append!(A1, A2, A3, A4, A5, A6, A7);
+
+impl<T: Transport> Requester<T> {
+    /// Starts a batch of requests that are sent to the service in a single message.
+    ///
+    /// Up to eight requests, for any mix of APIs, can be added with [`Batch::add`].
+    /// [`Batch::send`] then returns a tuple with the result of each of them, in the order they
+    /// were added.
+    pub fn batch(&self) -> Batch<'_, T> {
+        Batch {
+            requester: self,
+            requests: Vec::new(),
+            timeout_deadline: None,
+            deadline: None,
+            error: None,
+            apis: PhantomData,
+        }
+    }
+}
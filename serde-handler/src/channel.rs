@@ -1,44 +1,964 @@
-use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::{
+    cell::Cell,
+    fmt,
+    net::{TcpStream, ToSocketAddrs},
+    sync::{
+        mpsc::{self, Receiver, RecvTimeoutError, SyncSender},
+        Arc, Weak,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
-use crate::Result;
+#[cfg(unix)]
+use std::{os::unix::net::UnixStream, path::Path};
 
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    auth::Authenticator,
+    compression::{self, Compression, Encoding},
+    headers::{self, Headers, AUTHORIZATION, CORRELATION_ID, PRINCIPAL},
+    metrics::{Metrics, Observation, Side},
+    retry::RetryPolicy,
+    transport::{ShutdownHandle, Transport},
+    Result,
+};
+
+/// What a [`Message`] means to the receiving end.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Kind {
+    /// A request for the API `api_name` of `service`, with the request as `data`.
+    #[default]
+    Request,
+    /// A successful reply to a request, with the reply as `data`.
+    Reply,
+    /// A request could not be handled. `data` holds a UTF-8 description of the error.
+    Error,
+    /// The sending end will not send any further messages and asks the receiving end to stop.
+    ///
+    /// Transports also report it when the other end hangs up.
+    Shutdown,
+    /// One of several replies to a request for a streaming API, with the reply as `data`.
+    ///
+    /// A stream of items is terminated by an [`End`](Kind::End) message, or by an
+    /// [`Error`](Kind::Error) message if producing an item failed.
+    Item,
+    /// The stream of [`Item`](Kind::Item)s for a request has ended.
+    End,
+    /// The requester is no longer interested in the stream of items for the request with the
+    /// same [`id`](Message::id).
+    Cancel,
+    /// Like [`Request`](Kind::Request), but for a streaming API, which answers with
+    /// [`Item`](Kind::Item)s.
+    StreamRequest,
+    /// Several requests that are handled one after the other and answered together, see
+    /// [`Message::encode_batch`].
+    ///
+    /// `data` holds the [`Request`](Kind::Request) messages, and the [`Reply`](Kind::Reply)
+    /// holds a [`Reply`](Kind::Reply) or [`Error`](Kind::Error) message for each of them, in the
+    /// same order.
+    Batch,
+    /// An event for the topic `api_name` of `service`, with the payload as `data`.
+    ///
+    /// Events are not answered, see [`EventBus`](crate::event::EventBus).
+    Event,
+}
+
+impl Kind {
+    fn to_byte(self) -> u8 {
+        match self {
+            Kind::Request => 0,
+            Kind::Reply => 1,
+            Kind::Error => 2,
+            Kind::Shutdown => 3,
+            Kind::Item => 4,
+            Kind::End => 5,
+            Kind::Cancel => 6,
+            Kind::StreamRequest => 7,
+            Kind::Batch => 8,
+            Kind::Event => 9,
+        }
+    }
+
+    fn from_byte(byte: u8) -> Result<Self> {
+        match byte {
+            0 => Ok(Kind::Request),
+            1 => Ok(Kind::Reply),
+            2 => Ok(Kind::Error),
+            3 => Ok(Kind::Shutdown),
+            4 => Ok(Kind::Item),
+            5 => Ok(Kind::End),
+            6 => Ok(Kind::Cancel),
+            7 => Ok(Kind::StreamRequest),
+            8 => Ok(Kind::Batch),
+            9 => Ok(Kind::Event),
+            _ => Err(format!("Unknown message kind {byte}")),
+        }
+    }
+}
+
+/// The ways in which a request can fail.
+///
+/// Errors that a service reports for a request are sent back to the requester in a [`Kind::Error`]
+/// reply, so this type is the same on both ends.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Error {
+    /// No reply arrived before the request's deadline.
+    Timeout,
+    /// The request's deadline had already passed when the service got to it, so it was not
+    /// handled.
+    DeadlineExceeded,
+    /// The service does not serve the version of the API that was requested.
+    IncompatibleVersion {
+        requested: u32,
+        /// The versions the service does serve, in ascending order.
+        supported: Vec<u32>,
+    },
+    /// The service is at one of its limits for the API, and rejected the request without handling
+    /// it.
+    Overloaded {
+        limit: Overload,
+        /// How long until a request would no longer exceed the limit, if the service knows.
+        retry_after: Option<Duration>,
+    },
+    /// The data of the request or reply exceeded the maximum size of the receiving end, so it was
+    /// not deserialized.
+    TooLarge {
+        /// The size of the data, if it is known. The size of compressed data is not, as it is only
+        /// decompressed up to the maximum.
+        size: Option<usize>,
+        max: usize,
+    },
+    /// The service could not authenticate the requester, or does not allow it to request the API.
+    Unauthorized(String),
+    /// Sending the request or receiving its reply failed, e.g. because a connection broke.
+    Transport(String),
+    /// Any other failure, described by a message.
+    Other(String),
+}
+
+impl Error {
+    /// Whether the request may succeed if it is sent again, i.e. whether it is worth retrying.
+    ///
+    /// This does not mean that the request was not handled, only that it failed for a reason other
+    /// than the request itself.
+    pub fn is_transient(&self) -> bool {
+        matches!(self, Error::Transport(_) | Error::Overloaded { .. })
+    }
+
+    /// A short name for the kind of error, such as `timeout`, e.g. to label metrics.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Error::Timeout => "timeout",
+            Error::DeadlineExceeded => "deadline_exceeded",
+            Error::IncompatibleVersion { .. } => "incompatible_version",
+            Error::Overloaded { .. } => "overloaded",
+            Error::TooLarge { .. } => "too_large",
+            Error::Unauthorized(_) => "unauthorized",
+            Error::Transport(_) => "transport",
+            Error::Other(_) => "other",
+        }
+    }
+}
+
+/// The limit of a service that an [`Error::Overloaded`] request exceeded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Overload {
+    /// The API was requested more often than its rate limit allows.
+    Rate,
+    /// As many requests for the API as allowed were already being handled.
+    Concurrency,
+    /// As many requests for the API as allowed were already waiting to be handled.
+    Queue,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Timeout => write!(f, "Timed out waiting for a reply"),
+            Error::DeadlineExceeded => {
+                write!(f, "Deadline exceeded before the request was handled")
+            }
+            Error::IncompatibleVersion {
+                requested,
+                supported,
+            } => write!(
+                f,
+                "Version {requested} is not supported, expected one of {supported:?}"
+            ),
+            Error::Overloaded { limit, retry_after } => {
+                let limit = match limit {
+                    Overload::Rate => "rate limit",
+                    Overload::Concurrency => "concurrency limit",
+                    Overload::Queue => "queue limit",
+                };
+                write!(f, "The service is overloaded, the {limit} was reached")?;
+                match retry_after {
+                    Some(retry_after) => write!(f, ", retry after {retry_after:?}"),
+                    None => Ok(()),
+                }
+            }
+            Error::TooLarge {
+                size: Some(size),
+                max,
+            } => write!(
+                f,
+                "The message data of {size} bytes exceeds the maximum of {max} bytes"
+            ),
+            Error::TooLarge { size: None, max } => write!(
+                f,
+                "The decompressed message data exceeds the maximum of {max} bytes"
+            ),
+            Error::Unauthorized(reason) => write!(f, "Unauthorized: {reason}"),
+            Error::Transport(message) | Error::Other(message) => write!(f, "{message}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<String> for Error {
+    fn from(message: String) -> Self {
+        Error::Other(message)
+    }
+}
+
+impl From<Error> for String {
+    fn from(error: Error) -> Self {
+        error.to_string()
+    }
+}
+
+#[derive(Debug, Clone, Default)]
 pub struct Message {
+    pub kind: Kind,
+    /// Identifies the request that a reply belongs to.
+    ///
+    /// Requesters assign a new ID to every request, and replies carry the ID of their request.
+    pub id: u64,
+    /// The [`Api::VERSION`](crate::api::Api::VERSION) of the request.
+    ///
+    /// `0` means no particular version, which services handle with the newest one they know.
+    pub version: u32,
+    /// The point in time after which the requester no longer waits for a reply.
+    pub deadline: Option<SystemTime>,
+    pub service: String,
     pub api_name: String,
+    /// Metadata about the request, such as its [`CORRELATION_ID`].
+    pub headers: Headers,
+    /// How `data` is compressed.
+    ///
+    /// Requesters and responders decompress the data of the messages they receive, so this is
+    /// only ever not [`Encoding::Identity`] on the way.
+    pub encoding: Encoding,
     pub data: Vec<u8>,
 }
 
-pub struct Requester {
-    pub(crate) outgoing: SyncSender<Message>,
-    pub(crate) incoming: Receiver<Message>,
+impl Message {
+    /// Create a [`Kind::Shutdown`] message.
+    pub fn shutdown() -> Self {
+        Self {
+            kind: Kind::Shutdown,
+            ..Default::default()
+        }
+    }
+
+    /// Create a reply to `self` carrying `data`.
+    ///
+    /// Of the headers of `self`, the reply only keeps the [`CORRELATION_ID`].
+    pub fn reply(&self, data: Vec<u8>) -> Self {
+        Self {
+            kind: Kind::Reply,
+            id: self.id,
+            version: self.version,
+            deadline: None,
+            service: self.service.clone(),
+            api_name: self.api_name.clone(),
+            headers: self
+                .headers
+                .iter()
+                .filter(|(name, _)| *name == CORRELATION_ID)
+                .collect(),
+            encoding: Encoding::Identity,
+            data,
+        }
+    }
+
+    /// Create a reply to `self` that reports `error` instead of a result.
+    pub fn error(&self, error: impl Into<Error>) -> Self {
+        let data = serde_json::to_vec(&error.into()).expect("errors are always serializable");
+        Self {
+            kind: Kind::Error,
+            ..self.reply(data)
+        }
+    }
+
+    /// Create an item of the stream of replies to `self`, carrying `data`.
+    pub fn item(&self, data: Vec<u8>) -> Self {
+        Self {
+            kind: Kind::Item,
+            ..self.reply(data)
+        }
+    }
+
+    /// Create the message that ends the stream of replies to `self`.
+    pub fn end(&self) -> Self {
+        Self {
+            kind: Kind::End,
+            ..self.reply(Vec::new())
+        }
+    }
+
+    /// Returns whether `self` has a deadline that has already passed.
+    pub fn is_expired(&self) -> bool {
+        self.deadline
+            .is_some_and(|deadline| deadline <= SystemTime::now())
+    }
+
+    /// Encodes `self` into the binary representation used by stream-based [`Transport`]s.
+    ///
+    /// The [`Kind`] is written as a single byte, the ID as a big-endian `u64`, the version as a
+    /// big-endian `u32` and the [`Encoding`] as a single byte. Every other field
+    /// is written as a big-endian `u32` length followed by that many bytes, with the deadline
+    /// given in milliseconds since the Unix epoch (or no bytes if there is none). The headers are
+    /// written as a single field that holds each name and value as a field of its own.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes =
+            Vec::with_capacity(42 + self.service.len() + self.api_name.len() + self.data.len());
+        bytes.push(self.kind.to_byte());
+        bytes.extend_from_slice(&self.id.to_be_bytes());
+        bytes.extend_from_slice(&self.version.to_be_bytes());
+        bytes.push(self.encoding.to_byte());
+        let deadline = self.deadline.map(|deadline| {
+            let since_epoch = deadline.duration_since(UNIX_EPOCH).unwrap_or_default();
+            (since_epoch.as_millis() as u64).to_be_bytes()
+        });
+        put_field(&mut bytes, deadline.as_ref().map_or(&[], |d| &d[..]));
+        put_field(&mut bytes, self.service.as_bytes());
+        put_field(&mut bytes, self.api_name.as_bytes());
+        let mut headers = Vec::new();
+        for (name, value) in self.headers.iter() {
+            put_field(&mut headers, name.as_bytes());
+            put_field(&mut headers, value.as_bytes());
+        }
+        put_field(&mut bytes, &headers);
+        put_field(&mut bytes, &self.data);
+        bytes
+    }
+
+    /// Decodes a `Message` previously encoded with [`encode`](Message::encode).
+    pub fn decode(mut bytes: &[u8]) -> Result<Self> {
+        let Some((&kind, rest)) = bytes.split_first() else {
+            return Err("Empty message".to_string());
+        };
+        let kind = Kind::from_byte(kind)?;
+        let Some((id, rest)) = rest.split_first_chunk::<8>() else {
+            return Err("Truncated message: missing ID".to_string());
+        };
+        let id = u64::from_be_bytes(*id);
+        let Some((version, rest)) = rest.split_first_chunk::<4>() else {
+            return Err("Truncated message: missing version".to_string());
+        };
+        let version = u32::from_be_bytes(*version);
+        let Some((&encoding, rest)) = rest.split_first() else {
+            return Err("Truncated message: missing encoding".to_string());
+        };
+        let encoding = Encoding::from_byte(encoding)?;
+        bytes = rest;
+        let deadline = match take_field(&mut bytes)? {
+            [] => None,
+            millis => {
+                let millis: [u8; 8] = millis
+                    .try_into()
+                    .map_err(|_| format!("Invalid deadline of {} bytes", millis.len()))?;
+                Some(UNIX_EPOCH + Duration::from_millis(u64::from_be_bytes(millis)))
+            }
+        };
+        let service = take_string(&mut bytes, "service")?;
+        let api_name = take_string(&mut bytes, "API name")?;
+        let mut header_bytes = take_field(&mut bytes)?;
+        let mut headers = Headers::new();
+        while !header_bytes.is_empty() {
+            let name = take_string(&mut header_bytes, "header name")?;
+            let value = take_string(&mut header_bytes, "header value")?;
+            headers.insert(name, value);
+        }
+        let data = take_field(&mut bytes)?.to_vec();
+        if !bytes.is_empty() {
+            return Err(format!("{} trailing bytes after message", bytes.len()));
+        }
+        Ok(Self {
+            kind,
+            id,
+            version,
+            deadline,
+            service,
+            api_name,
+            headers,
+            encoding,
+            data,
+        })
+    }
+
+    /// Encodes `messages` into the `data` of a [`Kind::Batch`] message or of its reply.
+    ///
+    /// Every message is [encoded](Message::encode) and written as a big-endian `u32` length
+    /// followed by that many bytes.
+    pub fn encode_batch(messages: &[Message]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for message in messages {
+            put_field(&mut bytes, &message.encode());
+        }
+        bytes
+    }
+
+    /// Decodes messages previously encoded with [`encode_batch`](Message::encode_batch).
+    pub fn decode_batch(mut bytes: &[u8]) -> Result<Vec<Self>> {
+        let mut messages = Vec::new();
+        while !bytes.is_empty() {
+            messages.push(Self::decode(take_field(&mut bytes)?)?);
+        }
+        Ok(messages)
+    }
+}
+
+pub(crate) fn put_field(bytes: &mut Vec<u8>, field: &[u8]) {
+    let len = u32::try_from(field.len()).expect("message field exceeds 4 GiB");
+    bytes.extend_from_slice(&len.to_be_bytes());
+    bytes.extend_from_slice(field);
+}
+
+pub(crate) fn take_field<'a>(bytes: &mut &'a [u8]) -> Result<&'a [u8]> {
+    let Some((len, rest)) = bytes.split_first_chunk::<4>() else {
+        return Err("Truncated message: missing field length".to_string());
+    };
+    let len = u32::from_be_bytes(*len) as usize;
+    if rest.len() < len {
+        return Err(format!(
+            "Truncated message: expected {len} bytes, found {}",
+            rest.len()
+        ));
+    }
+    let (field, rest) = rest.split_at(len);
+    *bytes = rest;
+    Ok(field)
+}
+
+fn take_string(bytes: &mut &[u8], what: &str) -> Result<String> {
+    let field = take_field(bytes)?;
+    String::from_utf8(field.to_vec()).map_err(|e| format!("Invalid {what}: {e}"))
 }
 
-pub struct Responder {
+/// An in-process [`Transport`] built on a pair of bounded `mpsc` channels.
+///
+/// This is what [`new_pair`] connects its `Requester` and `Responder` with.
+pub struct Local {
+    outgoing: Arc<SyncSender<Message>>,
     incoming: Receiver<Message>,
-    outgoing: SyncSender<Message>,
+    /// The other end's sender for `incoming`. This must not keep the channel alive, so that
+    /// `incoming` disconnects once the other end is dropped.
+    peer: Weak<SyncSender<Message>>,
+}
+
+impl Transport for Local {
+    fn send(&self, message: Message) -> Result<()> {
+        self.outgoing.send(message).map_err(|e| e.to_string())
+    }
+
+    fn recv(&self) -> Result<Message> {
+        // The channel only disconnects once the other end has been dropped.
+        Ok(self.incoming.recv().unwrap_or_else(|_| Message::shutdown()))
+    }
+
+    fn recv_timeout(&self, timeout: Duration) -> Result<Option<Message>> {
+        match self.incoming.recv_timeout(timeout) {
+            Ok(message) => Ok(Some(message)),
+            Err(RecvTimeoutError::Timeout) => Ok(None),
+            Err(RecvTimeoutError::Disconnected) => Ok(Some(Message::shutdown())),
+        }
+    }
+
+    fn shutdown_handle(&self) -> Result<ShutdownHandle> {
+        let peer = Weak::clone(&self.peer);
+        Ok(ShutdownHandle::new(move || {
+            // If the other end is gone, `recv` already reports a shutdown. If `self` is gone,
+            // there is nothing left to stop.
+            if let Some(sender) = peer.upgrade() {
+                let _ = sender.send(Message::shutdown());
+            }
+            Ok(())
+        }))
+    }
+}
+
+pub struct Requester<T = Local> {
+    pub(crate) transport: T,
+    next_id: Cell<u64>,
+    /// Headers that are sent with every request.
+    headers: Headers,
+    retry: Option<RetryPolicy>,
+    metrics: Option<Arc<dyn Metrics>>,
+    compression: Option<Compression>,
+    max_reply_size: Option<usize>,
+    /// The ID and secret of the key that requests are signed with.
+    #[cfg(feature = "hmac")]
+    hmac_key: Option<(String, Vec<u8>)>,
+}
+
+pub struct Responder<T = Local> {
+    transport: T,
+    compression: Option<Compression>,
+    max_request_size: Option<usize>,
+    authenticator: Option<Arc<dyn Authenticator>>,
 }
 
 pub fn new_pair() -> (Requester, Responder) {
     let (send1, recv1) = mpsc::sync_channel(1);
     let (send2, recv2) = mpsc::sync_channel(1);
-    let req = Requester {
+    let (send1, send2) = (Arc::new(send1), Arc::new(send2));
+    let (peer1, peer2) = (Arc::downgrade(&send1), Arc::downgrade(&send2));
+    let req = Requester::new(Local {
         outgoing: send1,
         incoming: recv2,
-    };
-    let rep = Responder {
+        peer: peer2,
+    });
+    let rep = Responder::new(Local {
         incoming: recv1,
         outgoing: send2,
-    };
+        peer: peer1,
+    });
     (req, rep)
 }
 
-impl Responder {
+/// Connects a `Requester` to a [`Server`](crate::transport::Server) listening on `addr`.
+pub fn connect_tcp(addr: impl ToSocketAddrs) -> Result<Requester<TcpStream>> {
+    let stream = TcpStream::connect(addr).map_err(|e| format!("Failed to connect: {e}"))?;
+    // Requests are small and sent as a single frame, so there is nothing to gain from batching.
+    stream
+        .set_nodelay(true)
+        .map_err(|e| format!("Failed to configure socket: {e}"))?;
+    Ok(Requester::new(stream))
+}
+
+/// Connects a `Requester` to a [`Server`](crate::transport::Server) listening on the Unix domain
+/// socket at `path`.
+#[cfg(unix)]
+pub fn connect_unix(path: impl AsRef<Path>) -> Result<Requester<UnixStream>> {
+    let stream = UnixStream::connect(path).map_err(|e| format!("Failed to connect: {e}"))?;
+    Ok(Requester::new(stream))
+}
+
+impl<T: Transport> Requester<T> {
+    /// Create a `Requester` that sends its requests over `transport`.
+    pub fn new(transport: T) -> Self {
+        Self {
+            transport,
+            next_id: Cell::new(0),
+            headers: Headers::new(),
+            retry: None,
+            metrics: None,
+            compression: None,
+            max_reply_size: None,
+            #[cfg(feature = "hmac")]
+            hmac_key: None,
+        }
+    }
+
+    /// Returns the transport this requester sends its requests over, e.g. to wrap it in another
+    /// transport.
+    pub fn into_transport(self) -> T {
+        self.transport
+    }
+
+    /// Sends the header `name` with every request, unless the request sets it itself.
+    pub fn with_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.insert(name, value);
+        self
+    }
+
+    /// The headers that are sent with every request.
+    pub fn headers_mut(&mut self) -> &mut Headers {
+        &mut self.headers
+    }
+
+    /// Retry requests for [idempotent](crate::api::Api::IDEMPOTENT) APIs that fail with a
+    /// [transient](Error::is_transient) error according to `policy`.
+    pub fn with_retry(mut self, policy: RetryPolicy) -> Self {
+        self.retry = Some(policy);
+        self
+    }
+
+    /// Measure every request sent with [`send_request`](Requester::send_request), which includes
+    /// all requests for an [`Api`](crate::api::Api), with `metrics`.
+    ///
+    /// Every attempt of a retried request is measured on its own.
+    pub fn with_metrics(mut self, metrics: impl Metrics + 'static) -> Self {
+        self.metrics = Some(Arc::new(metrics));
+        self
+    }
+
+    /// Compress the data of requests according to `compression`.
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = Some(compression);
+        self
+    }
+
+    /// Fail requests whose reply carries more than `max` bytes of (decompressed) data with
+    /// [`Error::TooLarge`], without deserializing the reply.
+    ///
+    /// Stream transports also refuse to read a reply whose frame is more than 64 KiB longer than
+    /// `max`, without allocating memory for it. The request then fails with
+    /// [`Error::Transport`], and the connection cannot be used anymore.
+    pub fn with_max_reply_size(mut self, max: usize) -> Self {
+        self.max_reply_size = Some(max);
+        self
+    }
+
+    /// Send `token` with every request, for a responder that authenticates requests with
+    /// [`Tokens`](crate::auth::Tokens).
+    pub fn with_token(self, token: impl fmt::Display) -> Self {
+        self.with_header(AUTHORIZATION, format!("Bearer {token}"))
+    }
+
+    /// Sign every request with `secret`, for a responder that authenticates requests with
+    /// [`Hmac`](crate::auth::Hmac) and knows the secret as `key_id`.
+    ///
+    /// This requires the `hmac` feature.
+    #[cfg(feature = "hmac")]
+    pub fn with_hmac_key(mut self, key_id: impl Into<String>, secret: impl Into<Vec<u8>>) -> Self {
+        self.hmac_key = Some((key_id.into(), secret.into()));
+        self
+    }
+
+    /// The retry policy set with [`with_retry`](Requester::with_retry), if any.
+    #[cfg_attr(not(feature = "api"), allow(dead_code))]
+    pub(crate) fn retry_policy(&self) -> Option<&RetryPolicy> {
+        self.retry.as_ref()
+    }
+
+    /// Adds the headers of this requester to `headers`, and a [`CORRELATION_ID`] if there is none.
+    ///
+    /// The correlation ID is that of the request being handled on this thread, if any, so that
+    /// it is passed on to the requests a handler makes.
+    pub(crate) fn add_headers(&self, headers: &mut Headers) {
+        for (name, value) in self.headers.iter() {
+            if !headers.contains(name) {
+                headers.insert(name, value);
+            }
+        }
+        if !headers.contains(CORRELATION_ID) {
+            let id = headers::current_correlation_id().unwrap_or_else(headers::new_correlation_id);
+            headers.insert(CORRELATION_ID, id);
+        }
+    }
+
+    /// Sends `request` under a new [`id`](Message::id) and blocks until the reply to it arrives.
+    ///
+    /// If `request` has a [`deadline`](Message::deadline), this waits only until then and
+    /// returns [`Error::Timeout`] if no reply arrived. The service also skips the request if it
+    /// only gets to it after the deadline, so a request that timed out is effectively cancelled.
+    /// Replies that arrive after their request timed out are discarded.
+    ///
+    /// If the other end answers with a [`Kind::Error`] message, the error is returned. The same
+    /// happens if the other end has shut down.
+    ///
+    /// The headers of this requester are added to the request, see
+    /// [`with_header`](Requester::with_header).
+    pub fn send_request(&self, request: Message) -> Result<Message, Error> {
+        let Some(metrics) = &self.metrics else {
+            return self.exchange(request);
+        };
+        let observation = Observation::start(metrics, Side::Client, &request);
+        let result = self.exchange(request);
+        observation.finish(result.as_ref().map(|reply| reply.data.len()));
+        result
+    }
+
+    /// Sends `request` and waits for its reply, see [`send_request`](Requester::send_request).
+    fn exchange(&self, request: Message) -> Result<Message, Error> {
+        let deadline = request.deadline;
+        let id = self.start_request(request)?;
+        let response = self.recv_response(id, deadline)?;
+        match response.kind {
+            Kind::Reply => Ok(response),
+            Kind::Error => Err(response_error(&response)),
+            Kind::Shutdown => Err(Error::Other("The service has shut down".to_string())),
+            kind => Err(Error::Other(format!(
+                "Expected a reply, but received {kind:?} for '{}'",
+                response.api_name
+            ))),
+        }
+    }
+
+    /// Sends `request` to the API `name` of `service` and waits for the reply, without needing
+    /// its [`Api`](crate::api::Api) type.
+    ///
+    /// The request is handled by the newest version of the API the service knows, and the reply
+    /// is returned as the JSON value it was sent as. This is meant for scripts and debugging
+    /// tools, see the `serde-handler-cli` example.
+    pub fn call_raw(
+        &self,
+        service: &str,
+        name: &str,
+        request: serde_json::Value,
+    ) -> Result<serde_json::Value, Error> {
+        let data = serde_json::to_vec(&request).map_err(|e| format!("Serialize error: {e}"))?;
+        let response = self.send_request(Message {
+            service: service.to_string(),
+            api_name: name.to_string(),
+            data,
+            ..Default::default()
+        })?;
+        serde_json::from_slice(&response.data)
+            .map_err(|e| Error::Other(format!("Deserialize error: {e}")))
+    }
+
+    /// Sends `request` under a new [`id`](Message::id), which is returned.
+    pub(crate) fn start_request(&self, mut request: Message) -> Result<u64, Error> {
+        let id = self.next_id.get();
+        self.next_id.set(id.wrapping_add(1));
+        request.id = id;
+        self.add_headers(&mut request.headers);
+        if let Some(compression) = &self.compression {
+            compression.compress(&mut request)?;
+        }
+        // Signing comes last, as the signature covers the message as it is sent.
+        #[cfg(feature = "hmac")]
+        if let Some((key_id, secret)) = &self.hmac_key {
+            crate::auth::sign(&mut request, key_id, secret);
+        }
+        self.transport
+            .send(request)
+            .map_err(|e| Error::Transport(format!("Failed to send request: {e}")))?;
+        Ok(id)
+    }
+
+    /// Sends `message` as is, e.g. to cancel a request.
+    #[cfg(feature = "api")]
+    pub(crate) fn send(&self, message: Message) -> Result<(), Error> {
+        self.transport
+            .send(message)
+            .map_err(|e| Error::Transport(format!("Failed to send: {e}")))
+    }
+
+    /// Blocks until a message for the request `id` (or a shutdown) arrives, discarding messages
+    /// for other requests, or until `deadline`.
+    pub(crate) fn recv_response(
+        &self,
+        id: u64,
+        deadline: Option<SystemTime>,
+    ) -> Result<Message, Error> {
+        loop {
+            let timeout = match deadline {
+                Some(deadline) => Some(
+                    deadline
+                        .duration_since(SystemTime::now())
+                        .map_err(|_| Error::Timeout)?,
+                ),
+                None => None,
+            };
+            let response = self
+                .transport
+                .recv_limited(timeout, max_frame_len(self.max_reply_size));
+            let response = response
+                .map_err(|e| Error::Transport(format!("Error receiving response: {e}")))?
+                .ok_or(Error::Timeout)?;
+            // Shutdowns are not in response to any particular request.
+            if response.id == id || response.kind == Kind::Shutdown {
+                let mut response = response;
+                compression::decompress(&mut response, self.max_reply_size)?;
+                return Ok(response);
+            }
+        }
+    }
+}
+
+/// Room for the route and headers of a message, on top of its maximum data size.
+const MAX_FRAME_OVERHEAD: usize = 64 * 1024;
+
+/// The longest frame to read for a message that carries at most `max_size` bytes of data.
+///
+/// Compressed data is never much larger than the data itself, which the overhead allows for.
+fn max_frame_len(max_size: Option<usize>) -> usize {
+    max_size.map_or(usize::MAX, |max| max.saturating_add(MAX_FRAME_OVERHEAD))
+}
+
+/// The error reported by a [`Kind::Error`] message.
+pub(crate) fn response_error(response: &Message) -> Error {
+    serde_json::from_slice(&response.data)
+        .unwrap_or_else(|_| Error::Other(String::from_utf8_lossy(&response.data).into_owned()))
+}
+
+impl<T: Transport> Responder<T> {
+    /// Create a `Responder` that receives requests from `transport`.
+    pub fn new(transport: T) -> Self {
+        Self {
+            transport,
+            compression: None,
+            max_request_size: None,
+            authenticator: None,
+        }
+    }
+
+    /// Compress the data of replies according to `compression`.
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = Some(compression);
+        self
+    }
+
+    /// Answer requests that carry more than `max` bytes of (decompressed) data with
+    /// [`Error::TooLarge`], without handling them.
+    ///
+    /// Stream transports also refuse to read a request whose frame is more than 64 KiB longer
+    /// than `max`, without allocating memory for it, and close the connection it arrived on.
+    pub fn with_max_request_size(mut self, max: usize) -> Self {
+        self.max_request_size = Some(max);
+        self
+    }
+
+    /// Authenticate every request with `authenticator` before it is handled, answering the
+    /// requests it rejects with [`Error::Unauthorized`].
+    ///
+    /// Accepted requests carry the principal they were authenticated as in the [`PRINCIPAL`]
+    /// header. That header is removed from every request the responder receives, so it cannot be
+    /// forged by requesters, with or without an authenticator.
+    pub fn with_authenticator(mut self, authenticator: impl Authenticator + 'static) -> Self {
+        self.authenticator = Some(Arc::new(authenticator));
+        self
+    }
+
+    /// Blocks until the next request arrives.
+    ///
+    /// Returns a [`Kind::Shutdown`] message once all requesters have hung up or a
+    /// [`ShutdownHandle`] was used.
     pub fn next_request(&self) -> Result<Message> {
-        self.incoming.recv().map_err(|e| format!("Recv error: {e}"))
+        loop {
+            let request = self
+                .transport
+                .recv_limited(None, max_frame_len(self.max_request_size))
+                .map_err(|e| format!("Recv error: {e}"))?;
+            let Some(request) = request else {
+                continue;
+            };
+            if let Some(request) = self.accept(request)? {
+                return Ok(request);
+            }
+        }
     }
 
-    pub fn send_response(&self, message: Message) -> Result<()> {
-        self.outgoing
+    /// Returns the next request if one has already arrived, without blocking.
+    #[cfg(feature = "api")]
+    pub(crate) fn try_next_request(&self) -> Result<Option<Message>> {
+        self.next_request_timeout(Duration::ZERO)
+    }
+
+    /// Returns the next request, or `None` if none arrives within `timeout`.
+    #[cfg(feature = "api")]
+    pub(crate) fn next_request_timeout(&self, timeout: Duration) -> Result<Option<Message>> {
+        loop {
+            let request = self
+                .transport
+                .recv_limited(Some(timeout), max_frame_len(self.max_request_size))
+                .map_err(|e| format!("Recv error: {e}"))?;
+            let Some(request) = request else {
+                return Ok(None);
+            };
+            if let Some(request) = self.accept(request)? {
+                return Ok(Some(request));
+            }
+        }
+    }
+
+    /// The requester that sent the last received request, see [`Transport::current_peer`].
+    #[cfg(feature = "api")]
+    pub(crate) fn current_peer(&self) -> Option<crate::transport::PeerId> {
+        self.transport.current_peer()
+    }
+
+    /// Makes `peer` the requester that replies are sent to, see
+    /// [`Transport::set_current_peer`].
+    #[cfg(feature = "api")]
+    pub(crate) fn set_current_peer(&self, peer: Option<crate::transport::PeerId>) {
+        self.transport.set_current_peer(peer);
+    }
+
+    /// Authenticates `request` and decompresses its data, or answers it with an error if that
+    /// fails, e.g. because it exceeds the [maximum size](Responder::with_max_request_size).
+    /// Events are dropped instead, as they are never answered.
+    fn accept(&self, mut request: Message) -> Result<Option<Message>> {
+        match self
+            .authenticate(&mut request)
+            .and_then(|()| compression::decompress(&mut request, self.max_request_size))
+        {
+            Ok(()) => Ok(Some(request)),
+            Err(_) if request.kind == Kind::Event => Ok(None),
+            Err(e) => {
+                self.send_reply(request.error(e));
+                Ok(None)
+            }
+        }
+    }
+
+    /// Sets the [`PRINCIPAL`] of `request` if it starts a request and is authenticated.
+    ///
+    /// This happens before decompression, as requests are signed as they are sent.
+    fn authenticate(&self, request: &mut Message) -> Result<(), Error> {
+        request.headers.remove(PRINCIPAL);
+        let Some(authenticator) = &self.authenticator else {
+            return Ok(());
+        };
+        if !matches!(
+            request.kind,
+            Kind::Request | Kind::StreamRequest | Kind::Batch | Kind::Event
+        ) {
+            return Ok(());
+        }
+        let principal = authenticator
+            .authenticate(request)
+            .map_err(Error::Unauthorized)?;
+        request.headers.insert(PRINCIPAL, principal);
+        Ok(())
+    }
+
+    /// Returns the next message from the requester of the request that was received last, if
+    /// one has already arrived.
+    pub fn poll_requester(&self) -> Result<Option<Message>> {
+        self.transport
+            .recv_from_current_timeout(Duration::ZERO)
+            .map_err(|e| format!("Recv error: {e}"))
+    }
+
+    pub fn send_response(&self, mut message: Message) -> Result<()> {
+        if let Some(compression) = &self.compression {
+            compression.compress(&mut message)?;
+        }
+        self.transport
             .send(message)
             .map_err(|e| format!("Failed to send: {e}"))
     }
+
+    /// Like [`send_response`](Responder::send_response), but drops `message` if it cannot be sent,
+    /// e.g. because its requester hung up, returning whether it was sent.
+    ///
+    /// This way a requester that is gone does not stop a responder that has others, such as one
+    /// on a [`Server`](crate::transport::Server). Transports with a single peer report that it
+    /// hung up when receiving the next message.
+    pub(crate) fn send_reply(&self, message: Message) -> bool {
+        match self.send_response(message) {
+            Ok(()) => true,
+            Err(_e) => {
+                #[cfg(feature = "tracing")]
+                tracing::warn!(error = %_e, "Dropped a reply that could not be sent");
+                false
+            }
+        }
+    }
+
+    /// Create a handle that stops the responder from another thread.
+    ///
+    /// Using the handle makes [`next_request`](Responder::next_request) return a
+    /// [`Kind::Shutdown`] message once the requests that were received before have been handled.
+    pub fn shutdown_handle(&self) -> Result<ShutdownHandle> {
+        self.transport.shutdown_handle()
+    }
 }
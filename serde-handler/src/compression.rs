@@ -0,0 +1,194 @@
+//! Compressing the data of messages, and limiting its size.
+//!
+//! A [`Requester`](crate::channel::Requester::with_compression) compresses the data of its
+//! requests, and a [`Responder`](crate::channel::Responder::with_compression) that of its
+//! replies, if they are configured with a [`Compression`]. Every message says how its data is
+//! encoded, so the other end decompresses it without being configured, as long as the cargo
+//! feature of the [`Encoding`] is enabled. A [`Compression`] can only be created for an encoding
+//! whose feature is enabled, so that a missing feature shows up when it is configured.
+//!
+//! Data is decompressed as soon as a message arrives, and its size is checked against the
+//! maximum of the receiving end before it is deserialized.
+
+#[cfg(any(feature = "deflate", feature = "zstd"))]
+use std::io::Read;
+
+use crate::{
+    channel::{Error, Message},
+    Result,
+};
+
+/// How the [`data`](Message::data) of a message is encoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Encoding {
+    /// Not compressed.
+    #[default]
+    Identity,
+    /// Compressed with zlib's deflate, which requires the `deflate` feature.
+    Deflate,
+    /// Compressed with Zstandard, which requires the `zstd` feature.
+    Zstd,
+}
+
+impl Encoding {
+    pub(crate) fn to_byte(self) -> u8 {
+        match self {
+            Encoding::Identity => 0,
+            Encoding::Deflate => 1,
+            Encoding::Zstd => 2,
+        }
+    }
+
+    pub(crate) fn from_byte(byte: u8) -> Result<Self> {
+        match byte {
+            0 => Ok(Encoding::Identity),
+            1 => Ok(Encoding::Deflate),
+            2 => Ok(Encoding::Zstd),
+            _ => Err(format!("Unknown encoding {byte}")),
+        }
+    }
+
+    /// Whether the cargo feature that this encoding requires is enabled.
+    pub fn is_supported(self) -> bool {
+        match self {
+            Encoding::Identity => true,
+            Encoding::Deflate => cfg!(feature = "deflate"),
+            Encoding::Zstd => cfg!(feature = "zstd"),
+        }
+    }
+}
+
+/// When and how to compress the data of outgoing messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Compression {
+    encoding: Encoding,
+    threshold: usize,
+}
+
+impl Compression {
+    /// Compress data of at least 1 KiB with `encoding`.
+    ///
+    /// # Panics
+    ///
+    /// If the cargo feature of `encoding` is not enabled, as no message could be sent then.
+    pub fn new(encoding: Encoding) -> Self {
+        assert!(encoding.is_supported(), "{}", unsupported(encoding));
+        Self {
+            encoding,
+            threshold: 1024,
+        }
+    }
+
+    /// Only compress data of at least `threshold` bytes, as compressing small data costs more
+    /// time than it saves.
+    pub fn threshold(mut self, threshold: usize) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    /// Compresses the data of `message` if it is large enough, and compression makes it smaller.
+    pub(crate) fn compress(&self, message: &mut Message) -> Result<()> {
+        if message.encoding != Encoding::Identity || message.data.len() < self.threshold {
+            return Ok(());
+        }
+        let compressed = match self.encoding {
+            Encoding::Identity => return Ok(()),
+            Encoding::Deflate => deflate(&message.data)?,
+            Encoding::Zstd => zstd_compress(&message.data)?,
+        };
+        if compressed.len() < message.data.len() {
+            message.data = compressed;
+            message.encoding = self.encoding;
+        }
+        Ok(())
+    }
+}
+
+/// Decompresses the data of `message`, failing with [`Error::TooLarge`] if it has more than
+/// `max_size` bytes.
+pub(crate) fn decompress(message: &mut Message, max_size: Option<usize>) -> Result<(), Error> {
+    let too_large = |size| Error::TooLarge {
+        size,
+        max: max_size.unwrap_or_default(),
+    };
+    // Reading one byte more than allowed tells whether there were more.
+    let limit = max_size.map_or(u64::MAX, |max| max as u64 + 1);
+    let data = match message.encoding {
+        Encoding::Identity => {
+            if max_size.is_some_and(|max| message.data.len() > max) {
+                return Err(too_large(Some(message.data.len())));
+            }
+            return Ok(());
+        }
+        Encoding::Deflate => inflate(&message.data, limit)?,
+        Encoding::Zstd => zstd_decompress(&message.data, limit)?,
+    };
+    if max_size.is_some_and(|max| data.len() > max) {
+        return Err(too_large(None));
+    }
+    message.data = data;
+    message.encoding = Encoding::Identity;
+    Ok(())
+}
+
+#[cfg(feature = "deflate")]
+fn deflate(data: &[u8]) -> Result<Vec<u8>> {
+    let encoder = flate2::read::ZlibEncoder::new(data, flate2::Compression::default());
+    read_limited(encoder, u64::MAX).map_err(|e| format!("Compression error: {e}"))
+}
+
+#[cfg(feature = "deflate")]
+fn inflate(data: &[u8], limit: u64) -> Result<Vec<u8>> {
+    read_limited(flate2::read::ZlibDecoder::new(data), limit)
+        .map_err(|e| format!("Decompression error: {e}"))
+}
+
+#[cfg(not(feature = "deflate"))]
+fn deflate(_: &[u8]) -> Result<Vec<u8>> {
+    Err(unsupported(Encoding::Deflate))
+}
+
+#[cfg(not(feature = "deflate"))]
+fn inflate(_: &[u8], _: u64) -> Result<Vec<u8>> {
+    Err(unsupported(Encoding::Deflate))
+}
+
+#[cfg(feature = "zstd")]
+fn zstd_compress(data: &[u8]) -> Result<Vec<u8>> {
+    zstd::encode_all(data, zstd::DEFAULT_COMPRESSION_LEVEL)
+        .map_err(|e| format!("Compression error: {e}"))
+}
+
+#[cfg(feature = "zstd")]
+fn zstd_decompress(data: &[u8], limit: u64) -> Result<Vec<u8>> {
+    zstd::Decoder::new(data)
+        .and_then(|decoder| read_limited(decoder, limit))
+        .map_err(|e| format!("Decompression error: {e}"))
+}
+
+#[cfg(not(feature = "zstd"))]
+fn zstd_compress(_: &[u8]) -> Result<Vec<u8>> {
+    Err(unsupported(Encoding::Zstd))
+}
+
+#[cfg(not(feature = "zstd"))]
+fn zstd_decompress(_: &[u8], _: u64) -> Result<Vec<u8>> {
+    Err(unsupported(Encoding::Zstd))
+}
+
+/// Reads at most `limit` bytes from `decoder`.
+#[cfg(any(feature = "deflate", feature = "zstd"))]
+fn read_limited(decoder: impl Read, limit: u64) -> std::io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    decoder.take(limit).read_to_end(&mut data)?;
+    Ok(data)
+}
+
+fn unsupported(encoding: Encoding) -> String {
+    let feature = match encoding {
+        Encoding::Identity => unreachable!("uncompressed data is always supported"),
+        Encoding::Deflate => "deflate",
+        Encoding::Zstd => "zstd",
+    };
+    format!("{encoding:?} compression requires the `{feature}` feature")
+}
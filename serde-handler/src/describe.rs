@@ -0,0 +1,171 @@
+//! Introspection of the services and APIs an [`ApiRouter`](crate::api::ApiRouter) serves.
+//!
+//! Every router answers the reserved [`Describe`] API with a [`Description`] of its routes, so
+//! that generic tooling can discover services without knowing their [`Api`] types. Routers that
+//! should not reveal their routes to everyone can
+//! [authorize](crate::api::ApiRouter::authorize) requests for [`Describe`] like for any other API.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::{
+    api::Api,
+    channel::{Error, Requester},
+    transport::Transport,
+};
+
+/// The service of the [`Describe`] API. No other APIs can be registered for it.
+pub const RESERVED_SERVICE: &str = "serde_handler";
+
+/// Requests the [`Description`] of everything a router serves.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Describe;
+
+impl Api for Describe {
+    type Reply<'de> = Description;
+    type Request<'de> = Describe;
+
+    const NAME: &'static str = "describe";
+    const SERVICE: &'static str = RESERVED_SERVICE;
+}
+
+/// The services served by a router, by name, with their APIs by name.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Description {
+    pub services: BTreeMap<String, BTreeMap<String, ApiDescription>>,
+}
+
+impl Description {
+    /// Returns the description of the API `api_name` of `service`, if it is served.
+    pub fn api(&self, service: &str, api_name: &str) -> Option<&ApiDescription> {
+        self.services.get(service)?.get(api_name)
+    }
+}
+
+impl fmt::Display for Description {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (service, apis) in &self.services {
+            writeln!(f, "{service}")?;
+            for (api_name, api) in apis {
+                let versions: Vec<_> = api.versions.iter().map(|v| format!("v{v}")).collect();
+                let streaming = if api.streaming { ", streaming" } else { "" };
+                writeln!(f, "  {api_name} ({}{streaming})", versions.join(", "))?;
+                if let Some(schema) = &api.schema {
+                    writeln!(f, "    request: {}", schema.request)?;
+                    writeln!(f, "    reply  : {}", schema.reply)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A single API served by a router.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ApiDescription {
+    /// The [`Api::VERSION`]s that are served, in ascending order.
+    pub versions: Vec<u32>,
+    /// Whether the newest version is a [`StreamingApi`](crate::streaming::StreamingApi).
+    pub streaming: bool,
+    /// The schema of the newest version of the API, if it provides one via [`Api::schema`].
+    pub schema: Option<ApiSchema>,
+}
+
+/// The JSON Schemas of the [`Request`](Api::Request) and [`Reply`](Api::Reply) of an API.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ApiSchema {
+    pub request: Value,
+    pub reply: Value,
+}
+
+impl ApiSchema {
+    /// The schemas of the request type `Req` and the reply type `Rep`.
+    pub fn of<Req: JsonSchema + ?Sized, Rep: JsonSchema + ?Sized>() -> Self {
+        Self {
+            request: Req::json_schema(),
+            reply: Rep::json_schema(),
+        }
+    }
+}
+
+/// A type that can describe the JSON it serializes to with a JSON Schema.
+///
+/// This is implemented for common standard library types. For your own request and reply types,
+/// implement it by hand or forward to a schema generator such as `schemars`.
+pub trait JsonSchema {
+    fn json_schema() -> Value;
+}
+
+macro_rules! impl_json_schema {
+    ($schema_type:literal: $($ty:ty),*) => {
+        $(
+            impl JsonSchema for $ty {
+                fn json_schema() -> Value {
+                    json!({ "type": $schema_type })
+                }
+            }
+        )*
+    };
+}
+
+impl_json_schema!("boolean": bool);
+impl_json_schema!("integer": u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize);
+impl_json_schema!("number": f32, f64);
+impl_json_schema!("string": char, str, String);
+impl_json_schema!("null": ());
+
+impl<T: JsonSchema + ?Sized> JsonSchema for &T {
+    fn json_schema() -> Value {
+        T::json_schema()
+    }
+}
+
+impl<T: JsonSchema + ?Sized> JsonSchema for Box<T> {
+    fn json_schema() -> Value {
+        T::json_schema()
+    }
+}
+
+impl<T: JsonSchema> JsonSchema for Option<T> {
+    fn json_schema() -> Value {
+        json!({ "anyOf": [T::json_schema(), { "type": "null" }] })
+    }
+}
+
+impl<T: JsonSchema> JsonSchema for [T] {
+    fn json_schema() -> Value {
+        json!({ "type": "array", "items": T::json_schema() })
+    }
+}
+
+impl<T: JsonSchema> JsonSchema for Vec<T> {
+    fn json_schema() -> Value {
+        <[T]>::json_schema()
+    }
+}
+
+impl<V: JsonSchema, S> JsonSchema for HashMap<String, V, S> {
+    fn json_schema() -> Value {
+        json!({ "type": "object", "additionalProperties": V::json_schema() })
+    }
+}
+
+impl<V: JsonSchema> JsonSchema for BTreeMap<String, V> {
+    fn json_schema() -> Value {
+        json!({ "type": "object", "additionalProperties": V::json_schema() })
+    }
+}
+
+impl<T: Transport> Requester<T> {
+    /// Asks the service for a [`Description`] of all services and APIs it serves.
+    ///
+    /// The description implements `Display` to print it in a readable form.
+    pub fn describe(&self) -> Result<Description, Error> {
+        self.request(Describe)
+    }
+}
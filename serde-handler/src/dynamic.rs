@@ -0,0 +1,297 @@
+//! Changing the routes of an [`ApiRouter`](crate::api::ApiRouter) while it serves requests.
+//!
+//! [`ApiRouter::handle`](crate::api::ApiRouter::handle) returns a [`RouterHandle`], which can be
+//! cloned and sent to other threads, e.g. to load plugins on demand. Handlers registered through
+//! it are used from the next request on, and take precedence over the routes the router was built
+//! with for the same API version, so that those can be swapped out at runtime.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+};
+
+use crate::{
+    api::{Api, Handler},
+    describe::{ApiDescription, ApiSchema, RESERVED_SERVICE},
+    Result,
+};
+
+type BoxedSendHandler = Box<dyn FnMut(&[u8]) -> Result<Vec<u8>> + Send>;
+
+/// A handler that was registered through a [`RouterHandle`].
+///
+/// It is returned when it is replaced or removed, and can be put back with
+/// [`RouterHandle::restore`].
+#[derive(Clone)]
+pub struct DynamicHandler {
+    service: &'static str,
+    api_name: &'static str,
+    version: u32,
+    handle: Arc<Mutex<BoxedSendHandler>>,
+    schema: Option<ApiSchema>,
+}
+
+impl DynamicHandler {
+    /// Wraps a handler for API requests of type `A`.
+    pub fn new<A: Api, H: Handler<A> + Send + 'static>(mut handler: H) -> Self {
+        let handler = move |request_data: &[u8]| -> Result<Vec<u8>> {
+            let request: A::Request<'_> = serde_json::from_slice(request_data)
+                .map_err(|e| format!("Deserialize error: {e}"))?;
+            let reply = handler(request);
+            serde_json::to_vec(&reply).map_err(|e| format!("Serialize error: {e}"))
+        };
+        Self {
+            service: A::SERVICE,
+            api_name: A::NAME,
+            version: A::VERSION,
+            handle: Arc::new(Mutex::new(Box::new(handler))),
+            schema: A::schema(),
+        }
+    }
+
+    /// The [`Api::SERVICE`] of the handled API.
+    pub fn service(&self) -> &'static str {
+        self.service
+    }
+
+    /// The [`Api::NAME`] of the handled API.
+    pub fn api_name(&self) -> &'static str {
+        self.api_name
+    }
+
+    /// The [`Api::VERSION`] of the handled API.
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    /// Handles the serialized request `request_data`.
+    ///
+    /// Only this handler is locked while it runs, so it may change the routes itself.
+    pub(crate) fn call(&self, request_data: &[u8]) -> Result<Vec<u8>> {
+        // A handler that panicked is still in a usable state, as all it holds is its closure.
+        let mut handler = self.handle.lock().unwrap_or_else(PoisonError::into_inner);
+        handler(request_data)
+    }
+}
+
+/// A route of an [`ApiRouter`](crate::api::ApiRouter), as listed by [`RouterHandle::routes`].
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct RouteInfo {
+    pub service: String,
+    pub api_name: String,
+    pub version: u32,
+    /// Whether the route was registered through a [`RouterHandle`].
+    pub dynamic: bool,
+}
+
+type RouteKey = (&'static str, &'static str);
+
+#[derive(Default)]
+struct Routes {
+    /// The versions of the APIs the router was built with.
+    fixed: BTreeMap<RouteKey, BTreeSet<u32>>,
+    dynamic: BTreeMap<RouteKey, BTreeMap<u32, DynamicHandler>>,
+}
+
+impl Routes {
+    fn dynamic<'a>(
+        &'a self,
+        service: &'a str,
+        api_name: &'a str,
+    ) -> Option<&'a BTreeMap<u32, DynamicHandler>> {
+        // Looking up `&'static str` keys with shorter lived ones needs a shorter lived map.
+        let dynamic: &BTreeMap<(&str, &str), _> = &self.dynamic;
+        dynamic.get(&(service, api_name))
+    }
+}
+
+/// A shareable handle to the routes of an [`ApiRouter`](crate::api::ApiRouter), that registers,
+/// replaces and removes handlers while the router is serving.
+///
+/// Changes apply from the next request the router handles on. A request that is being handled
+/// while its route is replaced or removed still completes with the previous handler.
+#[derive(Clone, Default)]
+pub struct RouterHandle {
+    routes: Arc<Mutex<Routes>>,
+}
+
+impl RouterHandle {
+    /// Add a handler for API requests of type `A`.
+    ///
+    /// Fails if a handler for `A` has already been registered through a handle, or if `A`
+    /// belongs to the reserved service of [`Describe`](crate::describe::Describe). A route for
+    /// `A` that the router was built with is overridden until the handler is removed again.
+    pub fn register<A: Api, H: Handler<A> + Send + 'static>(&self, handler: H) -> Result<()> {
+        let handler = DynamicHandler::new::<A, _>(handler);
+        Self::check_service(handler.service)?;
+        let mut routes = self.lock();
+        let versions = routes
+            .dynamic
+            .entry((handler.service, handler.api_name))
+            .or_default();
+        if versions.contains_key(&handler.version) {
+            return Err(format!(
+                "Duplicate handler for '{}' in service '{}' at version {}",
+                handler.api_name, handler.service, handler.version
+            ));
+        }
+        versions.insert(handler.version, handler);
+        Ok(())
+    }
+
+    /// Add a handler for API requests of type `A`, returning the handler for `A` that was
+    /// registered through a handle before, if any.
+    ///
+    /// # Panics
+    ///
+    /// If `A` belongs to the reserved service of [`Describe`](crate::describe::Describe).
+    pub fn replace<A: Api, H: Handler<A> + Send + 'static>(
+        &self,
+        handler: H,
+    ) -> Option<DynamicHandler> {
+        self.restore(DynamicHandler::new::<A, _>(handler))
+    }
+
+    /// Register a `handler` that was returned from [`replace`](RouterHandle::replace) or
+    /// [`remove`](RouterHandle::remove), returning the handler it replaces, if any.
+    ///
+    /// # Panics
+    ///
+    /// Like [`replace`](RouterHandle::replace).
+    pub fn restore(&self, handler: DynamicHandler) -> Option<DynamicHandler> {
+        if let Err(e) = Self::check_service(handler.service) {
+            panic!("{e}");
+        }
+        self.lock()
+            .dynamic
+            .entry((handler.service, handler.api_name))
+            .or_default()
+            .insert(handler.version, handler)
+    }
+
+    /// Remove the handler for API requests of type `A` that was registered through a handle,
+    /// returning it.
+    ///
+    /// Routes the router was built with cannot be removed, and are used again once no handler
+    /// overrides them.
+    pub fn remove<A: Api>(&self) -> Option<DynamicHandler> {
+        let mut routes = self.lock();
+        let versions = routes.dynamic.get_mut(&(A::SERVICE, A::NAME))?;
+        let handler = versions.remove(&A::VERSION);
+        if versions.is_empty() {
+            routes.dynamic.remove(&(A::SERVICE, A::NAME));
+        }
+        handler
+    }
+
+    /// Lists the routes the router currently has, ordered by service, API name and version.
+    ///
+    /// A version that is overridden by a handler registered through a handle is only listed
+    /// once, as dynamic.
+    pub fn routes(&self) -> Vec<RouteInfo> {
+        let routes = self.lock();
+        let fixed = routes.fixed.iter().flat_map(|(key, versions)| {
+            versions
+                .iter()
+                .filter(|version| {
+                    !routes
+                        .dynamic
+                        .get(key)
+                        .is_some_and(|d| d.contains_key(version))
+                })
+                .map(move |&version| (key, version, false))
+        });
+        let dynamic = routes
+            .dynamic
+            .iter()
+            .flat_map(|(key, versions)| versions.keys().map(move |&version| (key, version, true)));
+        let mut list: Vec<_> = fixed
+            .chain(dynamic)
+            .map(|(&(service, api_name), version, dynamic)| RouteInfo {
+                service: service.to_string(),
+                api_name: api_name.to_string(),
+                version,
+                dynamic,
+            })
+            .collect();
+        list.sort();
+        list
+    }
+
+    fn check_service(service: &str) -> Result<()> {
+        if service == RESERVED_SERVICE {
+            return Err(format!("Service '{RESERVED_SERVICE}' is reserved"));
+        }
+        Ok(())
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Routes> {
+        // The routes are only changed by single inserts and removes, so they are never left in
+        // an inconsistent state by a panic.
+        self.routes.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Records a route the router was built with.
+    pub(crate) fn add_fixed(&self, service: &'static str, api_name: &'static str, version: u32) {
+        self.lock()
+            .fixed
+            .entry((service, api_name))
+            .or_default()
+            .insert(version);
+    }
+
+    /// Whether any handler for `service` was registered through a handle.
+    pub(crate) fn serves(&self, service: &str) -> bool {
+        self.lock()
+            .dynamic
+            .keys()
+            .any(|(dynamic_service, _)| *dynamic_service == service)
+    }
+
+    /// The versions of `api_name` in `service` that have a handler registered through a handle.
+    pub(crate) fn versions(&self, service: &str, api_name: &str) -> Vec<u32> {
+        self.lock()
+            .dynamic(service, api_name)
+            .map_or_else(Vec::new, |versions| versions.keys().copied().collect())
+    }
+
+    /// The handler registered through a handle for `version` of `api_name` in `service`.
+    pub(crate) fn get(
+        &self,
+        service: &str,
+        api_name: &str,
+        version: u32,
+    ) -> Option<DynamicHandler> {
+        self.lock()
+            .dynamic(service, api_name)?
+            .get(&version)
+            .cloned()
+    }
+
+    /// Describes the APIs that have handlers registered through a handle.
+    pub(crate) fn describe(&self) -> Vec<(&'static str, &'static str, ApiDescription)> {
+        self.lock()
+            .dynamic
+            .iter()
+            .map(|(&(service, api_name), versions)| {
+                let (_, newest) = versions.last_key_value().expect("APIs have a route");
+                let description = ApiDescription {
+                    versions: versions.keys().copied().collect(),
+                    streaming: false,
+                    schema: newest.schema.clone(),
+                };
+                (service, api_name, description)
+            })
+            .collect()
+    }
+
+    /// Moves the handlers registered through `other` into this handle, so that changes made
+    /// through `other` no longer have an effect.
+    pub(crate) fn absorb(&self, other: &RouterHandle) {
+        let other = std::mem::take(&mut other.lock().dynamic);
+        let mut routes = self.lock();
+        for (key, versions) in other {
+            routes.dynamic.entry(key).or_default().extend(versions);
+        }
+    }
+}
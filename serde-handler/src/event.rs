@@ -0,0 +1,181 @@
+//! Fire-and-forget events, delivered to every subscriber of their topic.
+//!
+//! Events are sent as [`Kind::Event`] messages with [`Requester::publish`], which returns as soon
+//! as the event has been sent. They are never answered, not even with an error.
+//!
+//! The topic of an [`Event`] is its [`NAME`](Event::NAME) in its [`SERVICE`](Event::SERVICE). An
+//! [`EventBus`] hands every event it receives to all subscribers of its topic, in the order they
+//! subscribed, either on its own [`Responder`] or on that of an
+//! [`ApiRouter`](crate::api::ApiRouter::with_events).
+//!
+//! Delivery is at most once: publishers never send an event again, and the bus does not retry a
+//! subscriber. Events are dropped without notice if they are lost by the transport, if their
+//! [`deadline`](Message::deadline) has passed, if their topic has no subscribers, if a router's
+//! [rule](crate::api::ApiRouter::authorize_event) does not allow them, or, for the subscribers
+//! concerned, if their payload cannot be deserialized.
+
+use std::{collections::HashMap, time::SystemTime};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    channel::{Error, Kind, Message, Requester, Responder},
+    transport::Transport,
+    Result,
+};
+
+/// A fire-and-forget message for the subscribers of a topic.
+///
+/// Like an [`Api`](crate::api::Api), an event is identified by a `SERVICE` and a `NAME`, and its
+/// [`Payload`](Event::Payload) may borrow from the data it is deserialized from.
+pub trait Event: Serialize {
+    /// The service that publishes the event.
+    const SERVICE: &'static str;
+
+    /// The name of the event, which identifies its topic within the `SERVICE`.
+    const NAME: &'static str;
+
+    /// The data of the event.
+    type Payload<'de>: Serialize + Deserialize<'de>;
+}
+
+/// A function that can receive [`E::Payload<'de>`](Event::Payload) for `'de == 'ev`.
+pub trait SubscriberOn<'ev, E: Event>: FnMut(E::Payload<'ev>) {}
+impl<'ev, E: Event, F: FnMut(E::Payload<'ev>)> SubscriberOn<'ev, E> for F {}
+
+/// A function that can receive [`E::Payload<'de>`](Event::Payload) for any `'de`.
+pub trait Subscriber<E: Event>: for<'ev> SubscriberOn<'ev, E> {}
+impl<E: Event, F: for<'ev> SubscriberOn<'ev, E>> Subscriber<E> for F {}
+
+type BoxedSubscriber = Box<dyn FnMut(&[u8]) -> Result<()>>;
+
+/// Delivers events to the subscribers of their topic, see the [module documentation](self).
+#[derive(Default)]
+pub struct EventBus {
+    /// The subscribers of each topic, by service and name.
+    topics: HashMap<&'static str, HashMap<&'static str, Vec<BoxedSubscriber>>>,
+}
+
+impl EventBus {
+    /// Create an `EventBus` without subscribers.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add `subscriber` to the topic of `E`, after any subscribers it already has.
+    ///
+    /// The subscriber receives every event for the topic whose data can be deserialized into
+    /// [`E::Payload`](Event::Payload).
+    pub fn subscribe<E: Event, H: Subscriber<E> + 'static>(mut self, mut subscriber: H) -> Self {
+        let subscriber = move |data: &[u8]| {
+            let payload: E::Payload<'_> =
+                serde_json::from_slice(data).map_err(|e| format!("Deserialize error: {e}"))?;
+            subscriber(payload);
+            Ok(())
+        };
+        self.topics
+            .entry(E::SERVICE)
+            .or_default()
+            .entry(E::NAME)
+            .or_default()
+            .push(Box::new(subscriber));
+        self
+    }
+
+    /// Delivers `event` to the subscribers of its topic right away, without a transport,
+    /// returning how many of them received it.
+    pub fn publish<'a, E: Event<Payload<'a> = E>>(&mut self, event: E) -> Result<usize> {
+        let data = serde_json::to_vec(&event).map_err(|e| format!("Serialize error: {e}"))?;
+        Ok(self.deliver(&Message {
+            kind: Kind::Event,
+            service: E::SERVICE.to_string(),
+            api_name: E::NAME.to_string(),
+            data,
+            ..Default::default()
+        }))
+    }
+
+    /// Hands the [`Kind::Event`] message `event` to the subscribers of its topic, returning how
+    /// many of them received it.
+    pub(crate) fn deliver(&mut self, event: &Message) -> usize {
+        if event.is_expired() {
+            return 0;
+        }
+        let Some(subscribers) = self
+            .topics
+            .get_mut(event.service.as_str())
+            .and_then(|topics| topics.get_mut(event.api_name.as_str()))
+        else {
+            return 0;
+        };
+        let mut delivered = 0;
+        for subscriber in subscribers {
+            match subscriber(&event.data) {
+                Ok(()) => delivered += 1,
+                Err(_e) => {
+                    #[cfg(feature = "tracing")]
+                    tracing::warn!(
+                        service = %event.service,
+                        api = %event.api_name,
+                        error = %_e,
+                        "Dropped an event that could not be decoded"
+                    );
+                }
+            }
+        }
+        delivered
+    }
+
+    /// Waits for events on `socket` and delivers them to the subscribers of their topic.
+    ///
+    /// Requests are answered with an error, as the bus has no handlers. Serving stops with
+    /// `Ok(())` when `socket` receives a [`Kind::Shutdown`] message, like
+    /// [`ApiRouter::serve_on`](crate::api::ApiRouter::serve_on).
+    pub fn serve_on<T: Transport>(mut self, socket: Responder<T>) -> Result<()> {
+        loop {
+            let message = socket.next_request()?;
+            match message.kind {
+                Kind::Event => {
+                    self.deliver(&message);
+                }
+                Kind::Shutdown => return Ok(()),
+                Kind::Request | Kind::StreamRequest | Kind::Batch => {
+                    socket.send_reply(message.error(format!(
+                        "'{}' in service '{}' cannot be requested from an event bus",
+                        message.api_name, message.service
+                    )));
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+impl<T: Transport> Requester<T> {
+    /// Sends `event` to the subscribers of its topic, without waiting for them to receive it.
+    ///
+    /// Events are sent once, even if this requester has a
+    /// [retry policy](Requester::with_retry), and an `Ok` only means that the event was sent.
+    pub fn publish<'a, E: Event<Payload<'a> = E>>(&self, event: E) -> Result<(), Error> {
+        self.publish_with_deadline(event, None)
+    }
+
+    /// Like [`publish`](Requester::publish), but subscribers only receive the event until
+    /// `deadline`.
+    pub fn publish_with_deadline<'a, E: Event<Payload<'a> = E>>(
+        &self,
+        event: E,
+        deadline: Option<SystemTime>,
+    ) -> Result<(), Error> {
+        let data = serde_json::to_vec(&event).map_err(|e| format!("Serialize error: {e}"))?;
+        self.start_request(Message {
+            kind: Kind::Event,
+            deadline,
+            service: E::SERVICE.to_string(),
+            api_name: E::NAME.to_string(),
+            data,
+            ..Default::default()
+        })?;
+        Ok(())
+    }
+}
@@ -0,0 +1,81 @@
+//! Values that handlers registered with
+//! [`ApiRouter::register_handler_with`](crate::api::ApiRouter::register_handler_with) get
+//! along with their request.
+
+use std::time::SystemTime;
+
+use crate::{channel::Message, headers::Headers, Result};
+
+/// A value that can be extracted from a request [`Message`] and the state `S` of the router that
+/// handles it.
+///
+/// Tuples of up to four `FromRequest` types are extracted element by element, and `()` extracts
+/// nothing.
+pub trait FromRequest<S>: Sized {
+    fn from_request(request: &Message, state: &mut S) -> Result<Self>;
+}
+
+/// A copy of the router's state.
+///
+/// Use a state that is cheap to clone, such as an `Rc` or a handle to a connection pool, or get
+/// mutable access to the state with
+/// [`register_stateful_handler`](crate::api::ApiRouter::register_stateful_handler) instead.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct State<S>(pub S);
+
+impl<S: Clone> FromRequest<S> for State<S> {
+    fn from_request(_: &Message, state: &mut S) -> Result<Self> {
+        Ok(State(state.clone()))
+    }
+}
+
+/// Information about the request that is being handled, besides its data.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Context {
+    pub service: String,
+    pub api_name: String,
+    /// The [`id`](Message::id) the requester assigned to the request.
+    pub id: u64,
+    /// The requested version, which may be older than the one of the handler if the request
+    /// was adapted.
+    pub version: u32,
+    pub deadline: Option<SystemTime>,
+    pub headers: Headers,
+}
+
+impl<S> FromRequest<S> for Context {
+    fn from_request(request: &Message, _: &mut S) -> Result<Self> {
+        Ok(Context {
+            service: request.service.clone(),
+            api_name: request.api_name.clone(),
+            id: request.id,
+            version: request.version,
+            deadline: request.deadline,
+            headers: request.headers.clone(),
+        })
+    }
+}
+
+/// The headers of the request.
+impl<S> FromRequest<S> for Headers {
+    fn from_request(request: &Message, _: &mut S) -> Result<Self> {
+        Ok(request.headers.clone())
+    }
+}
+
+macro_rules! impl_from_request {
+    ($($extract:ident),*) => {
+        impl<S, $($extract: FromRequest<S>),*> FromRequest<S> for ($($extract,)*) {
+            #[allow(unused_variables)]
+            fn from_request(request: &Message, state: &mut S) -> Result<Self> {
+                Ok(($($extract::from_request(request, state)?,)*))
+            }
+        }
+    };
+}
+
+impl_from_request!();
+impl_from_request!(E1);
+impl_from_request!(E1, E2);
+impl_from_request!(E1, E2, E3);
+impl_from_request!(E1, E2, E3, E4);
@@ -0,0 +1,168 @@
+//! Metadata that is sent along with the data of a [`Message`](crate::channel::Message).
+//!
+//! Requesters send their own headers with every request (see
+//! [`Requester::with_header`](crate::channel::Requester::with_header)), and middleware and
+//! handlers can read the headers of the request they handle.
+//!
+//! Every request carries a [`CORRELATION_ID`], which replies echo. Requests that are sent while
+//! a router handles a request on the same thread, e.g. to another service, get the correlation ID
+//! of that request, so that a request can be followed across services in logs.
+
+use std::{
+    cell::RefCell,
+    collections::{btree_map, BTreeMap},
+    process,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// The header that identifies a request and all requests made on its behalf.
+pub const CORRELATION_ID: &str = "correlation-id";
+
+/// The header that identifies a single request, even if it is sent several times.
+///
+/// Requests for APIs that are not [idempotent](crate::api::Api::IDEMPOTENT) carry one, and
+/// routers answer a request they have already handled with the same reply again.
+pub const REQUEST_ID: &str = "request-id";
+
+/// The header that carries the bearer token of a request, as `Bearer <token>`.
+///
+/// See [`auth::Tokens`](crate::auth::Tokens).
+pub const AUTHORIZATION: &str = "authorization";
+
+/// The header that names the principal a responder authenticated a request as.
+///
+/// Responders remove this header from every request they receive, and only set it after an
+/// [`Authenticator`](crate::auth::Authenticator) accepted the request, so handlers and
+/// authorization rules can trust it.
+pub const PRINCIPAL: &str = "principal";
+
+/// The header that names the key a request was signed with, see
+/// [`auth::Hmac`](crate::auth::Hmac).
+pub const KEY_ID: &str = "key-id";
+
+/// The header that carries the signature of a request, see [`auth::Hmac`](crate::auth::Hmac).
+pub const SIGNATURE: &str = "signature";
+
+/// Header names and their values.
+///
+/// Names are case-sensitive, and by convention lowercase.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Headers(BTreeMap<String, String>);
+
+impl Headers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the value of the header `name`, if it is set.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0.get(name).map(String::as_str)
+    }
+
+    /// Sets the header `name` to `value`, returning its previous value.
+    pub fn insert(&mut self, name: impl Into<String>, value: impl Into<String>) -> Option<String> {
+        self.0.insert(name.into(), value.into())
+    }
+
+    /// Removes the header `name`, returning its value.
+    pub fn remove(&mut self, name: &str) -> Option<String> {
+        self.0.remove(name)
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.0.contains_key(name)
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Returns the headers in the order of their names.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+
+    /// The [`CORRELATION_ID`] header.
+    pub fn correlation_id(&self) -> Option<&str> {
+        self.get(CORRELATION_ID)
+    }
+}
+
+impl<K: Into<String>, V: Into<String>> FromIterator<(K, V)> for Headers {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut headers = Headers::new();
+        headers.extend(iter);
+        headers
+    }
+}
+
+impl<K: Into<String>, V: Into<String>> Extend<(K, V)> for Headers {
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        for (name, value) in iter {
+            self.insert(name, value);
+        }
+    }
+}
+
+impl IntoIterator for Headers {
+    type Item = (String, String);
+    type IntoIter = btree_map::IntoIter<String, String>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+thread_local! {
+    /// The correlation ID of the request that is being handled on this thread.
+    static CURRENT: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Returns the correlation ID of the request that a router is handling on this thread, if any.
+pub fn current_correlation_id() -> Option<String> {
+    CURRENT.with(|current| current.borrow().clone())
+}
+
+/// Creates a correlation ID that is unique with high probability.
+pub fn new_correlation_id() -> String {
+    unique_id()
+}
+
+/// Creates a [`REQUEST_ID`] that is unique with high probability.
+pub fn new_request_id() -> String {
+    unique_id()
+}
+
+fn unique_id() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos() as u64;
+    format!("{nanos:016x}-{:x}-{count:x}", process::id())
+}
+
+/// Makes the correlation ID of `headers` the current one until the scope is dropped.
+#[cfg_attr(not(feature = "api"), allow(dead_code))]
+pub(crate) fn enter(headers: &Headers) -> CorrelationScope {
+    let id = headers.correlation_id().map(str::to_string);
+    CorrelationScope(CURRENT.with(|current| current.replace(id)))
+}
+
+/// Restores the previous correlation ID when dropped.
+pub(crate) struct CorrelationScope(Option<String>);
+
+impl Drop for CorrelationScope {
+    fn drop(&mut self) {
+        let previous = self.0.take();
+        CURRENT.with(|current| *current.borrow_mut() = previous);
+    }
+}
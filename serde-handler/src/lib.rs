@@ -1,25 +1,38 @@
+#[cfg(feature = "api")]
+pub mod api;
+pub mod auth;
+#[cfg(feature = "api")]
+pub mod batch;
 pub mod channel;
-#[cfg(feature = "missing_closure_type")]
-mod missing_closure_type;
-#[cfg(feature = "multiple_handlers1")]
-mod multiple_handlers1;
-#[cfg(feature = "multiple_handlers2")]
-mod multiple_handlers2;
-#[cfg(feature = "multiple_handlers3")]
-mod multiple_handlers3;
-#[cfg(feature = "multiple_handlers4")]
-mod multiple_handlers4;
-#[cfg(feature = "start")]
-mod start;
-#[cfg(feature = "working")]
-pub mod working;
-#[cfg(feature = "zero_copy1")]
-mod zero_copy1;
-#[cfg(feature = "zero_copy2")]
-pub mod zero_copy2;
-#[cfg(feature = "zero_copy3")]
-pub mod zero_copy3;
-#[cfg(feature = "zero_copy4")]
-pub mod zero_copy4;
+pub mod compression;
+#[cfg(feature = "api")]
+pub mod describe;
+#[cfg(feature = "api")]
+pub mod dynamic;
+#[cfg(feature = "api")]
+pub mod event;
+#[cfg(feature = "api")]
+pub mod extract;
+pub mod headers;
+#[cfg(feature = "api")]
+pub mod limit;
+pub mod metrics;
+pub mod middleware;
+#[cfg(feature = "noise")]
+pub mod noise;
+#[cfg(feature = "api")]
+pub mod request_log;
+pub mod retry;
+#[cfg(feature = "api")]
+pub mod streaming;
+#[cfg(feature = "testing")]
+pub mod testing;
+pub mod transport;
+
+#[cfg(feature = "derive")]
+#[doc(hidden)]
+pub mod __private {
+    pub use serde;
+}
 
 type Result<T, E = String> = std::result::Result<T, E>;
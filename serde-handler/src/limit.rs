@@ -0,0 +1,193 @@
+//! Protecting expensive handlers with rate limits, concurrency limits and queue limits.
+//!
+//! [`Limits`] are set per API with [`ApiRouter::limit`](crate::api::ApiRouter::limit). A request
+//! that exceeds one of them is not handled, but answered with [`Error::Overloaded`], which tells
+//! the requester which limit was reached.
+//!
+//! Clones of a [`Limits`] share their state, so one set of limits can protect an API that is
+//! served by several routers, e.g. on several threads.
+
+use std::{
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    time::{Duration, Instant},
+};
+
+use crate::channel::{Error, Overload};
+
+/// The source of the current time for rate limits.
+///
+/// Tests can use their own clock to control the passing of time.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> Instant;
+}
+
+/// The [`Clock`] of the operating system.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// The limits for requests to an API.
+///
+/// Without any limits set, all requests are handled.
+#[derive(Clone)]
+pub struct Limits {
+    state: Arc<Mutex<State>>,
+    clock: Arc<dyn Clock>,
+}
+
+#[derive(Default)]
+struct State {
+    rate: Option<TokenBucket>,
+    max_concurrency: Option<usize>,
+    max_queued: Option<usize>,
+    in_flight: usize,
+}
+
+/// Allows `capacity` requests at once, and one more every `interval`.
+struct TokenBucket {
+    capacity: u32,
+    interval: Duration,
+    tokens: u32,
+    /// When the last token was added, if the bucket is not full.
+    refilled: Option<Instant>,
+}
+
+impl TokenBucket {
+    /// Takes a token, or returns how long until the next one is added.
+    fn take(&mut self, now: Instant) -> Result<(), Duration> {
+        if let Some(refilled) = self.refilled {
+            let elapsed = now.saturating_duration_since(refilled);
+            let added = (elapsed.as_nanos() / self.interval.as_nanos().max(1))
+                .min(u128::from(self.capacity)) as u32;
+            self.tokens = self.tokens.saturating_add(added).min(self.capacity);
+            // The tokens were added by `now`, so the refill never moves past it.
+            let refilled = self
+                .interval
+                .checked_mul(added)
+                .and_then(|refill| refilled.checked_add(refill))
+                .map_or(now, |refilled| refilled.min(now));
+            self.refilled = (self.tokens < self.capacity).then_some(refilled);
+        }
+        if self.tokens == 0 {
+            let refilled = self.refilled.expect("empty buckets are refilled");
+            let retry_after = refilled
+                .checked_add(self.interval)
+                .map_or(Duration::MAX, |next| next.saturating_duration_since(now));
+            return Err(retry_after);
+        }
+        if self.tokens == self.capacity {
+            self.refilled = Some(now);
+        }
+        self.tokens -= 1;
+        Ok(())
+    }
+}
+
+impl Limits {
+    pub fn new() -> Self {
+        Self {
+            state: Arc::default(),
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Allow at most `requests` requests every `per`, with a token bucket that holds `requests`
+    /// tokens and gets one back every `per / requests`.
+    ///
+    /// This allows bursts of up to `requests` requests after a quiet period.
+    ///
+    /// # Panics
+    ///
+    /// If `requests` is zero.
+    pub fn rate(self, requests: u32, per: Duration) -> Self {
+        assert!(requests > 0, "The rate limit must allow some requests");
+        self.lock().rate = Some(TokenBucket {
+            capacity: requests,
+            interval: per / requests,
+            tokens: requests,
+            refilled: None,
+        });
+        self
+    }
+
+    /// Allow at most `max` requests to be handled at the same time.
+    pub fn max_concurrency(self, max: usize) -> Self {
+        self.lock().max_concurrency = Some(max);
+        self
+    }
+
+    /// Allow at most `max` requests to wait for being handled, not counting the one being
+    /// handled.
+    ///
+    /// Routers only know about the requests that have arrived on their
+    /// [`Responder`](crate::channel::Responder), so this limits the backlog of a single router.
+    pub fn max_queued(self, max: usize) -> Self {
+        self.lock().max_queued = Some(max);
+        self
+    }
+
+    /// Use `clock` instead of the [`SystemClock`] for the rate limit.
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Checks the rate and concurrency limits for a request that is about to be handled.
+    ///
+    /// The request counts as being handled until the returned permit is dropped.
+    pub(crate) fn acquire(&self) -> Result<Permit, Error> {
+        let mut state = self.lock();
+        if state
+            .max_concurrency
+            .is_some_and(|max| state.in_flight >= max)
+        {
+            return Err(Error::Overloaded {
+                limit: Overload::Concurrency,
+                retry_after: None,
+            });
+        }
+        if let Some(rate) = &mut state.rate {
+            rate.take(self.clock.now())
+                .map_err(|retry_after| Error::Overloaded {
+                    limit: Overload::Rate,
+                    retry_after: Some(retry_after),
+                })?;
+        }
+        state.in_flight += 1;
+        Ok(Permit(Arc::clone(&self.state)))
+    }
+
+    /// The [`max_queued`](Limits::max_queued) limit, if any.
+    pub(crate) fn queue_limit(&self) -> Option<usize> {
+        self.lock().max_queued
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        lock(&self.state)
+    }
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Counts a request as being handled until it is dropped.
+pub(crate) struct Permit(Arc<Mutex<State>>);
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        lock(&self.0).in_flight -= 1;
+    }
+}
+
+fn lock(state: &Mutex<State>) -> MutexGuard<'_, State> {
+    // The state is only changed by single assignments, so a panic never leaves it inconsistent.
+    state.lock().unwrap_or_else(PoisonError::into_inner)
+}
@@ -0,0 +1,345 @@
+//! Measuring the requests that requesters send and routers handle.
+//!
+//! A [`Metrics`] implementation is told when a request starts and how it ended. It can be set on
+//! a [`Requester`](crate::channel::Requester::with_metrics) and on an
+//! [`ApiRouter`](crate::api::ApiRouter::with_metrics), which measure every request they send or
+//! handle, but not streams.
+//!
+//! [`PrometheusMetrics`] keeps counts, gauges and histograms per API, and renders them in the
+//! Prometheus text format.
+
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{Arc, Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+
+use crate::channel::{response_error, Error, Kind, Message};
+
+/// Whether a request was measured by the requester that sent it or the router that handled it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Side {
+    Client,
+    Server,
+}
+
+impl Side {
+    fn name(self) -> &'static str {
+        match self {
+            Side::Client => "client",
+            Side::Server => "server",
+        }
+    }
+}
+
+/// The API a request is for, and who measured it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Labels<'a> {
+    pub side: Side,
+    pub service: &'a str,
+    pub api_name: &'a str,
+}
+
+/// Receives measurements of requests.
+///
+/// Every [`request_started`](Metrics::request_started) is followed by exactly one
+/// [`request_finished`](Metrics::request_finished) with the same labels, so the difference between
+/// the two is the number of requests in flight.
+pub trait Metrics: Send + Sync {
+    /// A request with `size` bytes of data is being sent or handled.
+    fn request_started(&self, labels: Labels<'_>, size: usize);
+
+    /// A request finished after `duration`, with a reply of `Ok(size)` bytes of data or an error.
+    fn request_finished(
+        &self,
+        labels: Labels<'_>,
+        duration: Duration,
+        outcome: Result<usize, &Error>,
+    );
+}
+
+/// The service and API name that routers measure requests for routes they do not serve as.
+///
+/// This keeps requesters from creating any number of labels, e.g. series of [`PrometheusMetrics`].
+pub const UNKNOWN: &str = "unknown";
+
+/// Measures a request from its start until [`finish`](Observation::finish) is called.
+pub(crate) struct Observation {
+    metrics: Arc<dyn Metrics>,
+    side: Side,
+    service: String,
+    api_name: String,
+    started: Instant,
+}
+
+impl Observation {
+    pub(crate) fn start(metrics: &Arc<dyn Metrics>, side: Side, request: &Message) -> Self {
+        Self::start_as(metrics, side, request, &request.service, &request.api_name)
+    }
+
+    /// Like [`start`](Observation::start), but measures `request` as one for `api_name` in
+    /// `service`.
+    #[cfg_attr(not(feature = "api"), allow(dead_code))]
+    pub(crate) fn start_as(
+        metrics: &Arc<dyn Metrics>,
+        side: Side,
+        request: &Message,
+        service: &str,
+        api_name: &str,
+    ) -> Self {
+        let observation = Self {
+            metrics: Arc::clone(metrics),
+            side,
+            service: service.to_string(),
+            api_name: api_name.to_string(),
+            started: Instant::now(),
+        };
+        observation
+            .metrics
+            .request_started(observation.labels(), request.data.len());
+        observation
+    }
+
+    pub(crate) fn finish(self, outcome: Result<usize, &Error>) {
+        let duration = self.started.elapsed();
+        self.metrics
+            .request_finished(self.labels(), duration, outcome);
+    }
+
+    /// Finishes with the reply or error that `response` carries.
+    #[cfg_attr(not(feature = "api"), allow(dead_code))]
+    pub(crate) fn finish_with(self, response: &Message) {
+        match response.kind {
+            Kind::Error => self.finish(Err(&response_error(response))),
+            _ => self.finish(Ok(response.data.len())),
+        }
+    }
+
+    fn labels(&self) -> Labels<'_> {
+        Labels {
+            side: self.side,
+            service: &self.service,
+            api_name: &self.api_name,
+        }
+    }
+}
+
+/// The upper bounds of the buckets of the duration histogram, in seconds.
+const DURATION_BUCKETS: &[f64] = &[
+    0.000_1, 0.000_5, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0,
+];
+
+/// The upper bounds of the buckets of the size histograms, in bytes.
+const SIZE_BUCKETS: &[f64] = &[
+    64.0, 256.0, 1024.0, 4096.0, 16384.0, 65536.0, 262144.0, 1048576.0,
+];
+
+/// [`Metrics`] that are kept in memory and rendered in the Prometheus text format.
+///
+/// Clones share their measurements, so one exporter can measure several requesters and routers,
+/// and be rendered from another thread, e.g. by an HTTP handler that serves `/metrics`.
+#[derive(Clone, Default)]
+pub struct PrometheusMetrics {
+    apis: Arc<Mutex<BTreeMap<ApiKey, ApiMetrics>>>,
+}
+
+/// The side, service and API name that measurements are kept for.
+type ApiKey = (Side, String, String);
+
+struct ApiMetrics {
+    requests: u64,
+    /// The number of errors by [`Error::name`].
+    errors: BTreeMap<&'static str, u64>,
+    in_flight: u64,
+    duration: Histogram,
+    request_size: Histogram,
+    reply_size: Histogram,
+}
+
+impl Default for ApiMetrics {
+    fn default() -> Self {
+        Self {
+            requests: 0,
+            errors: BTreeMap::new(),
+            in_flight: 0,
+            duration: Histogram::new(DURATION_BUCKETS),
+            request_size: Histogram::new(SIZE_BUCKETS),
+            reply_size: Histogram::new(SIZE_BUCKETS),
+        }
+    }
+}
+
+/// Selects one of the histograms of an API.
+type HistogramOf = fn(&ApiMetrics) -> &Histogram;
+
+struct Histogram {
+    bounds: &'static [f64],
+    /// The number of observations in each bucket, not including the smaller buckets.
+    buckets: Vec<u64>,
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    fn new(bounds: &'static [f64]) -> Self {
+        Self {
+            bounds,
+            buckets: vec![0; bounds.len()],
+            count: 0,
+            sum: 0.0,
+        }
+    }
+
+    fn observe(&mut self, value: f64) {
+        if let Some(bucket) = self.bounds.iter().position(|&bound| value <= bound) {
+            self.buckets[bucket] += 1;
+        }
+        self.count += 1;
+        self.sum += value;
+    }
+}
+
+impl PrometheusMetrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Renders all measurements in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let apis = self.apis.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut out = String::new();
+        let labels = |(side, service, api_name): &ApiKey| {
+            format!(
+                "side=\"{}\",service=\"{}\",api=\"{}\"",
+                side.name(),
+                escape(service),
+                escape(api_name)
+            )
+        };
+
+        header(
+            &mut out,
+            "requests_total",
+            "counter",
+            "Requests sent or handled.",
+        );
+        for (api, metrics) in apis.iter() {
+            sample(&mut out, "requests_total", &labels(api), metrics.requests);
+        }
+        header(
+            &mut out,
+            "errors_total",
+            "counter",
+            "Requests that failed, by kind of error.",
+        );
+        for (api, metrics) in apis.iter() {
+            for (error, count) in &metrics.errors {
+                let labels = format!("{},error=\"{error}\"", labels(api));
+                sample(&mut out, "errors_total", &labels, count);
+            }
+        }
+        header(
+            &mut out,
+            "in_flight",
+            "gauge",
+            "Requests being sent or handled.",
+        );
+        for (api, metrics) in apis.iter() {
+            sample(&mut out, "in_flight", &labels(api), metrics.in_flight);
+        }
+        let histograms: [(_, _, HistogramOf); 3] = [
+            (
+                "duration_seconds",
+                "How long requests took, until their reply or error.",
+                |metrics| &metrics.duration,
+            ),
+            (
+                "request_bytes",
+                "The size of the data of requests.",
+                |metrics| &metrics.request_size,
+            ),
+            (
+                "reply_bytes",
+                "The size of the data of replies.",
+                |metrics| &metrics.reply_size,
+            ),
+        ];
+        for (name, help, histogram) in histograms {
+            header(&mut out, name, "histogram", help);
+            for (api, metrics) in apis.iter() {
+                let labels = labels(api);
+                let histogram = histogram(metrics);
+                let mut cumulative = 0;
+                for (bound, count) in histogram.bounds.iter().zip(&histogram.buckets) {
+                    cumulative += count;
+                    let labels = format!("{labels},le=\"{bound}\"");
+                    sample(&mut out, &format!("{name}_bucket"), &labels, cumulative);
+                }
+                let labels_inf = format!("{labels},le=\"+Inf\"");
+                sample(
+                    &mut out,
+                    &format!("{name}_bucket"),
+                    &labels_inf,
+                    histogram.count,
+                );
+                sample(&mut out, &format!("{name}_sum"), &labels, histogram.sum);
+                sample(&mut out, &format!("{name}_count"), &labels, histogram.count);
+            }
+        }
+        out
+    }
+
+    fn update(&self, labels: Labels<'_>, update: impl FnOnce(&mut ApiMetrics)) {
+        let mut apis = self.apis.lock().unwrap_or_else(PoisonError::into_inner);
+        let key = (
+            labels.side,
+            labels.service.to_string(),
+            labels.api_name.to_string(),
+        );
+        update(apis.entry(key).or_default());
+    }
+}
+
+impl Metrics for PrometheusMetrics {
+    fn request_started(&self, labels: Labels<'_>, size: usize) {
+        self.update(labels, |metrics| {
+            metrics.requests += 1;
+            metrics.in_flight += 1;
+            metrics.request_size.observe(size as f64);
+        });
+    }
+
+    fn request_finished(
+        &self,
+        labels: Labels<'_>,
+        duration: Duration,
+        outcome: Result<usize, &Error>,
+    ) {
+        self.update(labels, |metrics| {
+            metrics.in_flight -= 1;
+            metrics.duration.observe(duration.as_secs_f64());
+            match outcome {
+                Ok(size) => metrics.reply_size.observe(size as f64),
+                Err(error) => *metrics.errors.entry(error.name()).or_default() += 1,
+            }
+        });
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    writeln!(out, "# HELP serde_handler_{name} {help}").unwrap();
+    writeln!(out, "# TYPE serde_handler_{name} {kind}").unwrap();
+}
+
+fn sample(out: &mut String, name: &str, labels: &str, value: impl std::fmt::Display) {
+    writeln!(out, "serde_handler_{name}{{{labels}}} {value}").unwrap();
+}
+
+/// Escapes a label value for the Prometheus text format.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
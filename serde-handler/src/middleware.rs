@@ -0,0 +1,138 @@
+//! Cross-cutting behaviour that runs around every handler of a router.
+
+use std::{
+    any::Any,
+    panic::{self, AssertUnwindSafe},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    channel::{Error, Message},
+    Result,
+};
+
+/// A type-erased handler that turns raw request data into raw reply data.
+pub type RawHandler<'a> = dyn FnMut(&[u8]) -> Result<Vec<u8>> + 'a;
+
+/// Behaviour that wraps the handling of every request.
+///
+/// A middleware sees the full request [`Message`] (including its `api_name` and raw `data`) and
+/// decides whether and how to pass it on to the `next` middleware, and eventually the handler. The
+/// serialized reply (or error) is returned back through every middleware, which may inspect or
+/// replace it.
+///
+/// Any `FnMut(&Message, Next<'_>) -> Result<Vec<u8>>` closure is a middleware.
+pub trait Middleware {
+    fn handle(&mut self, request: &Message, next: Next<'_>) -> Result<Vec<u8>>;
+
+    /// Called instead of [`handle`](Middleware::handle) for a request that the router answered
+    /// with `error` before it reached any middleware, e.g. because it was not authorized, exceeded
+    /// a limit, was for an unknown route or its deadline had passed.
+    fn rejected(&mut self, request: &Message, error: &Error) {
+        let _ = (request, error);
+    }
+}
+
+impl<F: FnMut(&Message, Next<'_>) -> Result<Vec<u8>>> Middleware for F {
+    fn handle(&mut self, request: &Message, next: Next<'_>) -> Result<Vec<u8>> {
+        self(request, next)
+    }
+}
+
+/// The remaining middleware and the handler for a request.
+pub struct Next<'a> {
+    middleware: &'a mut [Box<dyn Middleware>],
+    handler: &'a mut RawHandler<'a>,
+}
+
+impl<'a> Next<'a> {
+    pub(crate) fn new(
+        middleware: &'a mut [Box<dyn Middleware>],
+        handler: &'a mut RawHandler<'a>,
+    ) -> Self {
+        Self {
+            middleware,
+            handler,
+        }
+    }
+
+    /// Passes `request` on to the next middleware, or to the handler if there is none left.
+    pub fn run(self, request: &Message) -> Result<Vec<u8>> {
+        match self.middleware.split_first_mut() {
+            Some((middleware, rest)) => middleware.handle(request, Next::new(rest, self.handler)),
+            None => (self.handler)(&request.data),
+        }
+    }
+}
+
+/// Reports how long each request took to handle, including all middleware after this one.
+pub struct Timing<F> {
+    report: F,
+}
+
+impl<F: FnMut(&Message, Duration)> Timing<F> {
+    /// Calls `report` with every request and the time it took to handle it.
+    pub fn new(report: F) -> Self {
+        Self { report }
+    }
+}
+
+impl<F: FnMut(&Message, Duration)> Middleware for Timing<F> {
+    fn handle(&mut self, request: &Message, next: Next<'_>) -> Result<Vec<u8>> {
+        let start = Instant::now();
+        let reply = next.run(request);
+        (self.report)(request, start.elapsed());
+        reply
+    }
+}
+
+/// Logs every request and the size of its reply to standard error.
+pub struct Logging;
+
+impl Middleware for Logging {
+    fn handle(&mut self, request: &Message, next: Next<'_>) -> Result<Vec<u8>> {
+        let Message {
+            service,
+            api_name,
+            data,
+            ..
+        } = request;
+        let reply = next.run(request);
+        match &reply {
+            Ok(reply) => eprintln!(
+                "{service}::{api_name}: {} bytes -> {} bytes",
+                data.len(),
+                reply.len()
+            ),
+            Err(e) => eprintln!("{service}::{api_name}: {} bytes -> error: {e}", data.len()),
+        }
+        reply
+    }
+}
+
+/// Turns a panic in a handler into an error reply, so that the router keeps serving requests.
+pub struct CatchUnwind;
+
+impl Middleware for CatchUnwind {
+    fn handle(&mut self, request: &Message, next: Next<'_>) -> Result<Vec<u8>> {
+        // The router does not observe any state of a handler after it panicked other than by
+        // calling it again, which is the handler's responsibility to cope with.
+        panic::catch_unwind(AssertUnwindSafe(|| next.run(request))).unwrap_or_else(|payload| {
+            Err(format!(
+                "Handler for '{}' panicked: {}",
+                request.api_name,
+                panic_message(&*payload)
+            ))
+        })
+    }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message
+    } else {
+        "<non-string panic payload>"
+    }
+}
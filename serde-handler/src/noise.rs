@@ -0,0 +1,322 @@
+//! Encrypted and mutually authenticated TCP connections, using the
+//! [Noise protocol framework](https://noiseprotocol.org/).
+//!
+//! Each end has a static [`Keypair`], and proves that it holds the private key during the
+//! handshake. Clients [connect](connect_tcp) to a [`Server::noise_tcp`] only if it presents the
+//! public key they expect, and the server can tell clients apart by their
+//! [`remote_public_key`](NoiseStream::remote_public_key). After the handshake, every frame is
+//! encrypted with ChaCha20-Poly1305, so it can neither be read nor changed or replayed by anyone
+//! in between.
+//!
+//! This requires the `noise` feature.
+
+use std::{
+    io::{self, Read, Write},
+    net::{Shutdown, TcpListener, TcpStream, ToSocketAddrs},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use snow::{Builder, HandshakeState, StatelessTransportState};
+
+use crate::{
+    channel::{Message, Requester},
+    transport::{self, Server, ShutdownHandle, Transport},
+    Result,
+};
+
+/// The handshake pattern and algorithms, which both ends have to agree on.
+const PARAMS: &str = "Noise_XX_25519_ChaChaPoly_BLAKE2s";
+
+/// The largest Noise message, which holds at most 16 bytes of authentication tag besides data.
+const MAX_NOISE_MESSAGE: usize = 65535;
+const TAG_LEN: usize = 16;
+
+/// How long each end waits for the other during the handshake.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A static key pair, which identifies one end of a connection.
+#[derive(Clone)]
+pub struct Keypair {
+    private: Vec<u8>,
+    public: Vec<u8>,
+}
+
+impl Keypair {
+    /// Generate a new random key pair.
+    pub fn generate() -> Result<Self> {
+        let keypair = Builder::new(params())
+            .generate_keypair()
+            .map_err(|e| format!("Failed to generate key pair: {e}"))?;
+        Ok(Self {
+            private: keypair.private,
+            public: keypair.public,
+        })
+    }
+
+    /// The public key, which the other end of a connection has to know to trust this end.
+    pub fn public_key(&self) -> &[u8] {
+        &self.public
+    }
+}
+
+impl std::fmt::Debug for Keypair {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Keypair")
+            .field("public", &self.public)
+            .finish_non_exhaustive()
+    }
+}
+
+fn params() -> snow::params::NoiseParams {
+    PARAMS.parse().expect("the Noise parameters are valid")
+}
+
+/// A TCP connection that encrypts every frame, see the [module documentation](self).
+///
+/// Clones made with [`try_clone`](NoiseStream::try_clone) share the encryption state, so one
+/// can read while another writes.
+pub struct NoiseStream {
+    stream: TcpStream,
+    state: Arc<StatelessTransportState>,
+    /// The nonces of the next Noise messages to send and receive.
+    sent: Arc<AtomicU64>,
+    received: Arc<AtomicU64>,
+}
+
+/// Connects a `Requester` to a [`Server::noise_tcp`] listening on `addr`, authenticating as
+/// `keypair`.
+///
+/// Fails unless the server proves that it holds the private key of `server_public_key`.
+pub fn connect_tcp(
+    addr: impl ToSocketAddrs,
+    keypair: &Keypair,
+    server_public_key: &[u8],
+) -> Result<Requester<NoiseStream>> {
+    let stream = TcpStream::connect(addr).map_err(|e| format!("Failed to connect: {e}"))?;
+    stream
+        .set_nodelay(true)
+        .map_err(|e| format!("Failed to configure socket: {e}"))?;
+    let stream = NoiseStream::initiate(stream, keypair)
+        .map_err(|e| format!("Noise handshake failed: {e}"))?;
+    if stream.remote_public_key() != server_public_key {
+        return Err("The server presented an unexpected public key".to_string());
+    }
+    Ok(Requester::new(stream))
+}
+
+impl Server<NoiseStream> {
+    /// Serve all clients that connect to `listener` and complete a Noise handshake, presenting
+    /// `keypair` to them.
+    ///
+    /// Any client with a key pair may connect, so requests still need to be authenticated, e.g.
+    /// with [`Tokens`](crate::auth::Tokens), if not everyone may make them. Handshakes are done
+    /// on the thread of each connection, and give up after 10 seconds.
+    pub fn noise_tcp(listener: TcpListener, keypair: Keypair) -> Self {
+        let addr = listener.local_addr();
+        let wake = move || {
+            if let Ok(addr) = addr {
+                let _ = TcpStream::connect(addr);
+            }
+        };
+        Self::accept_with(
+            wake,
+            move || listener.accept().map(|(stream, _)| stream),
+            move |stream: TcpStream| {
+                stream.set_nodelay(true)?;
+                let stream = NoiseStream::respond(stream, &keypair)?;
+                Ok((stream.try_clone()?, stream))
+            },
+        )
+    }
+}
+
+impl NoiseStream {
+    /// Performs the handshake as the client.
+    fn initiate(stream: TcpStream, keypair: &Keypair) -> io::Result<Self> {
+        let mut handshake = Builder::new(params())
+            .local_private_key(&keypair.private)
+            .build_initiator()
+            .map_err(invalid_data)?;
+        stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+        // -> e
+        write_handshake(&stream, &mut handshake)?;
+        // <- e, ee, s, es
+        read_handshake(&stream, &mut handshake)?;
+        // -> s, se
+        write_handshake(&stream, &mut handshake)?;
+        stream.set_read_timeout(None)?;
+        Self::new(stream, handshake)
+    }
+
+    /// Performs the handshake as the server.
+    fn respond(stream: TcpStream, keypair: &Keypair) -> io::Result<Self> {
+        let mut handshake = Builder::new(params())
+            .local_private_key(&keypair.private)
+            .build_responder()
+            .map_err(invalid_data)?;
+        stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+        read_handshake(&stream, &mut handshake)?;
+        write_handshake(&stream, &mut handshake)?;
+        read_handshake(&stream, &mut handshake)?;
+        stream.set_read_timeout(None)?;
+        Self::new(stream, handshake)
+    }
+
+    fn new(stream: TcpStream, handshake: HandshakeState) -> io::Result<Self> {
+        let state = handshake
+            .into_stateless_transport_mode()
+            .map_err(invalid_data)?;
+        Ok(Self {
+            stream,
+            state: Arc::new(state),
+            sent: Arc::default(),
+            received: Arc::default(),
+        })
+    }
+
+    /// Returns a handle to the same connection.
+    pub fn try_clone(&self) -> io::Result<Self> {
+        Ok(Self {
+            stream: self.stream.try_clone()?,
+            state: Arc::clone(&self.state),
+            sent: Arc::clone(&self.sent),
+            received: Arc::clone(&self.received),
+        })
+    }
+
+    /// The static public key the other end authenticated with.
+    pub fn remote_public_key(&self) -> &[u8] {
+        self.state
+            .get_remote_static()
+            .expect("the XX handshake transmits both static keys")
+    }
+
+    /// Writes `message` as a single frame.
+    ///
+    /// A frame is the big-endian `u32` length of the encrypted message, followed by the
+    /// [encoded](Message::encode) message encrypted in Noise messages of at most 64 KiB.
+    fn write_frame(&self, message: &Message) -> io::Result<()> {
+        let body = message.encode();
+        let mut frame = vec![0; 4];
+        let mut ciphertext = vec![0; MAX_NOISE_MESSAGE];
+        for chunk in body.chunks(MAX_NOISE_MESSAGE - TAG_LEN) {
+            let nonce = self.sent.fetch_add(1, Ordering::Relaxed);
+            let len = self
+                .state
+                .write_message(nonce, chunk, &mut ciphertext)
+                .map_err(invalid_data)?;
+            frame.extend_from_slice(&ciphertext[..len]);
+        }
+        let len = u32::try_from(frame.len() - 4).map_err(|_| {
+            io::Error::new(io::ErrorKind::InvalidInput, "message too large to frame")
+        })?;
+        frame[..4].copy_from_slice(&len.to_be_bytes());
+        (&self.stream).write_all(&frame)?;
+        (&self.stream).flush()
+    }
+
+    /// Reads a single frame written by [`write_frame`](NoiseStream::write_frame) from `reader`,
+    /// failing without reading the message if it is longer than `max_len` bytes.
+    ///
+    /// Returns `None` if `reader` ends cleanly before the frame, i.e. the writer hung up.
+    fn read_frame(&self, mut reader: impl Read, max_len: usize) -> io::Result<Option<Message>> {
+        // Every Noise message adds its tag to at most `MAX_NOISE_MESSAGE - TAG_LEN` bytes.
+        let max_ciphertext_len = max_len
+            .div_ceil(MAX_NOISE_MESSAGE - TAG_LEN)
+            .saturating_mul(TAG_LEN)
+            .saturating_add(max_len);
+        let Some(mut remaining) = transport::read_frame_len(&mut reader, max_ciphertext_len)?
+        else {
+            return Ok(None);
+        };
+        // Each Noise message is authenticated before the next is read, so the body only grows
+        // with data that the other end actually sent.
+        let mut body = Vec::new();
+        let mut ciphertext = vec![0; MAX_NOISE_MESSAGE];
+        let mut plaintext = vec![0; MAX_NOISE_MESSAGE];
+        while remaining > 0 {
+            let chunk = &mut ciphertext[..remaining.min(MAX_NOISE_MESSAGE)];
+            reader.read_exact(chunk)?;
+            remaining -= chunk.len();
+            let nonce = self.received.fetch_add(1, Ordering::Relaxed);
+            let len = self
+                .state
+                .read_message(nonce, chunk, &mut plaintext)
+                .map_err(invalid_data)?;
+            body.extend_from_slice(&plaintext[..len]);
+        }
+        Message::decode(&body).map(Some).map_err(invalid_data)
+    }
+}
+
+impl Transport for NoiseStream {
+    fn send(&self, message: Message) -> Result<()> {
+        self.write_frame(&message).map_err(|e| e.to_string())
+    }
+
+    fn recv(&self) -> Result<Message> {
+        let message = self
+            .read_frame(&self.stream, usize::MAX)
+            .map_err(|e| e.to_string())?;
+        Ok(message.unwrap_or_else(Message::shutdown))
+    }
+
+    fn recv_timeout(&self, timeout: Duration) -> Result<Option<Message>> {
+        self.recv_limited(Some(timeout), usize::MAX)
+    }
+
+    fn recv_limited(&self, timeout: Option<Duration>, max_len: usize) -> Result<Option<Message>> {
+        let Some(timeout) = timeout else {
+            let message = self
+                .read_frame(&self.stream, max_len)
+                .map_err(|e| e.to_string())?;
+            return Ok(Some(message.unwrap_or_else(Message::shutdown)));
+        };
+        transport::recv_stream_timeout(
+            &self.stream,
+            timeout,
+            TcpStream::set_read_timeout,
+            |frame| self.read_frame(frame, max_len),
+        )
+    }
+
+    fn shutdown_handle(&self) -> Result<ShutdownHandle> {
+        let stream = self.stream.try_clone().map_err(|e| e.to_string())?;
+        Ok(ShutdownHandle::new(move || {
+            stream.shutdown(Shutdown::Read).map_err(|e| e.to_string())
+        }))
+    }
+}
+
+/// Writes the next handshake message, prefixed with its big-endian `u16` length.
+fn write_handshake(mut stream: &TcpStream, handshake: &mut HandshakeState) -> io::Result<()> {
+    let mut message = vec![0; MAX_NOISE_MESSAGE];
+    let len = handshake
+        .write_message(&[], &mut message)
+        .map_err(invalid_data)?;
+    let mut frame = (len as u16).to_be_bytes().to_vec();
+    frame.extend_from_slice(&message[..len]);
+    stream.write_all(&frame)?;
+    stream.flush()
+}
+
+/// Reads the next handshake message written by [`write_handshake`].
+fn read_handshake(mut stream: &TcpStream, handshake: &mut HandshakeState) -> io::Result<()> {
+    let mut len = [0; 2];
+    stream.read_exact(&mut len)?;
+    let mut message = vec![0; u16::from_be_bytes(len) as usize];
+    stream.read_exact(&mut message)?;
+    let mut payload = vec![0; MAX_NOISE_MESSAGE];
+    handshake
+        .read_message(&message, &mut payload)
+        .map_err(invalid_data)?;
+    Ok(())
+}
+
+fn invalid_data(error: impl ToString) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error.to_string())
+}
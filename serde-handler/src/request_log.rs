@@ -0,0 +1,328 @@
+//! Recording the requests a router handles, and replaying them to reproduce its replies.
+//!
+//! The [`RequestLog`] middleware appends every request it sees to a file, together with the
+//! time it arrived, how long it took to handle and its reply or error. [`replay`] hands the
+//! requests of such a log to a router again, e.g. one running a fixed version of the handlers,
+//! and reports every reply that differs from the recorded one. The `replay` example of this crate
+//! is a command line tool that records and replays the requests of a service this way.
+//!
+//! A log is a sequence of records, each the big-endian `u32` length of the record followed by:
+//!
+//! - the format version, currently 1, as one byte
+//! - the time the request arrived, in microseconds since the Unix epoch, as a big-endian `u64`
+//! - the time it took to handle, in microseconds, as a big-endian `u64`
+//! - 0 if the request was answered with a reply, or 1 if it failed, as one byte
+//! - the [encoded](Message::encode) request, prefixed with its big-endian `u32` length
+//! - the reply data, or the UTF-8 error message, prefixed with its big-endian `u32` length
+
+use std::{
+    fmt,
+    fs::{self, File, OpenOptions},
+    io::Write,
+    path::Path,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    api::ApiRouter,
+    channel::{self, response_error, Error, Kind, Message},
+    headers::{AUTHORIZATION, KEY_ID, SIGNATURE},
+    middleware::{Middleware, Next},
+    Result,
+};
+
+const FORMAT_VERSION: u8 = 1;
+
+/// The headers that carry the credentials of a request, which are not logged.
+const CREDENTIALS: [&str; 3] = [AUTHORIZATION, KEY_ID, SIGNATURE];
+
+/// A request that a [`RequestLog`] recorded.
+#[derive(Debug, Clone)]
+pub struct Entry {
+    /// When the request arrived at the middleware.
+    pub timestamp: SystemTime,
+    /// How long the request took to handle, including all middleware after the log.
+    pub duration: Duration,
+    /// The request, including its route and headers.
+    pub request: Message,
+    /// The reply data, or the error the request failed with.
+    pub reply: Result<Vec<u8>>,
+}
+
+impl Entry {
+    fn encode(&self) -> Vec<u8> {
+        let micros = |duration: Duration| u64::try_from(duration.as_micros()).unwrap_or(u64::MAX);
+        let since_epoch = self
+            .timestamp
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let mut body = vec![FORMAT_VERSION];
+        body.extend_from_slice(&micros(since_epoch).to_be_bytes());
+        body.extend_from_slice(&micros(self.duration).to_be_bytes());
+        let (failed, reply) = match &self.reply {
+            Ok(data) => (0, data.as_slice()),
+            Err(e) => (1, e.as_bytes()),
+        };
+        body.push(failed);
+        channel::put_field(&mut body, &self.request.encode());
+        channel::put_field(&mut body, reply);
+
+        let mut record = Vec::with_capacity(4 + body.len());
+        channel::put_field(&mut record, &body);
+        record
+    }
+
+    fn decode(mut body: &[u8]) -> Result<Self> {
+        let Some((&FORMAT_VERSION, rest)) = body.split_first() else {
+            return Err("Unsupported record format".to_string());
+        };
+        let Some((timestamp, rest)) = rest.split_first_chunk::<8>() else {
+            return Err("Truncated record: missing timestamp".to_string());
+        };
+        let Some((duration, rest)) = rest.split_first_chunk::<8>() else {
+            return Err("Truncated record: missing duration".to_string());
+        };
+        let Some((&failed, rest)) = rest.split_first() else {
+            return Err("Truncated record: missing status".to_string());
+        };
+        body = rest;
+        let request = Message::decode(channel::take_field(&mut body)?)?;
+        let reply = channel::take_field(&mut body)?.to_vec();
+        let reply = match failed {
+            0 => Ok(reply),
+            1 => Err(String::from_utf8(reply).map_err(|e| format!("Invalid error: {e}"))?),
+            _ => return Err(format!("Invalid record status {failed}")),
+        };
+        Ok(Self {
+            timestamp: UNIX_EPOCH + Duration::from_micros(u64::from_be_bytes(*timestamp)),
+            duration: Duration::from_micros(u64::from_be_bytes(*duration)),
+            request,
+            reply,
+        })
+    }
+}
+
+/// Appends every request and its reply or error to a file, see the
+/// [module documentation](self).
+///
+/// Requests are logged as the middleware sees them, i.e. after decompression and after the
+/// middleware before it. Each request of a [`Kind::Batch`] is logged on its own. Requests for
+/// streaming APIs are logged with an empty reply, as the middleware only runs around starting the
+/// stream.
+///
+/// Requests are logged without the credentials in their [`AUTHORIZATION`], [`KEY_ID`] and
+/// [`SIGNATURE`] headers, so that the log cannot be used to make requests in someone else's name.
+/// The [`PRINCIPAL`](crate::headers::PRINCIPAL) a request was authenticated as is kept.
+///
+/// Requests that the router answers before they reach its middleware are logged with their error
+/// and no duration. These are requests that are
+/// [not authorized](crate::api::ApiRouter::authorize), exceed the
+/// [limits](crate::api::ApiRouter::limit) of their API, ask for an unknown service, API or
+/// version, or whose deadline passed before they were handled.
+///
+/// Failing to write to the file does not fail the request, but is logged with the `tracing`
+/// feature.
+pub struct RequestLog {
+    file: File,
+}
+
+impl RequestLog {
+    /// Appends to the log at `path`, creating it if it does not exist.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| format!("Failed to open request log: {e}"))?;
+        Ok(Self { file })
+    }
+
+    /// Reads all entries of the log at `path`, oldest first.
+    pub fn read(path: impl AsRef<Path>) -> Result<Vec<Entry>> {
+        let bytes = fs::read(path).map_err(|e| format!("Failed to read request log: {e}"))?;
+        let mut bytes = bytes.as_slice();
+        let mut entries = Vec::new();
+        while !bytes.is_empty() {
+            let record =
+                channel::take_field(&mut bytes).map_err(|e| format!("Invalid request log: {e}"))?;
+            entries.push(Entry::decode(record).map_err(|e| format!("Invalid request log: {e}"))?);
+        }
+        Ok(entries)
+    }
+}
+
+impl RequestLog {
+    /// Appends `entry` to the log without the credentials of its request, returning its reply.
+    fn append(&mut self, mut entry: Entry) -> Result<Vec<u8>> {
+        for name in CREDENTIALS {
+            entry.request.headers.remove(name);
+        }
+        // Each record is written at once, so that records of several routers appending to the
+        // same file do not interleave.
+        if let Err(_e) = self.file.write_all(&entry.encode()) {
+            #[cfg(feature = "tracing")]
+            tracing::warn!(error = %_e, "Failed to append to request log");
+        }
+        entry.reply
+    }
+}
+
+impl Middleware for RequestLog {
+    fn handle(&mut self, request: &Message, next: Next<'_>) -> Result<Vec<u8>> {
+        let timestamp = SystemTime::now();
+        let start = Instant::now();
+        let reply = next.run(request);
+        self.append(Entry {
+            timestamp,
+            duration: start.elapsed(),
+            request: request.clone(),
+            reply,
+        })
+    }
+
+    fn rejected(&mut self, request: &Message, error: &Error) {
+        let _ = self.append(Entry {
+            timestamp: SystemTime::now(),
+            duration: Duration::ZERO,
+            request: request.clone(),
+            reply: Err(error.to_string()),
+        });
+    }
+}
+
+/// A replayed request whose reply differs from the recorded one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mismatch {
+    /// The position of the request in the replayed entries.
+    pub index: usize,
+    pub service: String,
+    pub api_name: String,
+    pub recorded: Result<Vec<u8>>,
+    pub replayed: Result<Vec<u8>>,
+}
+
+impl Mismatch {
+    /// Describes how the replies differ, one line per difference.
+    ///
+    /// Replies are compared as JSON, naming the path of each differing value, e.g.
+    /// `/items/0/name: "a" != "b"`. Errors and replies that are not JSON are compared as a
+    /// whole.
+    pub fn differences(&self) -> Vec<String> {
+        let mut differences = Vec::new();
+        match (&self.recorded, &self.replayed) {
+            (Ok(recorded), Ok(replayed)) => match (
+                serde_json::from_slice(recorded),
+                serde_json::from_slice(replayed),
+            ) {
+                (Ok(recorded), Ok(replayed)) => {
+                    diff_json(String::new(), &recorded, &replayed, &mut differences)
+                }
+                _ => differences.push(format!(
+                    "{} != {}",
+                    String::from_utf8_lossy(recorded),
+                    String::from_utf8_lossy(replayed)
+                )),
+            },
+            (Ok(recorded), Err(e)) => differences.push(format!(
+                "{} != error: {e}",
+                String::from_utf8_lossy(recorded)
+            )),
+            (Err(e), Ok(replayed)) => differences.push(format!(
+                "error: {e} != {}",
+                String::from_utf8_lossy(replayed)
+            )),
+            (Err(recorded), Err(replayed)) => {
+                differences.push(format!("error: {recorded} != error: {replayed}"))
+            }
+        }
+        differences
+    }
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Request {} for '{}' in service '{}' was answered differently:",
+            self.index, self.api_name, self.service
+        )?;
+        for difference in self.differences() {
+            write!(f, "\n  {difference}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Collects the differences between `a` and `b`, found at `path`, in `differences`.
+fn diff_json(
+    path: String,
+    a: &serde_json::Value,
+    b: &serde_json::Value,
+    differences: &mut Vec<String>,
+) {
+    use serde_json::Value;
+
+    match (a, b) {
+        (Value::Object(a), Value::Object(b)) => {
+            let mut keys: Vec<_> = a.keys().chain(b.keys()).collect();
+            keys.sort_unstable();
+            keys.dedup();
+            for key in keys {
+                let path = format!("{path}/{key}");
+                match (a.get(key), b.get(key)) {
+                    (Some(a), Some(b)) => diff_json(path, a, b, differences),
+                    (Some(a), None) => differences.push(format!("{path}: {a} != missing")),
+                    (None, Some(b)) => differences.push(format!("{path}: missing != {b}")),
+                    (None, None) => unreachable!("keys are taken from both objects"),
+                }
+            }
+        }
+        (Value::Array(a), Value::Array(b)) if a.len() == b.len() => {
+            for (i, (a, b)) in a.iter().zip(b).enumerate() {
+                diff_json(format!("{path}/{i}"), a, b, differences);
+            }
+        }
+        (a, b) if a != b => {
+            let path = if path.is_empty() { "/" } else { &path };
+            differences.push(format!("{path}: {a} != {b}"));
+        }
+        _ => {}
+    }
+}
+
+/// Handles the requests of `entries` with `router` again, in order, and returns the ones whose
+/// reply or error differs from the recorded one.
+///
+/// Requests are handled with their recorded headers, so that they are authorized as the same
+/// principal, but without their deadline, unless they failed because it had passed. The
+/// middleware of `router` runs as usual, so `router` should not log to the log that is replayed.
+/// Requests for streaming APIs are skipped.
+pub fn replay<S: 'static>(router: &mut ApiRouter<S>, entries: &[Entry]) -> Vec<Mismatch> {
+    let mut mismatches = Vec::new();
+    for (index, entry) in entries.iter().enumerate() {
+        if entry.request.kind != Kind::Request {
+            continue;
+        }
+        // Requests that were rejected for their deadline are rejected again.
+        let expired = entry.reply == Err(Error::DeadlineExceeded.to_string());
+        let request = Message {
+            deadline: entry.request.deadline.filter(|_| expired),
+            ..entry.request.clone()
+        };
+        let response = router.handle_single(&request);
+        let replayed = match response.kind {
+            Kind::Reply => Ok(response.data),
+            _ => Err(response_error(&response).to_string()),
+        };
+        if replayed != entry.reply {
+            mismatches.push(Mismatch {
+                index,
+                service: request.service,
+                api_name: request.api_name,
+                recorded: entry.reply.clone(),
+                replayed,
+            });
+        }
+    }
+    mismatches
+}
@@ -0,0 +1,74 @@
+//! Retrying requests that failed for transient reasons.
+//!
+//! Only requests for APIs that are marked [`IDEMPOTENT`](crate::api::Api::IDEMPOTENT) are retried
+//! automatically (see [`Requester::with_retry`](crate::channel::Requester::with_retry)), since
+//! a request that failed may still have been handled. Requests for other APIs carry a
+//! [`REQUEST_ID`](crate::headers::REQUEST_ID), so that an
+//! [`ApiRouter`](crate::api::ApiRouter) does not handle them twice if they are sent again.
+
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    time::Duration,
+};
+
+/// How often and how long apart to send a request that failed with a
+/// [transient](crate::channel::Error::is_transient) error.
+///
+/// The delay before the `n`th retry is `initial_backoff * 2^(n - 1)`, at most `max_backoff`. With
+/// jitter, which is the default, a random delay of up to half of that is subtracted, so that
+/// requesters that failed at the same time do not all retry at the same time.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    jitter: bool,
+}
+
+impl RetryPolicy {
+    /// Send a request at most `max_attempts` times, including the first attempt, waiting 50ms
+    /// before the first retry and at most 5s before any retry.
+    pub fn new(max_attempts: u32) -> Self {
+        Self {
+            max_attempts,
+            initial_backoff: Duration::from_millis(50),
+            max_backoff: Duration::from_secs(5),
+            jitter: true,
+        }
+    }
+
+    /// Wait `initial` before the first retry, doubling for every further retry up to `max`.
+    pub fn backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_backoff = initial;
+        self.max_backoff = max;
+        self
+    }
+
+    /// Always wait the full backoff.
+    pub fn without_jitter(mut self) -> Self {
+        self.jitter = false;
+        self
+    }
+
+    pub fn max_attempts(&self) -> u32 {
+        self.max_attempts
+    }
+
+    /// How long to wait before sending the request for the `attempt`th time, where the first
+    /// retry is the second attempt.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let doublings = attempt.saturating_sub(2).min(31);
+        let backoff = self
+            .initial_backoff
+            .saturating_mul(1 << doublings)
+            .min(self.max_backoff);
+        if !self.jitter {
+            return backoff;
+        }
+        // A random fraction in [0, 1), from the random keys of a new `RandomState`.
+        let random = RandomState::new().build_hasher().finish() >> 11;
+        let fraction = random as f64 / (1u64 << 53) as f64;
+        backoff - backoff.mul_f64(fraction / 2.0)
+    }
+}
@@ -0,0 +1,324 @@
+//! APIs that answer a request with a stream of replies, such as subscriptions.
+//!
+//! Requests for streaming APIs are sent as [`Kind::StreamRequest`] messages. The service sends
+//! each reply as a [`Kind::Item`] message and ends the stream with a
+//! [`Kind::End`] message, or with a [`Kind::Error`] message if producing a reply failed. The
+//! requester can stop the stream early with a [`Kind::Cancel`] message, which
+//! [`ReplyStream`] sends when it is dropped.
+//!
+//! A router produces the replies of each stream on a thread of its own, and goes on answering
+//! other requests in the meantime. Items are sent one at a time over the same bounded transport as
+//! all other messages, and the next one is only produced once the last one was sent, so a service
+//! can only get ahead of a slow requester by as many items as the transport buffers.
+
+use std::{
+    marker::PhantomData,
+    panic::{self, AssertUnwindSafe},
+    sync::mpsc::{self, Receiver, Sender},
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use serde::de::DeserializeOwned;
+
+use crate::{
+    api::Api,
+    channel::{response_error, Error, Kind, Message, Requester, Responder},
+    describe::ApiSchema,
+    headers,
+    limit::Permit,
+    transport::{PeerId, Transport},
+    Result,
+};
+
+/// An [`Api`] that is answered with any number of [`Reply`](Api::Reply)s.
+///
+/// The replies cannot borrow from the request, so handlers produce `A::Reply<'static>`.
+///
+/// Handlers for streaming APIs are registered with
+/// [`ApiRouter::register_stream_handler`](crate::api::ApiRouter::register_stream_handler)
+/// and requested with [`Requester::request_stream`]. The [`TIMEOUT`](Api::TIMEOUT) applies to
+/// the stream as a whole.
+pub trait StreamingApi: Api {}
+
+/// A function that can handle [`A::Request<'de>`](Api::Request) for `'de == 'req` with a
+/// stream of replies.
+pub trait StreamHandlerOn<'req, A: StreamingApi, S>: FnMut(A::Request<'req>) -> S {}
+impl<'req, A: StreamingApi, S, F: FnMut(A::Request<'req>) -> S> StreamHandlerOn<'req, A, S> for F {}
+
+/// A function that can handle [`A::Request<'de>`](Api::Request) for any `'de` with a stream `S`
+/// of replies.
+///
+/// The stream cannot borrow from the request, as it is consumed after the request has been
+/// handed to the handler, on another thread.
+pub trait StreamHandler<A: StreamingApi, S>: for<'req> StreamHandlerOn<'req, A, S> {}
+impl<A: StreamingApi, S, F: for<'req> StreamHandlerOn<'req, A, S>> StreamHandler<A, S> for F {}
+
+/// A stream of serialized replies.
+pub(crate) type BoxedStream = Box<dyn Iterator<Item = Result<Vec<u8>>> + Send>;
+
+type BoxedRequestStreamHandler = Box<dyn FnMut(&[u8]) -> Result<BoxedStream>>;
+pub(crate) struct BoxedStreamHandler {
+    pub(crate) handle: BoxedRequestStreamHandler,
+    pub(crate) schema: Option<ApiSchema>,
+}
+
+impl BoxedStreamHandler {
+    pub(crate) fn from_handler<A, S, H>(mut handler: H) -> Self
+    where
+        A: StreamingApi,
+        S: IntoIterator<Item = A::Reply<'static>, IntoIter: Send + 'static>,
+        H: StreamHandler<A, S> + 'static,
+    {
+        let handler = move |request_data: &[u8]| -> Result<BoxedStream> {
+            let request: A::Request<'_> = serde_json::from_slice(request_data)
+                .map_err(|e| format!("Deserialize error: {e}"))?;
+            let stream = handler(request).into_iter().map(|reply| {
+                serde_json::to_vec(&reply).map_err(|e| format!("Serialize error: {e}"))
+            });
+            Ok(Box::new(stream) as BoxedStream)
+        };
+        Self {
+            handle: Box::new(handler),
+            schema: A::schema(),
+        }
+    }
+}
+
+/// The next reply of the stream with the given key, or `None` once it has ended.
+type Produced = (u64, Option<Result<Vec<u8>>>);
+
+/// The streams of replies that a router is sending, each produced on a thread of its own.
+pub(crate) struct Streams {
+    active: Vec<ActiveStream>,
+    next_key: u64,
+    produced: (Sender<Produced>, Receiver<Produced>),
+}
+
+struct ActiveStream {
+    key: u64,
+    peer: Option<PeerId>,
+    request: Message,
+    /// Asks the thread of the stream for its next reply.
+    pull: Sender<()>,
+    _permit: Option<Permit>,
+}
+
+impl Streams {
+    pub(crate) fn new() -> Self {
+        Self {
+            active: Vec::new(),
+            next_key: 0,
+            produced: mpsc::channel(),
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.active.is_empty()
+    }
+
+    /// Starts producing the replies to `request`, which `peer` sent, from `stream` on another
+    /// thread. The `permit` is held until the stream ends.
+    pub(crate) fn start(
+        &mut self,
+        peer: Option<PeerId>,
+        request: &Message,
+        mut stream: BoxedStream,
+        permit: Option<Permit>,
+    ) {
+        let key = self.next_key;
+        self.next_key += 1;
+        let (pull, pulled) = mpsc::channel();
+        let produced = self.produced.0.clone();
+        let request_headers = request.headers.clone();
+        thread::spawn(move || {
+            // Requests made while producing replies carry on the correlation ID of the request.
+            let _correlation = headers::enter(&request_headers);
+            loop {
+                let item = panic::catch_unwind(AssertUnwindSafe(|| stream.next()))
+                    .unwrap_or_else(|_| Some(Err("The stream of replies panicked".to_string())));
+                let end = !matches!(item, Some(Ok(_)));
+                // The router drops a stream once it is cancelled, or once its requester is gone.
+                if produced.send((key, item)).is_err() || end || pulled.recv().is_err() {
+                    break;
+                }
+            }
+        });
+        self.active.push(ActiveStream {
+            key,
+            peer,
+            request: request.clone(),
+            pull,
+            _permit: permit,
+        });
+    }
+
+    /// Stops the stream that answers the request with `id` from `peer`, if it is still going,
+    /// and acknowledges that with a [`Kind::End`] message.
+    ///
+    /// A reply that is being produced at this point is dropped once it is done.
+    pub(crate) fn cancel<T: Transport>(
+        &mut self,
+        socket: &Responder<T>,
+        peer: Option<PeerId>,
+        id: u64,
+    ) {
+        let position = self
+            .active
+            .iter()
+            .position(|stream| stream.peer == peer && stream.request.id == id);
+        if let Some(position) = position {
+            let stream = self.active.swap_remove(position);
+            socket.set_current_peer(stream.peer);
+            socket.send_reply(stream.request.end());
+        }
+    }
+
+    /// Waits up to `timeout` for the streams to produce replies, and sends those that are ready
+    /// to their requesters. Streams whose deadline has passed end with
+    /// [`Error::DeadlineExceeded`].
+    ///
+    /// Afterwards, replies may go to any requester.
+    pub(crate) fn forward<T: Transport>(&mut self, socket: &Responder<T>, timeout: Duration) {
+        self.active.retain(|stream| {
+            if !stream.request.is_expired() {
+                return true;
+            }
+            socket.set_current_peer(stream.peer);
+            socket.send_reply(stream.request.error(Error::DeadlineExceeded));
+            false
+        });
+        let (_, produced) = &self.produced;
+        let first = produced.recv_timeout(timeout).ok();
+        // Every stream has at most one reply on its way.
+        let ready: Vec<_> = first
+            .into_iter()
+            .chain(produced.try_iter().take(self.active.len()))
+            .collect();
+        for (key, item) in ready {
+            // Replies of cancelled streams are dropped.
+            let Some(position) = self.active.iter().position(|stream| stream.key == key) else {
+                continue;
+            };
+            let stream = &self.active[position];
+            socket.set_current_peer(stream.peer);
+            let going = match item {
+                Some(Ok(data)) => {
+                    socket.send_reply(stream.request.item(data)) && stream.pull.send(()).is_ok()
+                }
+                Some(Err(e)) => {
+                    socket.send_reply(stream.request.error(e));
+                    false
+                }
+                None => {
+                    socket.send_reply(stream.request.end());
+                    false
+                }
+            };
+            if !going {
+                self.active.swap_remove(position);
+            }
+        }
+    }
+}
+
+/// The replies to a request for a [`StreamingApi`], in the order the service sent them.
+///
+/// The stream ends after the service's last reply, or after the first error. Dropping it before
+/// then cancels the stream and waits for the service to acknowledge that.
+pub struct ReplyStream<'r, A, T: Transport> {
+    requester: &'r Requester<T>,
+    id: u64,
+    deadline: Option<SystemTime>,
+    done: bool,
+    api: PhantomData<fn() -> A>,
+}
+
+impl<A: StreamingApi, T: Transport> Iterator for ReplyStream<'_, A, T>
+where
+    A::Reply<'static>: DeserializeOwned,
+{
+    type Item = Result<A::Reply<'static>, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let message = match self.requester.recv_response(self.id, self.deadline) {
+            Ok(message) => message,
+            Err(e) => {
+                self.done = true;
+                return Some(Err(e));
+            }
+        };
+        let item = match message.kind {
+            Kind::Item => serde_json::from_slice(&message.data)
+                .map_err(|e| Error::Other(format!("Deserialize error: {e}"))),
+            Kind::End => {
+                self.done = true;
+                return None;
+            }
+            Kind::Error => Err(response_error(&message)),
+            Kind::Shutdown => Err(Error::Other("The service has shut down".to_string())),
+            kind => Err(Error::Other(format!(
+                "Expected a stream of replies, but received {kind:?} for '{}'",
+                message.api_name
+            ))),
+        };
+        self.done = item.is_err();
+        Some(item)
+    }
+}
+
+impl<A, T: Transport> Drop for ReplyStream<'_, A, T> {
+    fn drop(&mut self) {
+        if self.done {
+            return;
+        }
+        let cancel = Message {
+            kind: Kind::Cancel,
+            id: self.id,
+            ..Default::default()
+        };
+        if self.requester.send(cancel).is_err() {
+            return;
+        }
+        // Items sent before the service noticed the cancellation are still on their way, and
+        // the service may be blocked until they are received.
+        while let Ok(message) = self.requester.recv_response(self.id, self.deadline) {
+            if matches!(message.kind, Kind::End | Kind::Error | Kind::Shutdown) {
+                break;
+            }
+        }
+    }
+}
+
+impl<T: Transport> Requester<T> {
+    /// Sends `request` to its service and returns an iterator over the replies.
+    ///
+    /// The iterator blocks until the next reply arrives. If [`A::TIMEOUT`](Api::TIMEOUT) is
+    /// set, it gives up with [`Error::Timeout`] once that has passed since the request was sent.
+    pub fn request_stream<'a, A: StreamingApi + Api<Request<'a> = A>>(
+        &self,
+        request: A,
+    ) -> Result<ReplyStream<'_, A, T>, Error> {
+        let deadline = A::TIMEOUT.map(|timeout| SystemTime::now() + timeout);
+        let data = serde_json::to_vec(&request).map_err(|e| format!("Serialize error: {e}"))?;
+        let id = self.start_request(Message {
+            kind: Kind::StreamRequest,
+            version: A::VERSION,
+            deadline,
+            service: A::SERVICE.to_string(),
+            api_name: A::NAME.to_string(),
+            data,
+            ..Default::default()
+        })?;
+        Ok(ReplyStream {
+            requester: self,
+            id,
+            deadline,
+            done: false,
+            api: PhantomData,
+        })
+    }
+}
@@ -0,0 +1,482 @@
+//! Utilities for testing code that makes requests, without running a real service.
+//!
+//! A [`MockService`] answers requests in-process with the replies that a test set up for each
+//! [`Api`], and checks how often each API was requested. A [`Recorder`] captures the requests and
+//! replies exchanged with a real service in a file, which a [`Replayer`] answers the same requests
+//! from later on.
+
+use std::{
+    cell::RefCell,
+    collections::{HashMap, VecDeque},
+    fs::{self, File},
+    io::Write,
+    marker::PhantomData,
+    path::Path,
+    rc::Rc,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+use crate::{
+    api::{Api, Handler},
+    channel::{Error, Kind, Message, Requester},
+    transport::{ShutdownHandle, Transport},
+    Result,
+};
+
+/// A service that answers requests with replies set up by a test.
+///
+/// Set up the replies for each API with [`expect`](MockService::expect), and send requests
+/// through the [`requester`](MockService::requester). Requests are answered as soon as they are
+/// sent, on the same thread.
+///
+/// Dropping the service [verifies](MockService::verify) that every expectation was met.
+pub struct MockService {
+    mock: Rc<RefCell<Mock>>,
+}
+
+#[derive(Default)]
+struct Mock {
+    expectations: Vec<Expectation>,
+    /// Descriptions of requests that did not match any expectation.
+    unexpected: Vec<String>,
+}
+
+type MockReply = Box<dyn FnMut(&[u8]) -> Result<Vec<u8>, Error>>;
+
+struct Expectation {
+    service: &'static str,
+    api_name: &'static str,
+    version: u32,
+    reply: MockReply,
+    times: Option<usize>,
+    calls: usize,
+}
+
+impl Expectation {
+    fn matches(&self, request: &Message) -> bool {
+        self.service == request.service
+            && self.api_name == request.api_name
+            && (request.version == 0 || request.version == self.version)
+    }
+
+    fn is_saturated(&self) -> bool {
+        self.times.is_some_and(|times| self.calls >= times)
+    }
+}
+
+impl Mock {
+    fn handle(&mut self, request: &Message) -> Message {
+        let Message {
+            service, api_name, ..
+        } = request;
+        let mut matching = self
+            .expectations
+            .iter_mut()
+            .filter(|expectation| expectation.matches(request))
+            .peekable();
+        if matching.peek().is_none() {
+            let error = format!("Unexpected request for '{api_name}' in service '{service}'");
+            self.unexpected.push(error.clone());
+            return request.error(error);
+        }
+        let Some(expectation) = matching.find(|expectation| !expectation.is_saturated()) else {
+            let error = format!(
+                "'{api_name}' in service '{service}' was requested more often than expected"
+            );
+            self.unexpected.push(error.clone());
+            return request.error(error);
+        };
+        expectation.calls += 1;
+        match (expectation.reply)(&request.data) {
+            Ok(reply) => request.reply(reply),
+            Err(e) => request.error(e),
+        }
+    }
+}
+
+impl MockService {
+    pub fn new() -> Self {
+        Self {
+            mock: Rc::default(),
+        }
+    }
+
+    /// Expects requests for `A`, which are answered with an error until a reply is set up with
+    /// [`returning`](Expect::returning).
+    ///
+    /// A request is handled by the first expectation for its API that has not been requested
+    /// as many [`times`](Expect::times) as expected yet.
+    pub fn expect<A: Api>(&self) -> Expect<'_, A> {
+        let reply = |_: &[u8]| {
+            Err(Error::Other(format!(
+                "No reply was set up for '{}' in service '{}'",
+                A::NAME,
+                A::SERVICE
+            )))
+        };
+        let mut mock = self.mock.borrow_mut();
+        mock.expectations.push(Expectation {
+            service: A::SERVICE,
+            api_name: A::NAME,
+            version: A::VERSION,
+            reply: Box::new(reply),
+            times: None,
+            calls: 0,
+        });
+        Expect {
+            mock: self,
+            index: mock.expectations.len() - 1,
+            api: PhantomData,
+        }
+    }
+
+    /// Creates a requester whose requests are answered by this service.
+    pub fn requester(&self) -> Requester<MockTransport> {
+        Requester::new(MockTransport {
+            mock: Rc::clone(&self.mock),
+            replies: RefCell::default(),
+            shutdown: Arc::default(),
+        })
+    }
+
+    /// Returns how often `A` has been requested so far.
+    pub fn calls<A: Api>(&self) -> usize {
+        self.mock
+            .borrow()
+            .expectations
+            .iter()
+            .filter(|expectation| {
+                (
+                    expectation.service,
+                    expectation.api_name,
+                    expectation.version,
+                ) == (A::SERVICE, A::NAME, A::VERSION)
+            })
+            .map(|expectation| expectation.calls)
+            .sum()
+    }
+
+    /// Panics if there were unexpected requests, or if an API was requested fewer or more
+    /// [`times`](Expect::times) than expected.
+    pub fn verify(&self) {
+        let mock = self.mock.borrow();
+        let mut failures = mock.unexpected.clone();
+        for expectation in &mock.expectations {
+            if let Some(times) = expectation
+                .times
+                .filter(|times| *times != expectation.calls)
+            {
+                failures.push(format!(
+                    "Expected '{}' in service '{}' to be requested {times} times, but it was \
+                     requested {} times",
+                    expectation.api_name, expectation.service, expectation.calls
+                ));
+            }
+        }
+        if !failures.is_empty() {
+            panic!("Mock service expectations failed:\n{}", failures.join("\n"));
+        }
+    }
+}
+
+impl Default for MockService {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for MockService {
+    fn drop(&mut self) {
+        // Failing again while a test is already failing would abort the process.
+        if !thread::panicking() {
+            self.verify();
+        }
+    }
+}
+
+/// An expectation for requests for `A`, see [`MockService::expect`].
+pub struct Expect<'m, A> {
+    mock: &'m MockService,
+    index: usize,
+    api: PhantomData<fn() -> A>,
+}
+
+impl<A: Api> Expect<'_, A> {
+    /// Answers the requests with the reply of `handler`, like a handler registered with
+    /// [`ApiRouter::register_handler`](crate::api::ApiRouter::register_handler).
+    pub fn returning<H: Handler<A> + 'static>(self, mut handler: H) -> Self {
+        let reply = move |request_data: &[u8]| -> Result<Vec<u8>, Error> {
+            let request: A::Request<'_> = serde_json::from_slice(request_data)
+                .map_err(|e| format!("Deserialize error: {e}"))?;
+            let reply = serde_json::to_vec(&handler(request))
+                .map_err(|e| format!("Serialize error: {e}"))?;
+            Ok(reply)
+        };
+        self.update(|expectation| expectation.reply = Box::new(reply))
+    }
+
+    /// Answers the requests with `error`.
+    pub fn returning_error(self, error: impl Into<Error>) -> Self {
+        let error = error.into();
+        self.update(|expectation| expectation.reply = Box::new(move |_| Err(error.clone())))
+    }
+
+    /// Expects exactly `times` requests, which [`MockService::verify`] checks.
+    pub fn times(self, times: usize) -> Self {
+        self.update(|expectation| expectation.times = Some(times))
+    }
+
+    /// Expects no requests at all.
+    pub fn never(self) -> Self {
+        self.times(0)
+    }
+
+    fn update(self, update: impl FnOnce(&mut Expectation)) -> Self {
+        update(&mut self.mock.mock.borrow_mut().expectations[self.index]);
+        self
+    }
+}
+
+/// The [`Transport`] between a [`MockService`] and its requesters.
+///
+/// Since requests are answered when they are sent, receiving returns a [`Kind::Shutdown`] message
+/// instead of blocking if there is no reply.
+pub struct MockTransport {
+    mock: Rc<RefCell<Mock>>,
+    replies: RefCell<VecDeque<Message>>,
+    shutdown: Arc<AtomicBool>,
+}
+
+impl Transport for MockTransport {
+    fn send(&self, message: Message) -> Result<()> {
+        let reply = match message.kind {
+            Kind::Request => self.mock.borrow_mut().handle(&message),
+            Kind::Batch => {
+                let requests = Message::decode_batch(&message.data)?;
+                let mut mock = self.mock.borrow_mut();
+                let replies: Vec<_> = requests.iter().map(|r| mock.handle(r)).collect();
+                message.reply(Message::encode_batch(&replies))
+            }
+            // Events are not answered, and mock services have no subscribers.
+            Kind::Cancel | Kind::Shutdown | Kind::Event => return Ok(()),
+            kind => message.error(format!("Mock services cannot answer {kind:?} messages")),
+        };
+        self.replies.borrow_mut().push_back(reply);
+        Ok(())
+    }
+
+    fn recv(&self) -> Result<Message> {
+        if self.shutdown.load(Ordering::Acquire) {
+            return Ok(Message::shutdown());
+        }
+        let reply = self.replies.borrow_mut().pop_front();
+        Ok(reply.unwrap_or_else(Message::shutdown))
+    }
+
+    fn recv_timeout(&self, _: Duration) -> Result<Option<Message>> {
+        self.recv().map(Some)
+    }
+
+    fn shutdown_handle(&self) -> Result<ShutdownHandle> {
+        let shutdown = Arc::clone(&self.shutdown);
+        Ok(ShutdownHandle::new(move || {
+            shutdown.store(true, Ordering::Release);
+            Ok(())
+        }))
+    }
+}
+
+/// A [`Transport`] that records every request sent over another transport together with its
+/// reply, for a [`Replayer`] to answer the same requests later on.
+///
+/// Requests are written to the file as soon as their reply arrives. Only requests with a single
+/// reply are recorded, not ones for streaming APIs.
+pub struct Recorder<T> {
+    transport: T,
+    file: RefCell<File>,
+    /// The requests that have not been answered yet, by ID.
+    pending: RefCell<HashMap<u64, Message>>,
+}
+
+impl<T: Transport> Recorder<T> {
+    /// Records the requests sent over `transport` to a new file at `path`, replacing any
+    /// existing file.
+    pub fn create(path: impl AsRef<Path>, transport: T) -> Result<Self> {
+        let file = File::create(path).map_err(|e| format!("Failed to create recording: {e}"))?;
+        Ok(Self {
+            transport,
+            file: RefCell::new(file),
+            pending: RefCell::default(),
+        })
+    }
+
+    fn record(&self, reply: &Message) -> Result<()> {
+        if !matches!(reply.kind, Kind::Reply | Kind::Error) {
+            return Ok(());
+        }
+        let Some(request) = self.pending.borrow_mut().remove(&reply.id) else {
+            return Ok(());
+        };
+        // The recording is a sequence of requests and replies in the batch encoding.
+        let pair = Message::encode_batch(&[request, reply.clone()]);
+        self.file
+            .borrow_mut()
+            .write_all(&pair)
+            .map_err(|e| format!("Failed to record reply: {e}"))
+    }
+}
+
+impl<T: Transport> Transport for Recorder<T> {
+    fn send(&self, message: Message) -> Result<()> {
+        if matches!(message.kind, Kind::Request | Kind::Batch) {
+            self.pending
+                .borrow_mut()
+                .insert(message.id, message.clone());
+        }
+        self.transport.send(message)
+    }
+
+    fn recv(&self) -> Result<Message> {
+        let message = self.transport.recv()?;
+        self.record(&message)?;
+        Ok(message)
+    }
+
+    fn recv_timeout(&self, timeout: Duration) -> Result<Option<Message>> {
+        let message = self.transport.recv_timeout(timeout)?;
+        if let Some(message) = &message {
+            self.record(message)?;
+        }
+        Ok(message)
+    }
+
+    fn recv_limited(&self, timeout: Option<Duration>, max_len: usize) -> Result<Option<Message>> {
+        let message = self.transport.recv_limited(timeout, max_len)?;
+        if let Some(message) = &message {
+            self.record(message)?;
+        }
+        Ok(message)
+    }
+
+    fn shutdown_handle(&self) -> Result<ShutdownHandle> {
+        self.transport.shutdown_handle()
+    }
+}
+
+/// A [`Transport`] that answers requests with the replies a [`Recorder`] recorded for them.
+///
+/// Requests match a recorded request if they are for the same version of the same API with the
+/// same data, regardless of their IDs, deadlines and headers. Requests that were recorded several
+/// times are answered with the recorded replies in order. Requests without a recorded reply are
+/// answered with an error.
+pub struct Replayer {
+    /// The recorded requests and replies that have not been replayed yet.
+    recorded: RefCell<Vec<(Message, Message)>>,
+    replies: RefCell<VecDeque<Message>>,
+    shutdown: Arc<AtomicBool>,
+}
+
+impl Replayer {
+    /// Loads the recording a [`Recorder`] made at `path`.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let bytes = fs::read(path).map_err(|e| format!("Failed to read recording: {e}"))?;
+        let mut messages = Message::decode_batch(&bytes)
+            .map_err(|e| format!("Invalid recording: {e}"))?
+            .into_iter();
+        let mut recorded = Vec::new();
+        while let Some(request) = messages.next() {
+            let reply = messages
+                .next()
+                .ok_or("Invalid recording: missing the last reply")?;
+            recorded.push((request, reply));
+        }
+        Ok(Self {
+            recorded: RefCell::new(recorded),
+            replies: RefCell::default(),
+            shutdown: Arc::default(),
+        })
+    }
+
+    /// Creates a requester whose requests are answered from the recording.
+    pub fn requester(self) -> Requester<Self> {
+        Requester::new(self)
+    }
+
+    /// Returns how many recorded requests have not been replayed yet.
+    pub fn remaining(&self) -> usize {
+        self.recorded.borrow().len()
+    }
+}
+
+/// Returns whether `a` and `b` request the same thing.
+fn same_request(a: &Message, b: &Message) -> bool {
+    let same_route = a.kind == b.kind
+        && a.service == b.service
+        && a.api_name == b.api_name
+        && a.version == b.version;
+    if !same_route {
+        return false;
+    }
+    if a.kind != Kind::Batch {
+        return a.data == b.data;
+    }
+    match (
+        Message::decode_batch(&a.data),
+        Message::decode_batch(&b.data),
+    ) {
+        (Ok(a), Ok(b)) => a.len() == b.len() && a.iter().zip(&b).all(|(a, b)| same_request(a, b)),
+        _ => false,
+    }
+}
+
+impl Transport for Replayer {
+    fn send(&self, message: Message) -> Result<()> {
+        if matches!(message.kind, Kind::Cancel | Kind::Shutdown | Kind::Event) {
+            return Ok(());
+        }
+        let mut recorded = self.recorded.borrow_mut();
+        let reply = match recorded
+            .iter()
+            .position(|(request, _)| same_request(request, &message))
+        {
+            Some(position) => {
+                let (_, reply) = recorded.remove(position);
+                Message {
+                    id: message.id,
+                    ..reply
+                }
+            }
+            None => message.error(format!(
+                "No recorded reply for '{}' in service '{}'",
+                message.api_name, message.service
+            )),
+        };
+        self.replies.borrow_mut().push_back(reply);
+        Ok(())
+    }
+
+    fn recv(&self) -> Result<Message> {
+        if self.shutdown.load(Ordering::Acquire) {
+            return Ok(Message::shutdown());
+        }
+        let reply = self.replies.borrow_mut().pop_front();
+        Ok(reply.unwrap_or_else(Message::shutdown))
+    }
+
+    fn recv_timeout(&self, _: Duration) -> Result<Option<Message>> {
+        self.recv().map(Some)
+    }
+
+    fn shutdown_handle(&self) -> Result<ShutdownHandle> {
+        let shutdown = Arc::clone(&self.shutdown);
+        Ok(ShutdownHandle::new(move || {
+            shutdown.store(true, Ordering::Release);
+            Ok(())
+        }))
+    }
+}
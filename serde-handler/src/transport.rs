@@ -0,0 +1,608 @@
+use std::{
+    cell::{Cell, RefCell},
+    collections::{HashMap, VecDeque},
+    io::{self, Read, Write},
+    net::{Shutdown, TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc::{self, Receiver, RecvTimeoutError, SyncSender},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+
+use crate::{
+    channel::{Kind, Message},
+    Result,
+};
+
+/// A bidirectional connection that [`Message`]s can be exchanged over.
+///
+/// [`Requester`](crate::channel::Requester) and [`Responder`](crate::channel::Responder) are
+/// generic over their transport, so the same [`Api`](crate::api::Api) types can be used
+/// between threads (with [`Local`](crate::channel::Local)) or between processes (with a socket).
+pub trait Transport {
+    /// Sends `message` to the other end of the connection.
+    fn send(&self, message: Message) -> Result<()>;
+
+    /// Blocks until the next message from the other end of the connection arrives.
+    ///
+    /// Once the other end has hung up, this returns a [`Kind::Shutdown`] message.
+    fn recv(&self) -> Result<Message>;
+
+    /// Like [`recv`](Transport::recv), but returns `None` if no message arrives within
+    /// `timeout`.
+    fn recv_timeout(&self, timeout: Duration) -> Result<Option<Message>>;
+
+    /// Like [`recv_timeout`](Transport::recv_timeout), or [`recv`](Transport::recv) without a
+    /// `timeout`, but fails without reading a message whose frame is longer than `max_len` bytes,
+    /// so that a peer cannot make the transport allocate more memory than that.
+    ///
+    /// Returns `None` only if the `timeout` passed. [`Requester`](crate::channel::Requester)s and
+    /// [`Responder`](crate::channel::Responder)s receive with this, given their maximum message
+    /// size. Transports that do not read frames from a byte stream do not need to override this.
+    fn recv_limited(&self, timeout: Option<Duration>, max_len: usize) -> Result<Option<Message>> {
+        let _ = max_len;
+        match timeout {
+            Some(timeout) => self.recv_timeout(timeout),
+            None => self.recv().map(Some),
+        }
+    }
+
+    /// Like [`recv_timeout`](Transport::recv_timeout), but only returns a message from the peer
+    /// that sent the last received message. Messages from other peers are kept for later calls
+    /// to [`recv`](Transport::recv).
+    ///
+    /// Transports with several peers return a [`Kind::Cancel`] message once that peer has hung
+    /// up, since that does not stop the transport as a whole. Transports with a single peer do not
+    /// need to override this.
+    fn recv_from_current_timeout(&self, timeout: Duration) -> Result<Option<Message>> {
+        self.recv_timeout(timeout)
+    }
+
+    /// Create a handle that makes [`recv`](Transport::recv) return a [`Kind::Shutdown`] message,
+    /// after any messages that have already arrived.
+    fn shutdown_handle(&self) -> Result<ShutdownHandle>;
+
+    /// Identifies the peer that sent the last received message, so that
+    /// [`set_current_peer`](Transport::set_current_peer) can turn back to it after messages from
+    /// other peers were received.
+    ///
+    /// Transports with a single peer do not need to override this.
+    fn current_peer(&self) -> Option<PeerId> {
+        None
+    }
+
+    /// Makes `peer`, as returned by [`current_peer`](Transport::current_peer), the peer that
+    /// [`send`](Transport::send) and
+    /// [`recv_from_current_timeout`](Transport::recv_from_current_timeout) concern, as if its
+    /// last message had just been received.
+    ///
+    /// Transports with a single peer do not need to override this.
+    fn set_current_peer(&self, peer: Option<PeerId>) {
+        let _ = peer;
+    }
+}
+
+/// Identifies one of the peers of a [`Transport`] with several peers, such as a client of a
+/// [`Server`].
+pub type PeerId = usize;
+
+/// Stops the endpoint of a [`Transport`] from another thread.
+///
+/// See [`Responder::shutdown_handle`](crate::channel::Responder::shutdown_handle).
+pub struct ShutdownHandle(Box<dyn Fn() -> Result<()> + Send + Sync>);
+
+impl ShutdownHandle {
+    /// Create a handle that runs `shutdown` when it is used.
+    pub fn new(shutdown: impl Fn() -> Result<()> + Send + Sync + 'static) -> Self {
+        Self(Box::new(shutdown))
+    }
+
+    /// Asks the endpoint to stop once it is done with the messages it has already received.
+    ///
+    /// This does not wait for the endpoint to actually stop.
+    pub fn shutdown(&self) -> Result<()> {
+        (self.0)()
+    }
+}
+
+/// Writes `message` to `writer` as a single frame.
+///
+/// A frame is the big-endian `u32` length of the [encoded](Message::encode) message, followed by
+/// the encoded message itself.
+pub fn write_frame(mut writer: impl Write, message: &Message) -> io::Result<()> {
+    let body = message.encode();
+    let len = u32::try_from(body.len())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "message too large to frame"))?;
+    let mut frame = Vec::with_capacity(4 + body.len());
+    frame.extend_from_slice(&len.to_be_bytes());
+    frame.extend_from_slice(&body);
+    writer.write_all(&frame)?;
+    writer.flush()
+}
+
+/// Reads a single frame written by [`write_frame`] from `reader`, failing without reading the
+/// message if it is longer than `max_len` bytes.
+///
+/// Returns `None` if `reader` ends cleanly before the frame, i.e. the writer hung up. After an
+/// error, `reader` may be in the middle of a frame and should not be read from anymore.
+pub fn read_frame(mut reader: impl Read, max_len: usize) -> io::Result<Option<Message>> {
+    let Some(len) = read_frame_len(&mut reader, max_len)? else {
+        return Ok(None);
+    };
+    // The body is read as it arrives, so that a frame that is cut short does not allocate its
+    // full length either.
+    let mut body = Vec::new();
+    reader.take(len as u64).read_to_end(&mut body)?;
+    if body.len() < len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Message::decode(&body)
+        .map(Some)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Reads the big-endian `u32` length that starts a frame, failing if it is more than `max_len`.
+///
+/// Returns `None` if `reader` ends cleanly before the frame.
+pub(crate) fn read_frame_len(mut reader: impl Read, max_len: usize) -> io::Result<Option<usize>> {
+    let mut len = [0; 4];
+    let read = loop {
+        match reader.read(&mut len) {
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            read => break read?,
+        }
+    };
+    if read == 0 {
+        return Ok(None);
+    }
+    reader.read_exact(&mut len[read..])?;
+    let len = u32::from_be_bytes(len) as usize;
+    if len > max_len {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Frame of {len} bytes exceeds the maximum of {max_len} bytes"),
+        ));
+    }
+    Ok(Some(len))
+}
+
+/// The least time the rest of a frame gets to arrive after its first byte, so that polling with a
+/// short timeout does not cut frames short.
+const MIN_FRAME_TIME: Duration = Duration::from_millis(100);
+
+/// Implements [`Transport::recv_timeout`] for a socket with the given `set_read_timeout` method,
+/// reading frames with `read_frame`.
+///
+/// A frame that has started to arrive must arrive completely by the end of the timeout, but gets
+/// at least [`MIN_FRAME_TIME`]. Otherwise, the frame is left partially read and this fails, as the
+/// socket cannot be read from anymore.
+pub(crate) fn recv_stream_timeout<'s, S>(
+    stream: &'s S,
+    timeout: Duration,
+    set_read_timeout: fn(&S, Option<Duration>) -> io::Result<()>,
+    read_frame: impl FnOnce(io::Chain<&[u8], ReadUntil<'s, S>>) -> io::Result<Option<Message>>,
+) -> Result<Option<Message>>
+where
+    for<'a> &'a S: Read,
+{
+    // A zero timeout is rejected by the socket, and would mean to block forever anyway.
+    let timeout = timeout.max(Duration::from_micros(1));
+    let deadline = Instant::now() + timeout;
+    set_read_timeout(stream, Some(timeout)).map_err(|e| e.to_string())?;
+    let mut first = [0; 1];
+    let mut reader = stream;
+    let read = loop {
+        match reader.read(&mut first) {
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            read => break read,
+        }
+    };
+    let result = match read {
+        Ok(0) => Ok(Some(Message::shutdown())),
+        Ok(_) => {
+            let rest = ReadUntil {
+                stream,
+                deadline: deadline.max(Instant::now() + MIN_FRAME_TIME),
+                set_read_timeout,
+            };
+            read_frame((&first[..]).chain(rest))
+                .map(|message| Some(message.unwrap_or_else(Message::shutdown)))
+                .map_err(|e| match e.kind() {
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => {
+                        "Timed out in the middle of a frame".to_string()
+                    }
+                    _ => e.to_string(),
+                })
+        }
+        Err(e)
+            if matches!(
+                e.kind(),
+                io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+            ) =>
+        {
+            Ok(None)
+        }
+        Err(e) => Err(e.to_string()),
+    };
+    set_read_timeout(stream, None).map_err(|e| e.to_string())?;
+    result
+}
+
+/// Reads from a socket until `deadline`, shrinking its read timeout before every read.
+pub(crate) struct ReadUntil<'s, S> {
+    stream: &'s S,
+    deadline: Instant,
+    set_read_timeout: fn(&S, Option<Duration>) -> io::Result<()>,
+}
+
+impl<S> Read for ReadUntil<'_, S>
+where
+    for<'a> &'a S: Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let remaining = self.deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(io::ErrorKind::TimedOut.into());
+        }
+        (self.set_read_timeout)(self.stream, Some(remaining))?;
+        let mut stream = self.stream;
+        stream.read(buf)
+    }
+}
+
+impl Transport for TcpStream {
+    fn send(&self, message: Message) -> Result<()> {
+        write_frame(self, &message).map_err(|e| e.to_string())
+    }
+
+    fn recv(&self) -> Result<Message> {
+        let message = read_frame(self, usize::MAX).map_err(|e| e.to_string())?;
+        Ok(message.unwrap_or_else(Message::shutdown))
+    }
+
+    fn recv_timeout(&self, timeout: Duration) -> Result<Option<Message>> {
+        self.recv_limited(Some(timeout), usize::MAX)
+    }
+
+    fn recv_limited(&self, timeout: Option<Duration>, max_len: usize) -> Result<Option<Message>> {
+        let Some(timeout) = timeout else {
+            let message = read_frame(self, max_len).map_err(|e| e.to_string())?;
+            return Ok(Some(message.unwrap_or_else(Message::shutdown)));
+        };
+        recv_stream_timeout(self, timeout, TcpStream::set_read_timeout, |frame| {
+            read_frame(frame, max_len)
+        })
+    }
+
+    fn shutdown_handle(&self) -> Result<ShutdownHandle> {
+        let stream = self.try_clone().map_err(|e| e.to_string())?;
+        Ok(ShutdownHandle::new(move || {
+            stream.shutdown(Shutdown::Read).map_err(|e| e.to_string())
+        }))
+    }
+}
+
+#[cfg(unix)]
+impl Transport for UnixStream {
+    fn send(&self, message: Message) -> Result<()> {
+        write_frame(self, &message).map_err(|e| e.to_string())
+    }
+
+    fn recv(&self) -> Result<Message> {
+        let message = read_frame(self, usize::MAX).map_err(|e| e.to_string())?;
+        Ok(message.unwrap_or_else(Message::shutdown))
+    }
+
+    fn recv_timeout(&self, timeout: Duration) -> Result<Option<Message>> {
+        self.recv_limited(Some(timeout), usize::MAX)
+    }
+
+    fn recv_limited(&self, timeout: Option<Duration>, max_len: usize) -> Result<Option<Message>> {
+        let Some(timeout) = timeout else {
+            let message = read_frame(self, max_len).map_err(|e| e.to_string())?;
+            return Ok(Some(message.unwrap_or_else(Message::shutdown)));
+        };
+        recv_stream_timeout(self, timeout, UnixStream::set_read_timeout, |frame| {
+            read_frame(frame, max_len)
+        })
+    }
+
+    fn shutdown_handle(&self) -> Result<ShutdownHandle> {
+        let stream = self.try_clone().map_err(|e| e.to_string())?;
+        Ok(ShutdownHandle::new(move || {
+            stream.shutdown(Shutdown::Read).map_err(|e| e.to_string())
+        }))
+    }
+}
+
+/// A received message and the connection it came from, or `None` for a shutdown requested through
+/// a [`ShutdownHandle`].
+type Incoming = (Option<PeerId>, Message);
+
+/// How long a [`Server`] waits before accepting connections again after failing to accept one.
+const ACCEPT_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// The handles for writing to the open connections, by their ID.
+///
+/// Each handle has its own lock, so that writing to a slow client does not keep the map locked
+/// for the others.
+type Connections<C> = Arc<Mutex<HashMap<PeerId, Arc<Mutex<C>>>>>;
+
+/// A [`Transport`] that accepts any number of clients on a listening socket.
+///
+/// Requests from all connected clients are received in the order they arrive. Sending a message
+/// answers the client whose request was received last, which matches how a
+/// [`Responder`](crate::channel::Responder) handles one request at a time, unless another client
+/// was made current with [`set_current_peer`](Transport::set_current_peer).
+///
+/// Each connection is read on its own background thread. Sending to a client that disconnected
+/// before its reply could be sent fails, without affecting the other clients. Clients hanging up or
+/// sending [`Kind::Shutdown`] only ends their own connection; the server as a whole is stopped
+/// with a [`ShutdownHandle`]. When the server is dropped, it stops accepting connections and
+/// sends [`Kind::Shutdown`] to all remaining clients.
+pub struct Server<C: Transport> {
+    incoming: Receiver<Incoming>,
+    shutdown: SyncSender<Incoming>,
+    connections: Connections<C>,
+    current: Cell<Option<PeerId>>,
+    /// Messages that arrived while waiting for one from the `current` connection.
+    deferred: RefCell<VecDeque<Incoming>>,
+    closed: Arc<AtomicBool>,
+    /// The longest frame that is read from any connection, as last given to
+    /// [`recv_limited`](Transport::recv_limited).
+    max_frame_len: Arc<AtomicUsize>,
+    /// Unblocks the thread waiting for new connections, so it can notice that `closed` is set.
+    wake_acceptor: Box<dyn Fn() + Send>,
+}
+
+impl Server<TcpStream> {
+    /// Serve all clients that connect to `listener`.
+    pub fn tcp(listener: TcpListener) -> Self {
+        let addr = listener.local_addr();
+        let wake = move || {
+            if let Ok(addr) = addr {
+                let _ = TcpStream::connect(addr);
+            }
+        };
+        Self::accept_with(
+            wake,
+            move || listener.accept().map(|(stream, _)| stream),
+            |stream: TcpStream| {
+                stream.set_nodelay(true)?;
+                Ok((stream.try_clone()?, stream))
+            },
+        )
+    }
+}
+
+#[cfg(unix)]
+impl Server<UnixStream> {
+    /// Serve all clients that connect to `listener`.
+    pub fn unix(listener: UnixListener) -> Self {
+        let path = listener
+            .local_addr()
+            .ok()
+            .and_then(|addr| addr.as_pathname().map(ToOwned::to_owned));
+        let wake = move || {
+            if let Some(path) = &path {
+                let _ = UnixStream::connect(path);
+            }
+        };
+        Self::accept_with(
+            wake,
+            move || listener.accept().map(|(stream, _)| stream),
+            |stream: UnixStream| Ok((stream.try_clone()?, stream)),
+        )
+    }
+}
+
+impl<C: Transport + Send + 'static> Server<C> {
+    /// Spawns a thread that repeatedly calls `accept` for a new connection, which `open` turns
+    /// into a pair of handles for reading and writing.
+    ///
+    /// `open` runs on the thread of the connection, so that a slow client, e.g. in a handshake,
+    /// does not hold up accepting the others.
+    pub(crate) fn accept_with<R, W, F, O>(wake_acceptor: W, mut accept: F, open: O) -> Self
+    where
+        R: Send + 'static,
+        W: Fn() + Send + 'static,
+        F: FnMut() -> io::Result<R> + Send + 'static,
+        O: Fn(R) -> io::Result<(C, C)> + Send + Sync + 'static,
+    {
+        let open = Arc::new(open);
+        let (send, recv) = mpsc::sync_channel(1);
+        let connections = Arc::new(Mutex::new(HashMap::new()));
+        let closed = Arc::new(AtomicBool::new(false));
+        let max_frame_len = Arc::new(AtomicUsize::new(usize::MAX));
+        let server = Self {
+            incoming: recv,
+            shutdown: send.clone(),
+            connections: Arc::clone(&connections),
+            current: Cell::new(None),
+            deferred: RefCell::new(VecDeque::new()),
+            closed: Arc::clone(&closed),
+            max_frame_len: Arc::clone(&max_frame_len),
+            wake_acceptor: Box::new(wake_acceptor),
+        };
+
+        thread::spawn(move || {
+            for id in 0.. {
+                let connection = accept();
+                if closed.load(Ordering::Acquire) {
+                    break;
+                }
+                let connection = match connection {
+                    Ok(connection) => connection,
+                    Err(_e) => {
+                        #[cfg(feature = "tracing")]
+                        tracing::warn!(error = %_e, "Failed to accept a connection");
+                        // Errors such as running out of file descriptors persist for a while.
+                        thread::sleep(ACCEPT_RETRY_DELAY);
+                        continue;
+                    }
+                };
+                let open = Arc::clone(&open);
+                let send = send.clone();
+                let connections = Arc::clone(&connections);
+                let closed = Arc::clone(&closed);
+                let max_frame_len = Arc::clone(&max_frame_len);
+                thread::spawn(move || {
+                    let (reader, writer) = match open(connection) {
+                        Ok(handles) => handles,
+                        Err(_e) => {
+                            #[cfg(feature = "tracing")]
+                            tracing::warn!(error = %_e, "Failed to open a connection");
+                            return;
+                        }
+                    };
+                    // Connections that open after the server was dropped are not served.
+                    if closed.load(Ordering::Acquire) {
+                        return;
+                    }
+                    connections
+                        .lock()
+                        .unwrap()
+                        .insert(id, Arc::new(Mutex::new(writer)));
+                    Self::read_connection(id, reader, send, connections, max_frame_len)
+                });
+            }
+        });
+
+        server
+    }
+
+    fn read_connection(
+        id: PeerId,
+        reader: C,
+        requests: SyncSender<Incoming>,
+        connections: Connections<C>,
+        max_frame_len: Arc<AtomicUsize>,
+    ) {
+        // Reading fails once the client hangs up or sends a frame that is too long, at which point
+        // the connection is discarded.
+        while let Ok(Some(message)) =
+            reader.recv_limited(None, max_frame_len.load(Ordering::Relaxed))
+        {
+            if message.kind == Kind::Shutdown || requests.send((Some(id), message)).is_err() {
+                break;
+            }
+        }
+        connections.lock().unwrap().remove(&id);
+    }
+}
+
+impl<C: Transport> Transport for Server<C> {
+    fn send(&self, message: Message) -> Result<()> {
+        let id = self
+            .current
+            .get()
+            .ok_or("No request has been received yet")?;
+        let connection = self
+            .connections
+            .lock()
+            .unwrap()
+            .get(&id)
+            .cloned()
+            .ok_or_else(|| format!("Connection {id} has closed"))?;
+        let result = connection.lock().unwrap().send(message);
+        // A connection that failed once is not used again.
+        if result.is_err() {
+            self.connections.lock().unwrap().remove(&id);
+        }
+        result
+    }
+
+    fn recv(&self) -> Result<Message> {
+        let deferred = self.deferred.borrow_mut().pop_front();
+        let (id, message) = match deferred {
+            Some(incoming) => incoming,
+            None => self.incoming.recv().map_err(|e| e.to_string())?,
+        };
+        self.current.set(id);
+        Ok(message)
+    }
+
+    fn recv_timeout(&self, timeout: Duration) -> Result<Option<Message>> {
+        if let Some((id, message)) = self.deferred.borrow_mut().pop_front() {
+            self.current.set(id);
+            return Ok(Some(message));
+        }
+        match self.incoming.recv_timeout(timeout) {
+            Ok((id, message)) => {
+                self.current.set(id);
+                Ok(Some(message))
+            }
+            Err(RecvTimeoutError::Timeout) => Ok(None),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    fn recv_limited(&self, timeout: Option<Duration>, max_len: usize) -> Result<Option<Message>> {
+        // Frames are read by the thread of each connection, which applies the limit to the next
+        // frames they read.
+        self.max_frame_len.store(max_len, Ordering::Relaxed);
+        match timeout {
+            Some(timeout) => self.recv_timeout(timeout),
+            None => self.recv().map(Some),
+        }
+    }
+
+    fn recv_from_current_timeout(&self, timeout: Duration) -> Result<Option<Message>> {
+        let current = self.current.get();
+        // Shutdowns through a `ShutdownHandle` concern every connection.
+        let is_for_current = |(id, _): &Incoming| *id == current || id.is_none();
+        let mut deferred = self.deferred.borrow_mut();
+        if let Some(position) = deferred.iter().position(is_for_current) {
+            return Ok(deferred.remove(position).map(|(_, message)| message));
+        }
+        let deadline = Instant::now() + timeout;
+        loop {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            match self.incoming.recv_timeout(timeout) {
+                Ok(incoming) if is_for_current(&incoming) => return Ok(Some(incoming.1)),
+                Ok(incoming) => deferred.push_back(incoming),
+                Err(RecvTimeoutError::Timeout) => break,
+                Err(e) => return Err(e.to_string()),
+            }
+        }
+        let hung_up = current.is_some_and(|id| !self.connections.lock().unwrap().contains_key(&id));
+        Ok(hung_up.then(|| Message {
+            kind: Kind::Cancel,
+            ..Default::default()
+        }))
+    }
+
+    fn shutdown_handle(&self) -> Result<ShutdownHandle> {
+        let shutdown = self.shutdown.clone();
+        Ok(ShutdownHandle::new(move || {
+            // Sending only fails if the server is gone already.
+            let _ = shutdown.send((None, Message::shutdown()));
+            Ok(())
+        }))
+    }
+
+    fn current_peer(&self) -> Option<PeerId> {
+        self.current.get()
+    }
+
+    fn set_current_peer(&self, peer: Option<PeerId>) {
+        self.current.set(peer);
+    }
+}
+
+impl<C: Transport> Drop for Server<C> {
+    fn drop(&mut self) {
+        self.closed.store(true, Ordering::Release);
+        (self.wake_acceptor)();
+        let connections: Vec<_> = self.connections.lock().unwrap().drain().collect();
+        for (_, connection) in connections {
+            let _ = connection.lock().unwrap().send(Message::shutdown());
+        }
+    }
+}
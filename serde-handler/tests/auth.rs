@@ -0,0 +1,221 @@
+use std::{
+    net::{TcpListener, TcpStream},
+    thread,
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+
+use serde_handler::{
+    api::*,
+    auth::{Hmac, Rule, Tokens},
+    channel::{self, Error, Kind, Message, Responder},
+    compression::{Compression, Encoding},
+    headers::{Headers, PRINCIPAL},
+    noise::{self, Keypair},
+    transport::Server,
+};
+
+/// Replies with the principal the request was authenticated as.
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct WhoAmI;
+
+impl Api for WhoAmI {
+    type Reply<'de> = Option<String>;
+    type Request<'de> = WhoAmI;
+
+    const NAME: &'static str = "whoami";
+    const SERVICE: &'static str = "accounts";
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct DeleteAccount(String);
+
+impl Api for DeleteAccount {
+    type Reply<'de> = String;
+    type Request<'de> = DeleteAccount;
+
+    const NAME: &'static str = "delete";
+    const SERVICE: &'static str = "accounts";
+}
+
+fn accounts_router() -> ApiRouter {
+    ApiRouter::new()
+        .register_handler_with::<WhoAmI, _, _>(|headers: Headers, _| {
+            headers.get(PRINCIPAL).map(str::to_string)
+        })
+        .register_handler::<DeleteAccount, _>(|req| format!("deleted {}", req.0))
+        .authorize::<DeleteAccount>(Rule::principals(["admin"]))
+}
+
+fn tokens() -> Tokens {
+    Tokens::new()
+        .token("alice-token", "alice")
+        .token("admin-token", "admin")
+}
+
+#[test]
+fn requests_are_authenticated_by_token() {
+    let (requester, responder) = channel::new_pair();
+    thread::spawn(move || accounts_router().serve_on(responder.with_authenticator(tokens())));
+
+    let requester = requester.with_token("alice-token");
+    assert_eq!(requester.request(WhoAmI).unwrap().as_deref(), Some("alice"));
+
+    let requester = requester.with_token("guessed-token");
+    assert_eq!(
+        requester.request(WhoAmI),
+        Err(Error::Unauthorized("Unknown token".to_string()))
+    );
+}
+
+#[test]
+fn requesters_cannot_claim_a_principal() {
+    let (requester, responder) = channel::new_pair();
+    thread::spawn(move || accounts_router().serve_on(responder));
+
+    let requester = requester.with_header(PRINCIPAL, "admin");
+    assert_eq!(requester.request(WhoAmI).unwrap(), None);
+    assert_eq!(
+        requester.request(DeleteAccount("bob".to_string())),
+        Err(Error::Unauthorized(
+            "Not allowed to request 'delete' in service 'accounts'".to_string()
+        ))
+    );
+}
+
+#[test]
+fn rules_decide_who_may_request_an_api() {
+    let (requester, responder) = channel::new_pair();
+    thread::spawn(move || accounts_router().serve_on(responder.with_authenticator(tokens())));
+
+    let alice = requester.with_token("alice-token");
+    let error = alice.request(DeleteAccount("bob".to_string())).unwrap_err();
+    assert_eq!(error.name(), "unauthorized");
+    assert_eq!(
+        error.to_string(),
+        "Unauthorized: Not allowed to request 'delete' in service 'accounts'"
+    );
+
+    let admin = alice.with_token("admin-token");
+    assert_eq!(
+        admin.request(DeleteAccount("bob".to_string())).unwrap(),
+        "deleted bob"
+    );
+}
+
+#[test]
+fn signed_requests_are_authenticated_by_key() {
+    let (requester, responder) = channel::new_pair();
+    thread::spawn(move || {
+        let hmac = Hmac::new().key("backup", b"backup secret".to_vec());
+        let responder = responder
+            .with_authenticator(hmac)
+            .with_compression(Compression::new(Encoding::Deflate).threshold(0));
+        accounts_router().serve_on(responder)
+    });
+
+    // The signature covers the compressed request as it is sent.
+    let requester = requester
+        .with_compression(Compression::new(Encoding::Deflate).threshold(0))
+        .with_hmac_key("backup", b"backup secret".to_vec());
+    assert_eq!(
+        requester.request(WhoAmI).unwrap().as_deref(),
+        Some("backup")
+    );
+
+    let requester = requester.with_hmac_key("backup", b"wrong secret".to_vec());
+    assert_eq!(
+        requester.request(WhoAmI),
+        Err(Error::Unauthorized("Invalid signature".to_string()))
+    );
+}
+
+#[test]
+fn batched_requests_are_authorized_as_the_batch() {
+    let (requester, responder) = channel::new_pair();
+    thread::spawn(move || accounts_router().serve_on(responder.with_authenticator(tokens())));
+
+    let alice = requester.with_token("alice-token");
+    let (whoami, delete) = alice
+        .batch()
+        .add(WhoAmI)
+        .add(DeleteAccount("bob".to_string()))
+        .send()
+        .unwrap();
+    assert_eq!(whoami.unwrap().as_deref(), Some("alice"));
+    assert_eq!(delete.unwrap_err().name(), "unauthorized");
+
+    // A principal claimed by a request inside the batch is replaced by that of the batch.
+    let forged = Message {
+        service: DeleteAccount::SERVICE.to_string(),
+        api_name: DeleteAccount::NAME.to_string(),
+        headers: [(PRINCIPAL, "admin")].into_iter().collect(),
+        data: serde_json::to_vec(&DeleteAccount("bob".to_string())).unwrap(),
+        ..Default::default()
+    };
+    let reply = alice
+        .send_request(Message {
+            kind: Kind::Batch,
+            data: Message::encode_batch(&[forged]),
+            ..Default::default()
+        })
+        .unwrap();
+    let [reply] = &Message::decode_batch(&reply.data).unwrap()[..] else {
+        panic!("expected one reply");
+    };
+    assert_eq!(reply.kind, Kind::Error);
+}
+
+#[test]
+fn noise_connections_are_encrypted_and_authenticated() {
+    let server_keys = Keypair::generate().unwrap();
+    let server_public_key = server_keys.public_key().to_vec();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let responder = Responder::new(Server::noise_tcp(listener, server_keys));
+    thread::spawn(move || accounts_router().serve_on(responder.with_authenticator(tokens())));
+
+    let client_keys = Keypair::generate().unwrap();
+    let requester = noise::connect_tcp(addr, &client_keys, &server_public_key)
+        .unwrap()
+        .with_token("admin-token");
+    assert_eq!(requester.request(WhoAmI).unwrap().as_deref(), Some("admin"));
+    // Larger than a single Noise message.
+    let name = "b".repeat(100_000);
+    assert_eq!(
+        requester.request(DeleteAccount(name.clone())).unwrap(),
+        format!("deleted {name}")
+    );
+    assert_eq!(
+        requester.into_transport().remote_public_key(),
+        server_public_key
+    );
+
+    let impostor = Keypair::generate().unwrap();
+    assert_eq!(
+        noise::connect_tcp(addr, &client_keys, impostor.public_key()).err(),
+        Some("The server presented an unexpected public key".to_string())
+    );
+}
+
+#[test]
+fn stalled_noise_handshakes_do_not_hold_up_other_clients() {
+    let server_keys = Keypair::generate().unwrap();
+    let server_public_key = server_keys.public_key().to_vec();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let responder = Responder::new(Server::noise_tcp(listener, server_keys));
+    thread::spawn(move || accounts_router().serve_on(responder.with_authenticator(tokens())));
+
+    // Connects, but never starts the handshake.
+    let _stalled = TcpStream::connect(addr).unwrap();
+
+    let start = Instant::now();
+    let client_keys = Keypair::generate().unwrap();
+    let requester = noise::connect_tcp(addr, &client_keys, &server_public_key)
+        .unwrap()
+        .with_token("alice-token");
+    assert_eq!(requester.request(WhoAmI).unwrap().as_deref(), Some("alice"));
+    assert!(start.elapsed() < Duration::from_secs(5));
+}
@@ -0,0 +1,218 @@
+use std::{
+    cell::Cell,
+    rc::Rc,
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use serde::{Deserialize, Serialize};
+
+use serde_handler::{
+    api::*,
+    channel::{self, Error, Kind, Message},
+    middleware::Next,
+    streaming::StreamingApi,
+};
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct UppercaseRequest<'a>(&'a str);
+
+impl Api for UppercaseRequest<'_> {
+    type Reply<'de> = String;
+    type Request<'de> = UppercaseRequest<'de>;
+
+    const NAME: &'static str = "upper";
+    const SERVICE: &'static str = "text";
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct LenRequest<'a>(&'a str);
+
+impl Api for LenRequest<'_> {
+    type Reply<'de> = usize;
+    type Request<'de> = LenRequest<'de>;
+
+    const NAME: &'static str = "len";
+    const SERVICE: &'static str = "text";
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct AddRequest(u64, u64);
+
+impl Api for AddRequest {
+    type Reply<'de> = u64;
+    type Request<'de> = AddRequest;
+
+    const NAME: &'static str = "add";
+    const SERVICE: &'static str = "math";
+}
+
+/// Not served by [`router`].
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct SubRequest(u64, u64);
+
+impl Api for SubRequest {
+    type Reply<'de> = u64;
+    type Request<'de> = SubRequest;
+
+    const NAME: &'static str = "sub";
+    const SERVICE: &'static str = "math";
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct CountRequest(u64);
+
+impl Api for CountRequest {
+    type Reply<'de> = u64;
+    type Request<'de> = CountRequest;
+
+    const NAME: &'static str = "count";
+    const SERVICE: &'static str = "math";
+}
+
+impl StreamingApi for CountRequest {}
+
+fn router() -> ApiRouter {
+    ApiRouter::new()
+        .register_handler::<UppercaseRequest, _>(|req| req.0.to_uppercase())
+        .register_handler::<LenRequest, _>(|req| req.0.len())
+        .register_handler::<AddRequest, _>(|req| req.0 + req.1)
+        .register_stream_handler::<CountRequest, _, _>(|req| 0..req.0)
+}
+
+#[test]
+fn batches_return_typed_replies_in_order() {
+    let (requester, responder) = channel::new_pair();
+    thread::spawn(move || router().serve_on(responder));
+
+    let (upper, len, sum) = requester
+        .batch()
+        .add(UppercaseRequest("abc"))
+        .add(LenRequest("abcd"))
+        .add(AddRequest(1, 2))
+        .send()
+        .unwrap();
+    assert_eq!(upper.unwrap(), "ABC");
+    assert_eq!(len.unwrap(), 4);
+    assert_eq!(sum.unwrap(), 3);
+
+    let () = requester.batch().send().unwrap();
+}
+
+#[test]
+fn failing_requests_do_not_affect_the_rest_of_the_batch() {
+    let (requester, responder) = channel::new_pair();
+    thread::spawn(move || router().serve_on(responder));
+
+    let (sub, count, sum) = requester
+        .batch()
+        .add(SubRequest(3, 2))
+        .add(CountRequest(3))
+        .add(AddRequest(1, 2))
+        .send()
+        .unwrap();
+    assert_eq!(
+        sub,
+        Err(Error::Other(
+            "No handler for 'sub' in service 'math'".to_string()
+        ))
+    );
+    assert_eq!(
+        count,
+        Err(Error::Other(
+            "'count' in service 'math' is a streaming API".to_string()
+        ))
+    );
+    assert_eq!(sum, Ok(3));
+}
+
+#[test]
+fn batches_are_sent_in_one_message() {
+    let (requester, responder) = channel::new_pair();
+    let service = thread::spawn(move || {
+        let batch = responder.next_request().unwrap();
+        assert_eq!(batch.kind, Kind::Batch);
+        let requests = Message::decode_batch(&batch.data).unwrap();
+        let names: Vec<_> = requests.iter().map(|r| r.api_name.as_str()).collect();
+        assert_eq!(names, ["upper", "add"]);
+        let replies = [
+            requests[0].reply(br#""HI""#.to_vec()),
+            requests[1].error("Overflow".to_string()),
+        ];
+        responder
+            .send_response(batch.reply(Message::encode_batch(&replies)))
+            .unwrap();
+    });
+
+    let (upper, sum) = requester
+        .batch()
+        .add(UppercaseRequest("hi"))
+        .add(AddRequest(u64::MAX, 1))
+        .send()
+        .unwrap();
+    service.join().unwrap();
+    assert_eq!(upper.unwrap(), "HI");
+    assert_eq!(sum, Err(Error::Other("Overflow".to_string())));
+}
+
+#[test]
+fn middleware_runs_for_each_request_of_a_batch() {
+    let (requester, responder) = channel::new_pair();
+    thread::spawn(move || {
+        let calls = Rc::new(Cell::new(0));
+        router()
+            .layer(move |request: &Message, next: Next<'_>| {
+                calls.set(calls.get() + 1);
+                let mut reply = next.run(request)?;
+                if request.api_name == "len" {
+                    reply = calls.get().to_string().into_bytes();
+                }
+                Ok(reply)
+            })
+            .serve_on(responder)
+    });
+
+    let (_, _, calls) = requester
+        .batch()
+        .add(AddRequest(1, 2))
+        .add(UppercaseRequest("a"))
+        .add(LenRequest(""))
+        .send()
+        .unwrap();
+    assert_eq!(calls.unwrap(), 3);
+}
+
+#[test]
+fn expired_batches_are_not_handled() {
+    let (requester, responder) = channel::new_pair();
+    thread::spawn(move || router().serve_on(responder));
+
+    let result = requester
+        .batch()
+        .add(AddRequest(1, 2))
+        .with_deadline(Some(SystemTime::now() - Duration::from_secs(1)))
+        .send();
+    assert_eq!(result, Err(Error::Timeout));
+
+    // The service is still usable afterwards
+    let (sum,) = requester.batch().add(AddRequest(2, 2)).send().unwrap();
+    assert_eq!(sum.unwrap(), 4);
+}
+
+#[test]
+fn batches_round_trip_through_the_encoding() {
+    let requests = [
+        Message {
+            api_name: "upper".to_string(),
+            data: b"\"a\"".to_vec(),
+            ..Default::default()
+        },
+        Message::shutdown(),
+    ];
+    let decoded = Message::decode_batch(&Message::encode_batch(&requests)).unwrap();
+    assert_eq!(decoded.len(), 2);
+    assert_eq!(decoded[0].api_name, "upper");
+    assert_eq!(decoded[0].data, b"\"a\"");
+    assert_eq!(decoded[1].kind, Kind::Shutdown);
+    assert!(Message::decode_batch(&[0, 0, 0, 9, 1]).is_err());
+}
@@ -0,0 +1,99 @@
+use std::{borrow::Cow, thread};
+
+use serde::{Deserialize, Serialize};
+
+use serde_handler::{api::*, channel};
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct TrimRequest<'a>(&'a str);
+
+/// Replies with a slice of the request.
+impl Api for TrimRequest<'_> {
+    type Reply<'de> = &'de str;
+    type Request<'de> = TrimRequest<'de>;
+
+    const NAME: &'static str = "trim";
+    const SERVICE: &'static str = "text";
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct SplitRequest<'a>(&'a str);
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct Split<'a> {
+    #[serde(borrow)]
+    head: Cow<'a, str>,
+    #[serde(borrow)]
+    tail: Cow<'a, str>,
+}
+
+/// Splits the request at the first comma.
+impl Api for SplitRequest<'_> {
+    type Reply<'de> = Split<'de>;
+    type Request<'de> = SplitRequest<'de>;
+
+    const NAME: &'static str = "split";
+    const SERVICE: &'static str = "text";
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct CountRequest<'a>(&'a str);
+
+impl Api for CountRequest<'_> {
+    type Reply<'de> = usize;
+    type Request<'de> = CountRequest<'de>;
+
+    const NAME: &'static str = "count";
+    const SERVICE: &'static str = "text";
+}
+
+fn text_router() -> ApiRouter {
+    ApiRouter::new()
+        .register_handler::<TrimRequest, _>(|req| req.0.trim())
+        .register_handler::<SplitRequest, _>(|req| {
+            let (head, tail) = req.0.split_once(',').unwrap_or((req.0, ""));
+            Split {
+                head: Cow::Borrowed(head),
+                tail: Cow::Borrowed(tail),
+            }
+        })
+}
+
+#[test]
+fn handlers_can_reply_with_borrowed_data() {
+    let (requester, responder) = channel::new_pair();
+    thread::spawn(move || text_router().serve_on(responder));
+
+    let trimmed = requester.request_borrowed(TrimRequest("  abc ")).unwrap();
+    assert_eq!(trimmed.reply().unwrap(), "abc");
+}
+
+#[test]
+fn borrowed_replies_are_not_copied() {
+    let (requester, responder) = channel::new_pair();
+    thread::spawn(move || text_router().serve_on(responder));
+
+    let guard = requester
+        .request_borrowed(SplitRequest("first,second"))
+        .unwrap();
+    let reply = guard.reply().unwrap();
+    assert_eq!(reply.head, "first");
+    assert_eq!(reply.tail, "second");
+    assert!(matches!(reply.head, Cow::Borrowed(_)));
+    assert!(matches!(reply.tail, Cow::Borrowed(_)));
+}
+
+#[test]
+fn owned_replies_can_be_requested_borrowed() {
+    let (requester, responder) = channel::new_pair();
+    thread::spawn(move || {
+        text_router()
+            .register_handler::<CountRequest, _>(|req| req.0.chars().count())
+            .serve_on(responder)
+    });
+
+    assert_eq!(requester.request(CountRequest("äbc")).unwrap(), 3);
+    let guard = requester.request_borrowed(CountRequest("äbc")).unwrap();
+    assert_eq!(guard.reply().unwrap(), 3);
+    assert_eq!(guard.into_bytes(), b"3");
+}
@@ -0,0 +1,59 @@
+use std::thread;
+
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use serde_handler::{
+    api::*,
+    channel::{self, Error},
+};
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct Split<'a> {
+    text: &'a str,
+    separator: char,
+}
+
+impl Api for Split<'_> {
+    type Reply<'de> = Vec<&'de str>;
+    type Request<'de> = Split<'de>;
+
+    const NAME: &'static str = "split";
+    const SERVICE: &'static str = "text";
+}
+
+fn text_router() -> ApiRouter {
+    ApiRouter::new().register_handler::<Split, _>(|req| req.text.split(req.separator).collect())
+}
+
+#[test]
+fn calls_apis_by_name() {
+    let (requester, responder) = channel::new_pair();
+    thread::spawn(move || text_router().serve_on(responder));
+
+    let reply = requester
+        .call_raw("text", "split", json!({ "text": "a,b", "separator": "," }))
+        .unwrap();
+    assert_eq!(reply, json!(["a", "b"]));
+}
+
+#[test]
+fn reports_errors_of_the_service() {
+    let (requester, responder) = channel::new_pair();
+    thread::spawn(move || text_router().serve_on(responder));
+
+    let error = requester
+        .call_raw("text", "join", json!(["a", "b"]))
+        .unwrap_err();
+    assert_eq!(
+        error,
+        Error::Other("No handler for 'join' in service 'text'".to_string())
+    );
+
+    let error = requester
+        .call_raw("text", "split", json!({ "text": "a,b" }))
+        .unwrap_err();
+    assert!(error
+        .to_string()
+        .starts_with("Deserialize error: missing field `separator`"));
+}
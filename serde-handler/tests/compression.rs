@@ -0,0 +1,226 @@
+use std::{
+    cell::RefCell,
+    io::{Read, Write},
+    net::{TcpListener, TcpStream},
+    rc::Rc,
+    thread,
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+use serde_handler::{
+    api::*,
+    channel::{self, Error, Local, Message, Requester, Responder},
+    compression::{Compression, Encoding},
+    transport::{self, Server, ShutdownHandle, Transport},
+};
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct Upper(String);
+
+impl Api for Upper {
+    type Reply<'de> = String;
+    type Request<'de> = Upper;
+
+    const NAME: &'static str = "upper";
+    const SERVICE: &'static str = "text";
+}
+
+/// The encoding and size of a message on the wire.
+type Seen = Rc<RefCell<Vec<(Encoding, usize)>>>;
+
+/// A transport that notes the encoding and size of the messages it sends and receives.
+struct Spy {
+    inner: Local,
+    sent: Seen,
+    received: Seen,
+}
+
+impl Transport for Spy {
+    fn send(&self, message: Message) -> Result<(), String> {
+        self.sent
+            .borrow_mut()
+            .push((message.encoding, message.data.len()));
+        self.inner.send(message)
+    }
+
+    fn recv(&self) -> Result<Message, String> {
+        let message = self.inner.recv()?;
+        self.received
+            .borrow_mut()
+            .push((message.encoding, message.data.len()));
+        Ok(message)
+    }
+
+    fn recv_timeout(&self, timeout: Duration) -> Result<Option<Message>, String> {
+        self.inner.recv_timeout(timeout)
+    }
+
+    fn shutdown_handle(&self) -> Result<ShutdownHandle, String> {
+        self.inner.shutdown_handle()
+    }
+}
+
+/// Serves [`Upper`] on a responder set up by `responder`, returning a requester that notes what
+/// it sends and receives.
+fn serve(
+    responder: impl FnOnce(Responder) -> Responder + Send + 'static,
+) -> (Requester<Spy>, Seen, Seen) {
+    let (requester, service) = channel::new_pair();
+    thread::spawn(move || {
+        ApiRouter::new()
+            .register_handler::<Upper, _>(|req| req.0.to_uppercase())
+            .serve_on(responder(service))
+    });
+    let (sent, received) = (Seen::default(), Seen::default());
+    let requester = Requester::new(Spy {
+        inner: requester.into_transport(),
+        sent: Rc::clone(&sent),
+        received: Rc::clone(&received),
+    });
+    (requester, sent, received)
+}
+
+fn text(len: usize) -> String {
+    "abc".repeat(len / 3)
+}
+
+#[test]
+fn large_messages_are_compressed() {
+    let (requester, sent, received) = serve(|responder| {
+        responder.with_compression(Compression::new(Encoding::Zstd).threshold(100))
+    });
+    let requester = requester.with_compression(Compression::new(Encoding::Deflate).threshold(100));
+
+    assert_eq!(
+        requester.request(Upper(text(3000))).unwrap(),
+        text(3000).to_uppercase()
+    );
+    let [(Encoding::Deflate, request_size)] = sent.borrow()[..] else {
+        panic!("{sent:?}");
+    };
+    assert!(request_size < 100, "{request_size}");
+    let [(Encoding::Zstd, reply_size)] = received.borrow()[..] else {
+        panic!("{received:?}");
+    };
+    assert!(reply_size < 100, "{reply_size}");
+}
+
+#[test]
+fn small_messages_are_not_compressed() {
+    let (requester, sent, received) =
+        serve(|responder| responder.with_compression(Compression::new(Encoding::Zstd)));
+    let requester = requester.with_compression(Compression::new(Encoding::Deflate));
+
+    assert_eq!(
+        requester.request(Upper(text(300))).unwrap(),
+        text(300).to_uppercase()
+    );
+    assert_eq!(*sent.borrow(), [(Encoding::Identity, 302)]);
+    assert_eq!(*received.borrow(), [(Encoding::Identity, 302)]);
+}
+
+#[test]
+fn requests_above_the_maximum_size_are_rejected() {
+    let (requester, _, _) = serve(|responder| responder.with_max_request_size(100));
+
+    assert_eq!(
+        requester.request(Upper(text(300))),
+        Err(Error::TooLarge {
+            size: Some(302),
+            max: 100
+        })
+    );
+    // Compressed requests are only decompressed up to the maximum
+    let requester = requester.with_compression(Compression::new(Encoding::Deflate).threshold(0));
+    assert_eq!(
+        requester.request(Upper(text(300))),
+        Err(Error::TooLarge {
+            size: None,
+            max: 100
+        })
+    );
+    assert_eq!(
+        requester.request(Upper(text(90))).unwrap(),
+        text(90).to_uppercase()
+    );
+}
+
+#[test]
+fn replies_above_the_maximum_size_are_rejected() {
+    let (requester, _, _) = serve(|responder| {
+        responder.with_compression(Compression::new(Encoding::Zstd).threshold(0))
+    });
+    let requester = requester.with_max_reply_size(100);
+
+    assert_eq!(
+        requester.request(Upper(text(300))),
+        Err(Error::TooLarge {
+            size: None,
+            max: 100
+        })
+    );
+    assert_eq!(
+        requester.request(Upper(text(300))).unwrap_err().to_string(),
+        "The decompressed message data exceeds the maximum of 100 bytes"
+    );
+    assert_eq!(
+        requester.request(Upper(text(90))).unwrap(),
+        text(90).to_uppercase()
+    );
+}
+
+#[test]
+fn requests_with_too_long_frames_are_not_read() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let responder = Responder::new(Server::tcp(listener)).with_max_request_size(100);
+    thread::spawn(move || {
+        ApiRouter::new()
+            .register_handler::<Upper, _>(|req| req.0.to_uppercase())
+            .serve_on(responder)
+    });
+    let requester = channel::connect_tcp(addr).unwrap();
+    assert_eq!(requester.request(Upper(text(3))).unwrap(), "ABC");
+
+    // The length of a 4 GiB frame closes the connection, without waiting for the frame.
+    let mut client = TcpStream::connect(addr).unwrap();
+    client
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    client.write_all(&u32::MAX.to_be_bytes()).unwrap();
+    assert_eq!(client.read(&mut [0; 1]).unwrap(), 0);
+
+    // Other clients are still served.
+    assert_eq!(requester.request(Upper(text(3))).unwrap(), "ABC");
+}
+
+#[test]
+fn replies_with_too_long_frames_are_not_read() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        transport::read_frame(&stream, usize::MAX).unwrap();
+        stream.write_all(&u32::MAX.to_be_bytes()).unwrap();
+        // Keep the connection open until the requester is done.
+        let _ = stream.read(&mut [0; 1]);
+    });
+    let requester = channel::connect_tcp(addr).unwrap().with_max_reply_size(100);
+
+    assert_eq!(
+        requester.request(Upper(text(3))),
+        Err(Error::Transport(
+            "Error receiving response: Frame of 4294967295 bytes exceeds the maximum of 65636 bytes"
+                .to_string()
+        ))
+    );
+}
+
+#[test]
+fn encodings_of_enabled_features_are_supported() {
+    for encoding in [Encoding::Identity, Encoding::Deflate, Encoding::Zstd] {
+        assert!(encoding.is_supported(), "{encoding:?}");
+    }
+}
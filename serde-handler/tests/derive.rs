@@ -0,0 +1,72 @@
+use std::{thread, time::Duration};
+
+use serde::{Deserialize, Serialize};
+
+use serde_handler::{api::*, channel};
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize, Api)]
+#[api(service = "text", name = "upper", reply = String, idempotent = true)]
+struct UppercaseRequest<'a>(&'a str);
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize, Api)]
+#[api(service = "text", name = "repeat", reply = Vec<String>, timeout_ms = 1500)]
+struct RepeatRequest<'a> {
+    text: &'a str,
+    times: usize,
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize, Api)]
+#[api(service = "text", name = "trim", reply = &'a str)]
+struct TrimRequest<'a>(&'a str);
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize, Api)]
+#[api(service = "math", name = "add")]
+#[api(reply = u64, version = 2)]
+struct AddRequest(u64, u64);
+
+#[test]
+fn derived_constants() {
+    assert_eq!(UppercaseRequest::SERVICE, "text");
+    assert_eq!(UppercaseRequest::NAME, "upper");
+    assert_eq!(UppercaseRequest::TIMEOUT, None);
+    assert_eq!(
+        [UppercaseRequest::IDEMPOTENT, RepeatRequest::IDEMPOTENT],
+        [true, false]
+    );
+    assert_eq!(RepeatRequest::NAME, "repeat");
+    assert_eq!(RepeatRequest::TIMEOUT, Some(Duration::from_millis(1500)));
+    assert_eq!(AddRequest::SERVICE, "math");
+    assert_eq!(AddRequest::VERSION, 2);
+    assert_eq!(UppercaseRequest::VERSION, 1);
+}
+
+#[test]
+fn derived_apis_can_be_served() {
+    let (requester, responder) = channel::new_pair();
+    thread::spawn(move || {
+        ApiRouter::new()
+            .register_handler::<UppercaseRequest, _>(|req| req.0.to_uppercase())
+            .register_handler::<RepeatRequest, _>(|req| vec![req.text.to_string(); req.times])
+            .register_handler::<TrimRequest, _>(|req| req.0.trim())
+            .register_handler::<AddRequest, _>(|req| req.0 + req.1)
+            .serve_on(responder)
+    });
+
+    assert_eq!(requester.request(UppercaseRequest("abc")).unwrap(), "ABC");
+    let repeated = requester
+        .request(RepeatRequest {
+            text: "ab",
+            times: 2,
+        })
+        .unwrap();
+    assert_eq!(repeated, ["ab", "ab"]);
+    let trimmed = requester.request_borrowed(TrimRequest(" ab ")).unwrap();
+    assert_eq!(trimmed.reply().unwrap(), "ab");
+    assert_eq!(requester.request(AddRequest(1, 2)).unwrap(), 3);
+}
+
+#[test]
+fn ui() {
+    let t = trybuild::TestCases::new();
+    t.compile_fail("tests/ui/*.rs");
+}
@@ -0,0 +1,171 @@
+use std::{thread, time::Duration};
+
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use serde_handler::{
+    api::*,
+    auth::{Rule, Tokens},
+    channel::{self, Error, Message, Requester},
+    describe::{ApiSchema, Describe, JsonSchema, RESERVED_SERVICE},
+    limit::Limits,
+    middleware::Next,
+};
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct UppercaseRequest<'a>(&'a str);
+
+impl Api for UppercaseRequest<'_> {
+    type Reply<'de> = String;
+    type Request<'de> = UppercaseRequest<'de>;
+
+    const NAME: &'static str = "upper";
+    const SERVICE: &'static str = "text";
+
+    fn schema() -> Option<ApiSchema> {
+        Some(ApiSchema::of::<Self, Self::Reply<'static>>())
+    }
+}
+
+impl JsonSchema for UppercaseRequest<'_> {
+    fn json_schema() -> serde_json::Value {
+        <&str>::json_schema()
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct SplitRequest<'a>(&'a str);
+
+impl Api for SplitRequest<'_> {
+    type Reply<'de> = Vec<String>;
+    type Request<'de> = SplitRequest<'de>;
+
+    const NAME: &'static str = "split";
+    const SERVICE: &'static str = "text";
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct AddRequest(u64, u64);
+
+impl Api for AddRequest {
+    type Reply<'de> = u64;
+    type Request<'de> = AddRequest;
+
+    const NAME: &'static str = "add";
+    const SERVICE: &'static str = "math";
+}
+
+fn router() -> ApiRouter {
+    ApiRouter::new()
+        .register_handler::<UppercaseRequest, _>(|req| req.0.to_uppercase())
+        .register_handler::<SplitRequest, _>(|req| {
+            req.0.split_whitespace().map(str::to_string).collect()
+        })
+        .merge(ApiRouter::new().register_handler::<AddRequest, _>(|req| req.0 + req.1))
+}
+
+#[test]
+fn describe_lists_all_routes() {
+    let (requester, responder) = channel::new_pair();
+    thread::spawn(move || router().serve_on(responder));
+
+    let description = requester.describe().unwrap();
+    let services: Vec<_> = description.services.keys().map(String::as_str).collect();
+    assert_eq!(services, ["math", RESERVED_SERVICE, "text"]);
+    let text_apis: Vec<_> = description.services["text"]
+        .keys()
+        .map(String::as_str)
+        .collect();
+    assert_eq!(text_apis, ["split", "upper"]);
+    assert!(description.api(RESERVED_SERVICE, "describe").is_some());
+
+    let upper = description.api("text", "upper").unwrap();
+    assert_eq!(
+        upper.schema,
+        Some(ApiSchema {
+            request: json!({ "type": "string" }),
+            reply: json!({ "type": "string" }),
+        })
+    );
+    assert_eq!(description.api("math", "add").unwrap().schema, None);
+    assert_eq!(description, router().describe());
+}
+
+#[test]
+fn describe_is_printable() {
+    let description = router().describe();
+    let printed = description.to_string();
+    assert_eq!(
+        printed,
+        "math\n  add (v1)\nserde_handler\n  describe (v1)\ntext\n  split (v1)\n  upper (v1)\n    \
+         request: {\"type\":\"string\"}\n    reply  : {\"type\":\"string\"}\n"
+    );
+}
+
+#[test]
+fn describe_runs_through_middleware() {
+    let (requester, responder) = channel::new_pair();
+    thread::spawn(move || {
+        router()
+            .layer(|request: &Message, next: Next<'_>| {
+                if request.api_name == Describe::NAME {
+                    return Err("Introspection is disabled".to_string());
+                }
+                next.run(request)
+            })
+            .serve_on(responder)
+    });
+
+    assert!(requester.describe().is_err());
+    assert_eq!(requester.request(AddRequest(1, 2)).unwrap(), 3);
+}
+
+#[test]
+fn describe_follows_authorization_rules_and_limits() {
+    let (requester, responder) = channel::new_pair();
+    let tokens = Tokens::new()
+        .token("admin-token", "admin")
+        .token("user-token", "bob");
+    let responder = responder.with_authenticator(tokens);
+    thread::spawn(move || {
+        router()
+            .authorize::<Describe>(Rule::principals(["admin"]))
+            .limit::<Describe>(Limits::new().rate(1, Duration::from_secs(60)))
+            .serve_on(responder)
+    });
+
+    let user = requester.with_token("user-token");
+    assert_eq!(
+        user.describe(),
+        Err(Error::Unauthorized(
+            "Not allowed to request 'describe' in service 'serde_handler'".to_string()
+        ))
+    );
+    assert_eq!(user.request(AddRequest(1, 2)).unwrap(), 3);
+
+    let admin = Requester::new(user.into_transport()).with_token("admin-token");
+    assert!(admin.describe().is_ok());
+    assert_eq!(admin.describe().unwrap_err().name(), "overloaded");
+}
+
+#[test]
+fn reserved_service() {
+    assert!(ApiRouter::new().serves(RESERVED_SERVICE));
+}
+
+#[test]
+#[should_panic(expected = "Service 'serde_handler' is reserved")]
+fn reserved_service_cannot_be_registered() {
+    let _ = ApiRouter::new().register_handler::<Describe, _>(|_| Default::default());
+}
+
+#[test]
+fn schemas_of_std_types() {
+    assert_eq!(
+        <Option<Vec<u32>>>::json_schema(),
+        json!({
+            "anyOf": [{ "type": "array", "items": { "type": "integer" } }, { "type": "null" }]
+        })
+    );
+    assert_eq!(<()>::json_schema(), json!({ "type": "null" }));
+}
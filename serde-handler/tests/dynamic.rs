@@ -0,0 +1,152 @@
+use std::{sync::mpsc, thread};
+
+use serde::{Deserialize, Serialize};
+
+use serde_handler::{
+    api::*,
+    channel::{self, Error, Requester},
+    dynamic::{RouteInfo, RouterHandle},
+};
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct Upper<'a>(&'a str);
+
+impl Api for Upper<'_> {
+    type Reply<'de> = String;
+    type Request<'de> = Upper<'de>;
+
+    const NAME: &'static str = "upper";
+    const SERVICE: &'static str = "text";
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct Reverse<'a>(&'a str);
+
+impl Api for Reverse<'_> {
+    type Reply<'de> = String;
+    type Request<'de> = Reverse<'de>;
+
+    const NAME: &'static str = "reverse";
+    const SERVICE: &'static str = "plugins";
+}
+
+/// Loads the plugin with the given name.
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct Load<'a>(&'a str);
+
+impl Api for Load<'_> {
+    type Reply<'de> = bool;
+    type Request<'de> = Load<'de>;
+
+    const NAME: &'static str = "load";
+    const SERVICE: &'static str = "plugins";
+}
+
+fn route(service: &str, api_name: &str, dynamic: bool) -> RouteInfo {
+    RouteInfo {
+        service: service.to_string(),
+        api_name: api_name.to_string(),
+        version: 1,
+        dynamic,
+    }
+}
+
+/// Serves the router built by `router` on another thread, returning a handle to its routes.
+fn serve(router: impl FnOnce() -> ApiRouter + Send + 'static) -> (Requester, RouterHandle) {
+    let (requester, responder) = channel::new_pair();
+    let (send_handle, handle) = mpsc::channel();
+    thread::spawn(move || {
+        let router = router();
+        send_handle.send(router.handle()).unwrap();
+        router.serve_on(responder)
+    });
+    (requester, handle.recv().unwrap())
+}
+
+#[test]
+fn handlers_can_be_registered_while_serving() {
+    let (requester, handle) = serve(ApiRouter::new);
+
+    assert_eq!(
+        requester.request(Reverse("abc")),
+        Err(Error::Other("Unknown service 'plugins'".to_string()))
+    );
+    handle
+        .register::<Reverse, _>(|req| req.0.chars().rev().collect())
+        .unwrap();
+    assert_eq!(requester.request(Reverse("abc")).unwrap(), "cba");
+    assert_eq!(
+        handle.register::<Reverse, _>(|req| req.0.to_string()),
+        Err("Duplicate handler for 'reverse' in service 'plugins' at version 1".to_string())
+    );
+}
+
+#[test]
+fn handlers_can_be_replaced_and_removed() {
+    let (requester, handle) =
+        serve(|| ApiRouter::new().register_handler::<Upper, _>(|req| req.0.to_uppercase()));
+
+    // Replacing a route of the router overrides it
+    assert!(handle
+        .replace::<Upper, _>(|req| format!("{}!", req.0.to_uppercase()))
+        .is_none());
+    assert_eq!(requester.request(Upper("abc")).unwrap(), "ABC!");
+    assert_eq!(handle.routes(), [route("text", "upper", true)]);
+
+    let previous = handle
+        .replace::<Upper, _>(|req| format!("{}?", req.0.to_uppercase()))
+        .unwrap();
+    assert_eq!(
+        (previous.service(), previous.api_name(), previous.version()),
+        ("text", "upper", 1)
+    );
+    assert_eq!(requester.request(Upper("abc")).unwrap(), "ABC?");
+    handle.restore(previous);
+    assert_eq!(requester.request(Upper("abc")).unwrap(), "ABC!");
+
+    // Removing the handler falls back to the route of the router
+    assert!(handle.remove::<Upper>().is_some());
+    assert!(handle.remove::<Upper>().is_none());
+    assert_eq!(requester.request(Upper("abc")).unwrap(), "ABC");
+    assert_eq!(handle.routes(), [route("text", "upper", false)]);
+}
+
+#[test]
+fn handlers_can_register_handlers() {
+    let (requester, handle) = serve(ApiRouter::new);
+    let plugins = handle.clone();
+    handle
+        .register::<Load, _>(move |req| match req.0 {
+            "reverse" => plugins
+                .register::<Reverse, _>(|req| req.0.chars().rev().collect())
+                .is_ok(),
+            _ => false,
+        })
+        .unwrap();
+
+    assert!(!requester.request(Load("shout")).unwrap());
+    assert!(requester.request(Load("reverse")).unwrap());
+    assert!(!requester.request(Load("reverse")).unwrap());
+    assert_eq!(requester.request(Reverse("abc")).unwrap(), "cba");
+    assert_eq!(
+        handle.routes(),
+        [
+            route("plugins", "load", true),
+            route("plugins", "reverse", true)
+        ]
+    );
+}
+
+#[test]
+fn dynamic_routes_are_described() {
+    let router = ApiRouter::new().register_handler::<Upper, _>(|req| req.0.to_uppercase());
+    router
+        .handle()
+        .register::<Reverse, _>(|req| req.0.chars().rev().collect())
+        .unwrap();
+
+    assert!(router.serves("plugins"));
+    let description = router.describe();
+    assert_eq!(description.services["plugins"]["reverse"].versions, [1]);
+    assert_eq!(description.services["text"]["upper"].versions, [1]);
+}
@@ -0,0 +1,191 @@
+use std::{
+    sync::mpsc::{self, Sender},
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use serde::{Deserialize, Serialize};
+
+use serde_handler::{
+    api::*,
+    auth::{Rule, Tokens},
+    channel::{self, Error},
+    event::{Event, EventBus},
+};
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct UserCreated<'a> {
+    name: &'a str,
+}
+
+impl Event for UserCreated<'_> {
+    type Payload<'de> = UserCreated<'de>;
+
+    const NAME: &'static str = "user_created";
+    const SERVICE: &'static str = "accounts";
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct UserDeleted(u64);
+
+impl Event for UserDeleted {
+    type Payload<'de> = UserDeleted;
+
+    const NAME: &'static str = "user_deleted";
+    const SERVICE: &'static str = "accounts";
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct Ping;
+
+impl Api for Ping {
+    type Reply<'de> = String;
+    type Request<'de> = Ping;
+
+    const NAME: &'static str = "ping";
+    const SERVICE: &'static str = "accounts";
+}
+
+/// A bus whose subscribers report what they received to `received`.
+fn bus(received: Sender<String>) -> EventBus {
+    let (mailer, audit, deletions) = (received.clone(), received.clone(), received);
+    EventBus::new()
+        .subscribe::<UserCreated, _>(move |event| {
+            mailer.send(format!("welcome {}", event.name)).unwrap();
+        })
+        .subscribe::<UserCreated, _>(move |event| {
+            audit.send(format!("created {}", event.name)).unwrap();
+        })
+        .subscribe::<UserDeleted, _>(move |event| {
+            deletions.send(format!("deleted {}", event.0)).unwrap();
+        })
+}
+
+#[test]
+fn events_are_delivered_to_all_subscribers_of_their_topic() {
+    let (received, receive) = mpsc::channel();
+    let (requester, responder) = channel::new_pair();
+    thread::spawn(move || bus(received).serve_on(responder));
+
+    requester.publish(UserCreated { name: "alice" }).unwrap();
+    requester.publish(UserDeleted(7)).unwrap();
+    drop(requester);
+
+    assert_eq!(
+        receive.iter().collect::<Vec<_>>(),
+        ["welcome alice", "created alice", "deleted 7"]
+    );
+}
+
+#[test]
+fn events_are_not_answered() {
+    let (received, receive) = mpsc::channel();
+    let (requester, responder) = channel::new_pair();
+    thread::spawn(move || bus(received).serve_on(responder));
+
+    for i in 0..5 {
+        requester.publish(UserDeleted(i)).unwrap();
+    }
+    assert_eq!(
+        requester.request(Ping),
+        Err(Error::Other(
+            "'ping' in service 'accounts' cannot be requested from an event bus".to_string()
+        ))
+    );
+    assert_eq!(receive.try_iter().count(), 5);
+}
+
+#[test]
+fn routers_deliver_events_in_between_requests() {
+    let (received, receive) = mpsc::channel();
+    let (requester, responder) = channel::new_pair();
+    thread::spawn(move || {
+        ApiRouter::new()
+            .register_handler::<Ping, _>(|_| "pong".to_string())
+            .with_events(bus(received))
+            .serve_on(responder)
+    });
+
+    requester.publish(UserCreated { name: "bob" }).unwrap();
+    assert_eq!(requester.request(Ping).unwrap(), "pong");
+    assert_eq!(
+        receive.try_iter().collect::<Vec<_>>(),
+        ["welcome bob", "created bob"]
+    );
+}
+
+#[test]
+fn unauthorized_events_are_dropped() {
+    let (received, receive) = mpsc::channel();
+    let (requester, responder) = channel::new_pair();
+    let tokens = Tokens::new()
+        .token("admin-token", "admin")
+        .token("bob-token", "bob");
+    thread::spawn(move || {
+        ApiRouter::new()
+            .register_handler::<Ping, _>(|_| "pong".to_string())
+            .with_events(bus(received))
+            .authorize_event::<UserDeleted>(Rule::principals(["admin"]))
+            .serve_on(responder.with_authenticator(tokens))
+    });
+
+    let bob = requester.with_token("bob-token");
+    bob.publish(UserDeleted(1)).unwrap();
+    bob.publish(UserCreated { name: "bob" }).unwrap();
+    let admin = bob.with_token("admin-token");
+    admin.publish(UserDeleted(2)).unwrap();
+    assert_eq!(admin.request(Ping).unwrap(), "pong");
+    assert_eq!(
+        receive.try_iter().collect::<Vec<_>>(),
+        ["welcome bob", "created bob", "deleted 2"]
+    );
+}
+
+#[test]
+fn buses_deliver_events_published_on_them() {
+    let (received, receive) = mpsc::channel();
+    let mut bus = bus(received);
+
+    assert_eq!(bus.publish(UserCreated { name: "carol" }).unwrap(), 2);
+    assert_eq!(bus.publish(UserDeleted(3)).unwrap(), 1);
+    assert_eq!(receive.try_iter().count(), 3);
+    assert_eq!(EventBus::new().publish(UserDeleted(3)).unwrap(), 0);
+}
+
+#[test]
+fn routers_without_an_event_bus_drop_events() {
+    let (requester, responder) = channel::new_pair();
+    thread::spawn(move || {
+        ApiRouter::new()
+            .register_handler::<Ping, _>(|_| "pong".to_string())
+            .serve_on(responder)
+    });
+
+    for i in 0..5 {
+        requester.publish(UserDeleted(i)).unwrap();
+    }
+    assert_eq!(requester.request(Ping).unwrap(), "pong");
+}
+
+#[test]
+fn expired_events_are_dropped() {
+    let (received, receive) = mpsc::channel();
+    let (requester, responder) = channel::new_pair();
+    thread::spawn(move || bus(received).serve_on(responder));
+
+    requester
+        .publish_with_deadline(
+            UserDeleted(1),
+            Some(SystemTime::now() - Duration::from_secs(1)),
+        )
+        .unwrap();
+    requester
+        .publish_with_deadline(
+            UserDeleted(2),
+            Some(SystemTime::now() + Duration::from_secs(60)),
+        )
+        .unwrap();
+    drop(requester);
+
+    assert_eq!(receive.iter().collect::<Vec<_>>(), ["deleted 2"]);
+}
@@ -0,0 +1,179 @@
+use std::thread;
+
+use serde::{Deserialize, Serialize};
+
+use serde_handler::{
+    api::*,
+    channel::{self, Error, Message, Requester},
+    extract::Context,
+    headers::{Headers, CORRELATION_ID, REQUEST_ID},
+    middleware::Next,
+};
+
+/// Replies with the headers of the request.
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct EchoHeaders;
+
+impl Api for EchoHeaders {
+    type Reply<'de> = Vec<(String, String)>;
+    type Request<'de> = EchoHeaders;
+
+    const NAME: &'static str = "echo";
+    const SERVICE: &'static str = "headers";
+}
+
+/// Replies with the correlation ID of the request.
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct Correlation;
+
+impl Api for Correlation {
+    type Reply<'de> = Option<String>;
+    type Request<'de> = Correlation;
+
+    const NAME: &'static str = "correlation";
+    const SERVICE: &'static str = "backend";
+}
+
+/// Asks the backend for its [`Correlation`].
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct Forward;
+
+impl Api for Forward {
+    type Reply<'de> = Option<String>;
+    type Request<'de> = Forward;
+
+    const NAME: &'static str = "forward";
+    const SERVICE: &'static str = "frontend";
+}
+
+/// Echoes all headers but the correlation ID, with the generated request ID replaced by `<id>`.
+fn echo_router() -> ApiRouter {
+    ApiRouter::new().register_handler_with::<EchoHeaders, _, _>(|headers: Headers, _| {
+        headers
+            .into_iter()
+            .filter(|(name, _)| name != CORRELATION_ID)
+            .map(|(name, value)| match name.as_str() {
+                REQUEST_ID => (name, "<id>".to_string()),
+                _ => (name, value),
+            })
+            .collect()
+    })
+}
+
+fn pairs(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+    pairs
+        .iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect()
+}
+
+#[test]
+fn requests_carry_client_and_call_headers() {
+    let (requester, responder) = channel::new_pair();
+    thread::spawn(move || echo_router().serve_on(responder));
+    let requester = requester
+        .with_header("user", "alice")
+        .with_header("locale", "en");
+
+    let echoed = requester.request(EchoHeaders).unwrap();
+    assert_eq!(
+        echoed,
+        pairs(&[("locale", "en"), (REQUEST_ID, "<id>"), ("user", "alice")])
+    );
+
+    let call_headers = Headers::from_iter([("user", "bob"), ("trace", "1")]);
+    let echoed = requester
+        .request_with_headers(EchoHeaders, call_headers)
+        .unwrap();
+    assert_eq!(
+        echoed,
+        pairs(&[
+            ("locale", "en"),
+            (REQUEST_ID, "<id>"),
+            ("trace", "1"),
+            ("user", "bob")
+        ])
+    );
+}
+
+#[test]
+fn middleware_can_check_headers() {
+    let (mut requester, responder) = channel::new_pair();
+    thread::spawn(move || {
+        echo_router()
+            .layer(
+                |request: &Message, next: Next<'_>| match request.headers.get("token") {
+                    Some("secret") => next.run(request),
+                    _ => Err("Unauthorized".to_string()),
+                },
+            )
+            .serve_on(responder)
+    });
+
+    assert_eq!(
+        requester.request(EchoHeaders),
+        Err(Error::Other("Unauthorized".to_string()))
+    );
+    requester.headers_mut().insert("token", "secret");
+    assert_eq!(
+        requester.request(EchoHeaders).unwrap(),
+        pairs(&[(REQUEST_ID, "<id>"), ("token", "secret")])
+    );
+}
+
+#[test]
+fn replies_echo_only_the_correlation_id() {
+    let (requester, responder) = channel::new_pair();
+    thread::spawn(move || echo_router().serve_on(responder));
+
+    let reply = requester
+        .send_request(Message {
+            service: "headers".to_string(),
+            api_name: "echo".to_string(),
+            headers: Headers::from_iter([(CORRELATION_ID, "abc"), ("user", "alice")]),
+            data: b"null".to_vec(),
+            ..Default::default()
+        })
+        .unwrap();
+    assert_eq!(reply.headers, Headers::from_iter([(CORRELATION_ID, "abc")]));
+
+    // Requests without one get a new correlation ID
+    let reply = requester
+        .send_request(Message {
+            service: "headers".to_string(),
+            api_name: "echo".to_string(),
+            data: b"null".to_vec(),
+            ..Default::default()
+        })
+        .unwrap();
+    assert!(reply.headers.correlation_id().is_some());
+}
+
+#[test]
+fn correlation_ids_propagate_across_services() {
+    let (backend, backend_responder) = channel::new_pair();
+    thread::spawn(move || {
+        ApiRouter::new()
+            .register_handler_with::<Correlation, _, _>(|cx: Context, _| {
+                cx.headers.correlation_id().map(str::to_string)
+            })
+            .serve_on(backend_responder)
+    });
+    let (requester, responder) = channel::new_pair();
+    thread::spawn(move || {
+        ApiRouter::with_state(backend)
+            .register_stateful_handler::<Forward, _>(|backend: &mut Requester, _| {
+                backend.request(Correlation).unwrap()
+            })
+            .serve_on(responder)
+    });
+
+    let headers = Headers::from_iter([(CORRELATION_ID, "request-1")]);
+    let forwarded = requester.request_with_headers(Forward, headers).unwrap();
+    assert_eq!(forwarded.as_deref(), Some("request-1"));
+
+    // Every request from outside a handler starts a new correlation
+    let first = requester.request(Forward).unwrap().unwrap();
+    let second = requester.request(Forward).unwrap().unwrap();
+    assert_ne!(first, second);
+}
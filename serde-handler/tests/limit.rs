@@ -0,0 +1,291 @@
+use std::{
+    cell::RefCell,
+    collections::VecDeque,
+    net::TcpListener,
+    rc::Rc,
+    sync::{mpsc, Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+
+use serde_handler::{
+    api::*,
+    channel::{self, Error, Kind, Message, Overload, Responder},
+    limit::{Clock, Limits},
+    transport::{Server, ShutdownHandle, Transport},
+};
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct Render(u32);
+
+impl Api for Render {
+    type Reply<'de> = u32;
+    type Request<'de> = Render;
+
+    const NAME: &'static str = "render";
+    const SERVICE: &'static str = "images";
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct Ping;
+
+impl Api for Ping {
+    type Reply<'de> = ();
+    type Request<'de> = Ping;
+
+    const NAME: &'static str = "ping";
+    const SERVICE: &'static str = "images";
+}
+
+/// A clock that only moves when told to.
+#[derive(Clone)]
+struct FakeClock(Arc<Mutex<Instant>>);
+
+impl FakeClock {
+    fn new() -> Self {
+        Self(Arc::new(Mutex::new(Instant::now())))
+    }
+
+    fn advance(&self, duration: Duration) {
+        *self.0.lock().unwrap() += duration;
+    }
+}
+
+impl Clock for FakeClock {
+    fn now(&self) -> Instant {
+        *self.0.lock().unwrap()
+    }
+}
+
+/// A transport whose requests have all arrived before serving starts.
+struct Arrived {
+    requests: RefCell<VecDeque<Message>>,
+    sent: Rc<RefCell<Vec<Message>>>,
+}
+
+impl Transport for Arrived {
+    fn send(&self, message: Message) -> Result<(), String> {
+        self.sent.borrow_mut().push(message);
+        Ok(())
+    }
+
+    fn recv(&self) -> Result<Message, String> {
+        Ok(self
+            .requests
+            .borrow_mut()
+            .pop_front()
+            .unwrap_or_else(Message::shutdown))
+    }
+
+    fn recv_timeout(&self, _: Duration) -> Result<Option<Message>, String> {
+        self.recv().map(Some)
+    }
+
+    fn shutdown_handle(&self) -> Result<ShutdownHandle, String> {
+        Err("Not supported".to_string())
+    }
+}
+
+fn request<A: Api>(id: u64, request: A) -> Message {
+    Message {
+        id,
+        service: A::SERVICE.to_string(),
+        api_name: A::NAME.to_string(),
+        data: serde_json::to_vec(&request).unwrap(),
+        ..Default::default()
+    }
+}
+
+fn overloaded(limit: Overload, retry_after: Option<Duration>) -> Error {
+    Error::Overloaded { limit, retry_after }
+}
+
+#[test]
+fn rate_limits_refill_over_time() {
+    let clock = FakeClock::new();
+    let limits = Limits::new()
+        .rate(2, Duration::from_secs(1))
+        .with_clock(clock.clone());
+    let (requester, responder) = channel::new_pair();
+    thread::spawn(move || {
+        ApiRouter::new()
+            .register_handler::<Render, _>(|req| req.0)
+            .register_handler::<Ping, _>(|_| ())
+            .limit::<Render>(limits)
+            .serve_on(responder)
+    });
+
+    assert_eq!(requester.request(Render(1)), Ok(1));
+    assert_eq!(requester.request(Render(2)), Ok(2));
+    assert_eq!(
+        requester.request(Render(3)),
+        Err(overloaded(Overload::Rate, Some(Duration::from_millis(500))))
+    );
+    // Other APIs are not limited
+    assert_eq!(requester.request(Ping), Ok(()));
+
+    clock.advance(Duration::from_millis(300));
+    assert_eq!(
+        requester.request(Render(3)),
+        Err(overloaded(Overload::Rate, Some(Duration::from_millis(200))))
+    );
+    clock.advance(Duration::from_millis(200));
+    assert_eq!(requester.request(Render(3)), Ok(3));
+    assert!(requester.request(Render(4)).is_err());
+
+    // A quiet period allows a burst again, but no larger than the limit
+    clock.advance(Duration::from_secs(10));
+    assert_eq!(requester.request(Render(4)), Ok(4));
+    assert_eq!(requester.request(Render(5)), Ok(5));
+    assert!(requester.request(Render(6)).is_err());
+}
+
+#[test]
+fn rate_limits_accept_extreme_configurations() {
+    let clock = FakeClock::new();
+    let many = Limits::new()
+        .rate(u32::MAX, Duration::from_secs(u32::MAX.into()))
+        .with_clock(clock.clone());
+    let rare = Limits::new()
+        .rate(1, Duration::MAX)
+        .with_clock(clock.clone());
+    let (requester, responder) = channel::new_pair();
+    thread::spawn(move || {
+        ApiRouter::new()
+            .register_handler::<Render, _>(|req| req.0)
+            .register_handler::<Ping, _>(|_| ())
+            .limit::<Render>(many)
+            .limit::<Ping>(rare)
+            .serve_on(responder)
+    });
+
+    assert_eq!(requester.request(Render(1)), Ok(1));
+    clock.advance(Duration::from_secs(2));
+    assert_eq!(requester.request(Render(2)), Ok(2));
+
+    assert_eq!(requester.request(Ping), Ok(()));
+    assert_eq!(
+        requester.request(Ping),
+        Err(overloaded(Overload::Rate, Some(Duration::MAX)))
+    );
+}
+
+#[test]
+fn concurrency_limits_are_shared_between_routers() {
+    let limits = Limits::new().max_concurrency(1);
+    let (started, wait_started) = mpsc::channel();
+    let (release, wait_release) = mpsc::channel::<()>();
+    let wait_release = Arc::new(Mutex::new(wait_release));
+    let serve = |responder: Responder| {
+        let limits = limits.clone();
+        let started = started.clone();
+        let wait_release = Arc::clone(&wait_release);
+        thread::spawn(move || {
+            ApiRouter::new()
+                .register_handler::<Render, _>(move |req| {
+                    started.send(()).unwrap();
+                    wait_release.lock().unwrap().recv().unwrap();
+                    req.0
+                })
+                .limit::<Render>(limits)
+                .serve_on(responder)
+        });
+    };
+    let (first, responder) = channel::new_pair();
+    serve(responder);
+    let (second, responder) = channel::new_pair();
+    serve(responder);
+
+    let slow = thread::spawn(move || first.request(Render(1)));
+    wait_started.recv().unwrap();
+    assert_eq!(
+        second.request(Render(2)),
+        Err(overloaded(Overload::Concurrency, None))
+    );
+    release.send(()).unwrap();
+    assert_eq!(slow.join().unwrap(), Ok(1));
+
+    release.send(()).unwrap();
+    assert_eq!(second.request(Render(2)), Ok(2));
+}
+
+#[test]
+fn queue_limits_reject_the_backlog() {
+    let sent = Rc::new(RefCell::new(Vec::new()));
+    let requests = [
+        request(0, Render(0)),
+        request(1, Render(1)),
+        request(2, Ping),
+        request(3, Render(3)),
+        request(4, Render(4)),
+    ];
+    let responder = Responder::new(Arrived {
+        requests: RefCell::new(requests.into()),
+        sent: Rc::clone(&sent),
+    });
+    ApiRouter::new()
+        .register_handler::<Render, _>(|req| req.0)
+        .register_handler::<Ping, _>(|_| ())
+        .limit::<Render>(Limits::new().max_queued(1))
+        .serve_on(responder)
+        .unwrap();
+
+    let replies: Vec<_> = sent
+        .borrow()
+        .iter()
+        .map(|reply| (reply.id, reply.kind))
+        .collect();
+    // While the first request is handled, the second one waits, and the others are rejected.
+    assert_eq!(
+        replies,
+        [
+            (3, Kind::Error),
+            (4, Kind::Error),
+            (0, Kind::Reply),
+            (1, Kind::Reply),
+            (2, Kind::Reply),
+        ]
+    );
+    assert_eq!(
+        serde_json::from_slice::<Error>(&sent.borrow()[0].data).unwrap(),
+        overloaded(Overload::Queue, None)
+    );
+}
+
+#[test]
+fn queued_requests_are_answered_to_their_client() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let responder = Responder::new(Server::tcp(listener));
+    thread::spawn(move || {
+        ApiRouter::new()
+            .register_handler::<Render, _>(|req| {
+                // Slow enough for the other requests to queue up meanwhile.
+                thread::sleep(Duration::from_millis(20));
+                req.0
+            })
+            .limit::<Render>(Limits::new().max_queued(50))
+            .serve_on(responder)
+    });
+
+    let clients: Vec<_> = (0..10)
+        .map(|i| {
+            thread::spawn(move || {
+                let requester = channel::connect_tcp(addr).unwrap();
+                (0..3)
+                    .map(|j| {
+                        requester.request_with_timeout(Render(i * 10 + j), Duration::from_secs(10))
+                    })
+                    .collect::<Vec<_>>()
+            })
+        })
+        .collect();
+    for (i, client) in (0..).zip(clients) {
+        assert_eq!(
+            client.join().unwrap(),
+            [Ok(i * 10), Ok(i * 10 + 1), Ok(i * 10 + 2)]
+        );
+    }
+}
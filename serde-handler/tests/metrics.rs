@@ -0,0 +1,178 @@
+use std::{
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+use serde_handler::{
+    api::*,
+    channel::{self, Error, Message},
+    metrics::{Labels, Metrics, PrometheusMetrics, Side},
+};
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct UppercaseRequest<'a>(&'a str);
+
+impl Api for UppercaseRequest<'_> {
+    type Reply<'de> = String;
+    type Request<'de> = UppercaseRequest<'de>;
+
+    const NAME: &'static str = "upper";
+    const SERVICE: &'static str = "text";
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct Missing;
+
+impl Api for Missing {
+    type Reply<'de> = ();
+    type Request<'de> = Missing;
+
+    const NAME: &'static str = "missing";
+    const SERVICE: &'static str = "text";
+}
+
+/// Records every measurement as a line of text.
+#[derive(Clone, Default)]
+struct Recorded(Arc<Mutex<Vec<String>>>);
+
+impl Metrics for Recorded {
+    fn request_started(&self, labels: Labels<'_>, size: usize) {
+        let line = format!("{:?} start {} {size}", labels.side, labels.api_name);
+        self.0.lock().unwrap().push(line);
+    }
+
+    fn request_finished(&self, labels: Labels<'_>, _: Duration, outcome: Result<usize, &Error>) {
+        let outcome = match outcome {
+            Ok(size) => size.to_string(),
+            Err(error) => error.name().to_string(),
+        };
+        let line = format!("{:?} finish {} {outcome}", labels.side, labels.api_name);
+        self.0.lock().unwrap().push(line);
+    }
+}
+
+fn text_service_router() -> ApiRouter {
+    ApiRouter::new().register_handler::<UppercaseRequest, _>(|req| req.0.to_uppercase())
+}
+
+#[test]
+fn requesters_and_routers_report_requests() {
+    let recorded = Recorded::default();
+    let (requester, responder) = channel::new_pair();
+    let router_metrics = recorded.clone();
+    let service = thread::spawn(move || {
+        text_service_router()
+            .with_metrics(router_metrics)
+            .serve_on(responder)
+    });
+    let requester = requester.with_metrics(recorded.clone());
+
+    assert_eq!(requester.request(UppercaseRequest("abc")).unwrap(), "ABC");
+    assert!(requester.request(Missing).is_err());
+    drop(requester);
+    service.join().unwrap().unwrap();
+
+    assert_eq!(
+        *recorded.0.lock().unwrap(),
+        [
+            "Client start upper 5",
+            "Server start upper 5",
+            "Server finish upper 5",
+            "Client finish upper 5",
+            "Client start missing 4",
+            "Server start unknown 4",
+            "Server finish unknown other",
+            "Client finish missing other",
+        ]
+    );
+}
+
+#[test]
+fn prometheus_metrics_are_rendered_per_api() {
+    let metrics = PrometheusMetrics::new();
+    let (requester, responder) = channel::new_pair();
+    let router_metrics = metrics.clone();
+    thread::spawn(move || {
+        text_service_router()
+            .with_metrics(router_metrics)
+            .serve_on(responder)
+    });
+
+    assert_eq!(requester.request(UppercaseRequest("abc")).unwrap(), "ABC");
+    assert_eq!(requester.request(UppercaseRequest("de")).unwrap(), "DE");
+    assert!(requester.request(Missing).is_err());
+
+    let rendered = metrics.render();
+    let upper = r#"side="server",service="text",api="upper""#;
+    // Requests for routes that are not served are measured together.
+    let missing = r#"side="server",service="unknown",api="unknown""#;
+    for line in [
+        "# TYPE serde_handler_requests_total counter".to_string(),
+        format!("serde_handler_requests_total{{{upper}}} 2"),
+        format!("serde_handler_requests_total{{{missing}}} 1"),
+        format!("serde_handler_errors_total{{{missing},error=\"other\"}} 1"),
+        format!("serde_handler_in_flight{{{upper}}} 0"),
+        "# TYPE serde_handler_duration_seconds histogram".to_string(),
+        format!("serde_handler_duration_seconds_count{{{upper}}} 2"),
+        format!("serde_handler_request_bytes_bucket{{{upper},le=\"64\"}} 2"),
+        format!("serde_handler_request_bytes_sum{{{upper}}} 9"),
+        format!("serde_handler_reply_bytes_bucket{{{upper},le=\"+Inf\"}} 2"),
+        format!("serde_handler_reply_bytes_count{{{missing}}} 0"),
+    ] {
+        assert!(rendered.lines().any(|l| l == line), "{line}\n{rendered}");
+    }
+    assert!(!rendered.contains(r#"api="upper",error"#));
+    assert!(!rendered.contains(r#"api="missing""#));
+}
+
+#[test]
+fn label_values_are_escaped() {
+    let metrics = PrometheusMetrics::new();
+    let labels = Labels {
+        side: Side::Client,
+        service: "a\"b",
+        api_name: "c\\d\ne",
+    };
+    metrics.request_started(labels, 0);
+
+    assert!(metrics
+        .render()
+        .contains(r#"serde_handler_in_flight{side="client",service="a\"b",api="c\\d\ne"} 1"#));
+}
+
+#[test]
+fn batched_requests_are_measured() {
+    let recorded = Recorded::default();
+    let (requester, responder) = channel::new_pair();
+    let router_metrics = recorded.clone();
+    thread::spawn(move || {
+        text_service_router()
+            .with_metrics(router_metrics)
+            .serve_on(responder)
+    });
+
+    let request = |text: &str| Message {
+        service: "text".to_string(),
+        api_name: "upper".to_string(),
+        data: serde_json::to_vec(text).unwrap(),
+        ..Default::default()
+    };
+    let batch = Message {
+        kind: channel::Kind::Batch,
+        data: Message::encode_batch(&[request("a"), request("b")]),
+        ..Default::default()
+    };
+    requester.send_request(batch).unwrap();
+
+    let recorded = recorded.0.lock().unwrap();
+    assert_eq!(
+        recorded
+            .iter()
+            .filter(|line| line.starts_with("Server finish upper"))
+            .count(),
+        2
+    );
+}
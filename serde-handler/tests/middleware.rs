@@ -0,0 +1,137 @@
+use std::{
+    sync::{Arc, Mutex},
+    thread,
+};
+
+use serde::{Deserialize, Serialize};
+
+use serde_handler::{
+    api::*,
+    channel::{self, Error, Message},
+    middleware::{CatchUnwind, Logging, Next, Timing},
+};
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct UppercaseRequest<'a>(&'a str);
+
+impl Api for UppercaseRequest<'_> {
+    type Reply<'de> = String;
+    type Request<'de> = UppercaseRequest<'de>;
+
+    const NAME: &'static str = "upper";
+    const SERVICE: &'static str = "text";
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct PanicRequest;
+
+impl Api for PanicRequest {
+    type Reply<'de> = ();
+    type Request<'de> = PanicRequest;
+
+    const NAME: &'static str = "panic";
+    const SERVICE: &'static str = "text";
+}
+
+fn text_service_router() -> ApiRouter {
+    ApiRouter::new()
+        .register_handler::<UppercaseRequest, _>(|req| req.0.to_uppercase())
+        .register_handler::<PanicRequest, _>(|_| panic!("handler failed"))
+}
+
+#[test]
+fn middleware_runs_in_order() {
+    let calls = Arc::new(Mutex::new(Vec::new()));
+    let (requester, responder) = channel::new_pair();
+
+    let layer = |name: &'static str| {
+        let calls = Arc::clone(&calls);
+        move |request: &Message, next: Next<'_>| {
+            calls
+                .lock()
+                .unwrap()
+                .push(format!("{name} before {}", request.api_name));
+            let reply = next.run(request);
+            calls.lock().unwrap().push(format!("{name} after"));
+            reply
+        }
+    };
+    let (first, second) = (layer("first"), layer("second"));
+    thread::spawn(move || {
+        text_service_router()
+            .layer(first)
+            .layer(second)
+            .serve_on(responder)
+    });
+
+    assert_eq!(requester.request(UppercaseRequest("abc")).unwrap(), "ABC");
+    assert_eq!(
+        *calls.lock().unwrap(),
+        [
+            "first before upper",
+            "second before upper",
+            "second after",
+            "first after"
+        ]
+    );
+}
+
+#[test]
+fn middleware_can_reject_and_rewrite() {
+    let (requester, responder) = channel::new_pair();
+    thread::spawn(move || {
+        text_service_router()
+            .layer(|request: &Message, next: Next<'_>| {
+                if request.data.len() > 16 {
+                    return Err(format!("Request too large: {} bytes", request.data.len()));
+                }
+                let mut reply = next.run(request)?;
+                reply.splice(1..1, *b"> ");
+                Ok(reply)
+            })
+            .serve_on(responder)
+    });
+
+    assert_eq!(requester.request(UppercaseRequest("abc")).unwrap(), "> ABC");
+    let error = requester
+        .request(UppercaseRequest("this is too long"))
+        .unwrap_err();
+    assert_eq!(
+        error,
+        Error::Other("Request too large: 18 bytes".to_string())
+    );
+}
+
+#[test]
+fn timing_reports_every_request() {
+    let timings = Arc::new(Mutex::new(Vec::new()));
+    let (requester, responder) = channel::new_pair();
+    let report = {
+        let timings = Arc::clone(&timings);
+        move |request: &Message, _| timings.lock().unwrap().push(request.api_name.clone())
+    };
+    thread::spawn(move || {
+        text_service_router()
+            .layer(Logging)
+            .layer(Timing::new(report))
+            .serve_on(responder)
+    });
+
+    requester.request(UppercaseRequest("a")).unwrap();
+    requester.request(UppercaseRequest("b")).unwrap();
+    assert_eq!(*timings.lock().unwrap(), ["upper", "upper"]);
+}
+
+#[test]
+fn panicking_handler_returns_error() {
+    let (requester, responder) = channel::new_pair();
+    thread::spawn(move || text_service_router().layer(CatchUnwind).serve_on(responder));
+
+    let error = requester.request(PanicRequest).unwrap_err();
+    assert_eq!(
+        error,
+        Error::Other("Handler for 'panic' panicked: handler failed".to_string())
+    );
+    // The router is still running
+    assert_eq!(requester.request(UppercaseRequest("abc")).unwrap(), "ABC");
+}
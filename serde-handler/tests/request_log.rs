@@ -0,0 +1,282 @@
+use std::{
+    fs,
+    path::PathBuf,
+    process, thread,
+    time::{Duration, SystemTime},
+};
+
+use serde::{Deserialize, Serialize};
+
+use serde_handler::{
+    api::*,
+    auth::Rule,
+    channel::{self, Error, Kind, Message},
+    headers::{AUTHORIZATION, KEY_ID, SIGNATURE},
+    middleware::CatchUnwind,
+    request_log::{self, RequestLog},
+};
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct Stats<'a>(&'a str);
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct TextStats {
+    words: usize,
+    longest: String,
+}
+
+impl Api for Stats<'_> {
+    type Reply<'de> = TextStats;
+    type Request<'de> = Stats<'de>;
+
+    const NAME: &'static str = "stats";
+    const SERVICE: &'static str = "text";
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct Parse<'a>(&'a str);
+
+impl Api for Parse<'_> {
+    type Reply<'de> = u32;
+    type Request<'de> = Parse<'de>;
+
+    const NAME: &'static str = "parse";
+    const SERVICE: &'static str = "text";
+}
+
+fn stats(text: &str) -> TextStats {
+    TextStats {
+        words: text.split_whitespace().count(),
+        longest: text
+            .split_whitespace()
+            .max_by_key(|word| word.len())
+            .unwrap_or_default()
+            .to_string(),
+    }
+}
+
+/// A router with a bug: of several longest words, `stats` reports the last one.
+fn buggy_router() -> ApiRouter {
+    ApiRouter::new()
+        .register_handler::<Stats, _>(|req| stats(req.0))
+        .register_handler::<Parse, _>(|req| req.0.parse().unwrap_or_default())
+}
+
+/// The same router with the bug fixed.
+fn fixed_router() -> ApiRouter {
+    ApiRouter::new()
+        .register_handler::<Stats, _>(|req| {
+            let mut stats = stats(req.0);
+            stats.longest = req
+                .0
+                .split_whitespace()
+                .fold("", |longest, word| {
+                    if word.len() > longest.len() {
+                        word
+                    } else {
+                        longest
+                    }
+                })
+                .to_string();
+            stats
+        })
+        .register_handler::<Parse, _>(|req| req.0.parse().unwrap_or_default())
+}
+
+fn log_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("serde-handler-{}-{name}.log", process::id()))
+}
+
+/// Serves the `router` with a [`RequestLog`] at `path` while `requests` runs.
+fn record(path: &PathBuf, router: fn() -> ApiRouter, requests: impl FnOnce(channel::Requester)) {
+    let (requester, responder) = channel::new_pair();
+    let log = RequestLog::open(path).unwrap();
+    let service = thread::spawn(move || router().layer(log).serve_on(responder));
+    requests(requester);
+    service.join().unwrap().unwrap();
+}
+
+#[test]
+fn requests_and_replies_are_appended_to_the_log() {
+    let path = log_path("append");
+    let _ = fs::remove_file(&path);
+
+    record(&path, buggy_router, |requester| {
+        requester.request(Stats("a bb cc")).unwrap();
+        let invalid = Message {
+            service: "text".to_string(),
+            api_name: "parse".to_string(),
+            data: b"{".to_vec(),
+            ..Default::default()
+        };
+        assert!(requester.send_request(invalid).is_err());
+    });
+    record(&path, buggy_router, |requester| {
+        requester.request(Parse("42")).unwrap();
+    });
+
+    let entries = RequestLog::read(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    let summary: Vec<_> = entries
+        .iter()
+        .map(|entry| {
+            let reply = match &entry.reply {
+                Ok(data) => String::from_utf8_lossy(data).into_owned(),
+                Err(e) => e.split(':').next().unwrap().to_string(),
+            };
+            (entry.request.api_name.as_str(), reply)
+        })
+        .collect();
+    assert_eq!(
+        summary,
+        [
+            ("stats", r#"{"words":3,"longest":"cc"}"#.to_string()),
+            ("parse", "Deserialize error".to_string()),
+            ("parse", "42".to_string()),
+        ]
+    );
+    assert!(entries.windows(2).all(|e| e[0].timestamp <= e[1].timestamp));
+    assert_eq!(entries[0].request.kind, Kind::Request);
+    assert_eq!(entries[0].request.data, br#""a bb cc""#);
+}
+
+#[test]
+fn rejected_requests_are_logged_with_their_error() {
+    let path = log_path("rejected");
+    let _ = fs::remove_file(&path);
+    fn strict_router() -> ApiRouter {
+        buggy_router().authorize::<Parse>(Rule::principals(["admin"]))
+    }
+    record(&path, strict_router, |requester| {
+        assert!(matches!(
+            requester.request(Parse("1")),
+            Err(Error::Unauthorized(_))
+        ));
+        let expired = Message {
+            service: "text".to_string(),
+            api_name: "stats".to_string(),
+            data: br#""a""#.to_vec(),
+            deadline: Some(SystemTime::now() - Duration::from_secs(1)),
+            ..Default::default()
+        };
+        assert!(requester.send_request(expired).is_err());
+        let unknown = Message {
+            service: "text".to_string(),
+            api_name: "count".to_string(),
+            ..Default::default()
+        };
+        assert!(requester.send_request(unknown).is_err());
+    });
+
+    let entries = RequestLog::read(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    let summary: Vec<_> = entries
+        .iter()
+        .map(|entry| {
+            (
+                entry.request.api_name.as_str(),
+                entry.duration,
+                entry.reply.clone().unwrap_err(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        summary,
+        [
+            (
+                "parse",
+                Duration::ZERO,
+                Error::Unauthorized("Not allowed to request 'parse' in service 'text'".into())
+                    .to_string()
+            ),
+            ("stats", Duration::ZERO, Error::DeadlineExceeded.to_string()),
+            (
+                "count",
+                Duration::ZERO,
+                "No handler for 'count' in service 'text'".to_string()
+            ),
+        ]
+    );
+
+    // The requests are rejected for the same reasons again.
+    assert_eq!(request_log::replay(&mut strict_router(), &entries), []);
+}
+
+#[test]
+fn credentials_are_not_logged() {
+    let path = log_path("credentials");
+    let _ = fs::remove_file(&path);
+    record(&path, buggy_router, |requester| {
+        let requester = requester
+            .with_token("secret-token")
+            .with_header(KEY_ID, "secret-key")
+            .with_header(SIGNATURE, "secret-signature")
+            .with_header("locale", "en");
+        requester.request(Parse("1")).unwrap();
+    });
+
+    let log = fs::read(&path).unwrap();
+    let entries = RequestLog::read(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    assert!(!log.windows(6).any(|window| window == b"secret"));
+    let headers = &entries[0].request.headers;
+    for name in [AUTHORIZATION, KEY_ID, SIGNATURE] {
+        assert_eq!(headers.get(name), None, "{name}");
+    }
+    assert_eq!(headers.get("locale"), Some("en"));
+}
+
+#[test]
+fn replaying_a_log_reports_changed_replies() {
+    let path = log_path("replay");
+    let _ = fs::remove_file(&path);
+    record(&path, buggy_router, |requester| {
+        requester.request(Stats("a bb cc")).unwrap();
+        requester.request(Stats("one")).unwrap();
+        requester.request(Parse("x")).unwrap();
+    });
+    let entries = RequestLog::read(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(request_log::replay(&mut buggy_router(), &entries), []);
+
+    let mismatches = request_log::replay(&mut fixed_router(), &entries);
+    let [mismatch] = &mismatches[..] else {
+        panic!("{mismatches:?}");
+    };
+    assert_eq!(mismatch.index, 0);
+    assert_eq!(mismatch.differences(), [r#"/longest: "cc" != "bb""#]);
+    assert_eq!(
+        mismatch.to_string(),
+        "Request 0 for 'stats' in service 'text' was answered differently:\n  \
+         /longest: \"cc\" != \"bb\""
+    );
+
+    // Requests that fail now are reported with their error.
+    let mut strict_router = ApiRouter::new()
+        .register_handler::<Parse, _>(|req| req.0.parse::<u32>().expect("a number"))
+        .layer(CatchUnwind);
+    let mismatches = request_log::replay(&mut strict_router, &entries[2..]);
+    let [mismatch] = &mismatches[..] else {
+        panic!("{mismatches:?}");
+    };
+    let [difference] = &mismatch.differences()[..] else {
+        panic!("{mismatch}");
+    };
+    assert!(
+        difference.starts_with("0 != error: Handler for 'parse' panicked: a number"),
+        "{difference}"
+    );
+}
+
+#[test]
+fn invalid_logs_are_rejected() {
+    let path = log_path("invalid");
+    fs::write(&path, [0, 0, 0, 9, 1, 2, 3]).unwrap();
+    let error = RequestLog::read(&path).unwrap_err();
+    fs::remove_file(&path).unwrap();
+    assert_eq!(
+        error,
+        "Invalid request log: Truncated message: expected 9 bytes, found 3"
+    );
+}
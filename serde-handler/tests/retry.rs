@@ -0,0 +1,185 @@
+use std::{
+    cell::Cell,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+use serde_handler::{
+    api::*,
+    channel::{self, Error, Local, Message, Overload, Requester},
+    headers::{Headers, REQUEST_ID},
+    limit::Limits,
+    retry::RetryPolicy,
+    transport::{ShutdownHandle, Transport},
+};
+
+/// Reads a counter, which is idempotent.
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct Get;
+
+impl Api for Get {
+    type Reply<'de> = u32;
+    type Request<'de> = Get;
+
+    const IDEMPOTENT: bool = true;
+    const NAME: &'static str = "get";
+    const SERVICE: &'static str = "counter";
+}
+
+/// Increments a counter, which is not idempotent.
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct Increment;
+
+impl Api for Increment {
+    type Reply<'de> = u32;
+    type Request<'de> = Increment;
+
+    const NAME: &'static str = "increment";
+    const SERVICE: &'static str = "counter";
+}
+
+/// A connection that loses the first replies that arrive on it.
+struct Lossy {
+    inner: Local,
+    lose: Cell<u32>,
+}
+
+impl Transport for Lossy {
+    fn send(&self, message: Message) -> Result<(), String> {
+        self.inner.send(message)
+    }
+
+    fn recv(&self) -> Result<Message, String> {
+        let message = self.inner.recv()?;
+        if self.lose.get() > 0 {
+            self.lose.set(self.lose.get() - 1);
+            return Err("Connection reset".to_string());
+        }
+        Ok(message)
+    }
+
+    fn recv_timeout(&self, timeout: Duration) -> Result<Option<Message>, String> {
+        self.inner.recv_timeout(timeout)
+    }
+
+    fn shutdown_handle(&self) -> Result<ShutdownHandle, String> {
+        self.inner.shutdown_handle()
+    }
+}
+
+/// Serves a counter, returning a requester that loses the first `lose` replies and the number
+/// of requests that were handled.
+fn serve_counter(lose: u32, limits: Limits) -> (Requester<Lossy>, Arc<AtomicU32>) {
+    let (requester, responder) = channel::new_pair();
+    let handled = Arc::new(AtomicU32::new(0));
+    let counter = Arc::clone(&handled);
+    thread::spawn(move || {
+        let count = Arc::clone(&counter);
+        ApiRouter::new()
+            .register_handler::<Get, _>(move |_| count.fetch_add(1, Ordering::SeqCst))
+            .register_handler::<Increment, _>(move |_| counter.fetch_add(1, Ordering::SeqCst) + 1)
+            .limit::<Get>(limits)
+            .serve_on(responder)
+    });
+    let requester = Requester::new(Lossy {
+        inner: requester.into_transport(),
+        lose: Cell::new(lose),
+    });
+    (requester, handled)
+}
+
+fn policy(max_attempts: u32) -> RetryPolicy {
+    RetryPolicy::new(max_attempts)
+        .backoff(Duration::from_millis(1), Duration::from_millis(10))
+        .without_jitter()
+}
+
+#[test]
+fn idempotent_requests_are_retried() {
+    let (requester, handled) = serve_counter(2, Limits::new());
+    let requester = requester.with_retry(policy(3));
+
+    assert_eq!(requester.request(Get), Ok(2));
+    assert_eq!(handled.load(Ordering::SeqCst), 3);
+}
+
+#[test]
+fn retries_give_up_after_max_attempts() {
+    let (requester, _) = serve_counter(3, Limits::new());
+    let requester = requester.with_retry(policy(3));
+
+    assert_eq!(
+        requester.request(Get),
+        Err(Error::Transport(
+            "Error receiving response: Connection reset".to_string()
+        ))
+    );
+    assert_eq!(requester.request(Get), Ok(3));
+}
+
+#[test]
+fn other_requests_are_not_retried() {
+    let (requester, handled) = serve_counter(1, Limits::new());
+    let requester = requester.with_retry(policy(3));
+
+    assert!(matches!(
+        requester.request(Increment),
+        Err(Error::Transport(_))
+    ));
+    assert_eq!(requester.request(Increment), Ok(2));
+    assert_eq!(handled.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn overloaded_requests_are_retried_after_the_limit_allows() {
+    let limits = Limits::new().rate(1, Duration::from_millis(20));
+    let (requester, _) = serve_counter(0, limits);
+
+    assert_eq!(requester.request(Get), Ok(0));
+    assert!(matches!(
+        requester.request(Get),
+        Err(Error::Overloaded {
+            limit: Overload::Rate,
+            ..
+        })
+    ));
+
+    let requester = requester.with_retry(policy(2));
+    assert_eq!(requester.request(Get), Ok(1));
+}
+
+#[test]
+fn requests_sent_again_are_handled_once() {
+    let (requester, handled) = serve_counter(1, Limits::new());
+    let headers = Headers::from_iter([(REQUEST_ID, "increment-1")]);
+
+    assert!(requester
+        .request_with_headers(Increment, headers.clone())
+        .is_err());
+    assert_eq!(requester.request_with_headers(Increment, headers), Ok(1));
+    assert_eq!(handled.load(Ordering::SeqCst), 1);
+
+    // Requests with another request ID are handled
+    assert_eq!(requester.request(Increment), Ok(2));
+}
+
+#[test]
+fn backoff_doubles_up_to_the_maximum() {
+    let policy = RetryPolicy::new(5)
+        .backoff(Duration::from_millis(50), Duration::from_millis(150))
+        .without_jitter();
+    let delays: Vec<_> = (2..=5).map(|attempt| policy.delay(attempt)).collect();
+    assert_eq!(
+        delays,
+        [50, 100, 150, 150].map(Duration::from_millis).to_vec()
+    );
+
+    let jittered = RetryPolicy::new(5).delay(3);
+    assert!(jittered > Duration::from_millis(50) && jittered <= Duration::from_millis(100));
+}
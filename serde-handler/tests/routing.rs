@@ -0,0 +1,129 @@
+use std::thread;
+
+use serde::{Deserialize, Serialize};
+
+use serde_handler::{
+    api::*,
+    channel::{self, Error, Kind, Message},
+};
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct TextUpper<'a>(&'a str);
+
+impl Api for TextUpper<'_> {
+    type Reply<'de> = String;
+    type Request<'de> = TextUpper<'de>;
+
+    const NAME: &'static str = "upper";
+    const SERVICE: &'static str = "text";
+}
+
+/// Same API name as [`TextUpper`], but part of a different service.
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct GreekUpper<'a>(&'a str);
+
+impl Api for GreekUpper<'_> {
+    type Reply<'de> = String;
+    type Request<'de> = GreekUpper<'de>;
+
+    const NAME: &'static str = "upper";
+    const SERVICE: &'static str = "greek";
+}
+
+fn text_router() -> ApiRouter {
+    ApiRouter::new().register_handler::<TextUpper, _>(|req| req.0.to_uppercase())
+}
+
+fn greek_router() -> ApiRouter {
+    ApiRouter::new().register_handler::<GreekUpper, _>(|req| format!("ΑΛΦΑ {}", req.0))
+}
+
+#[test]
+fn same_name_in_different_services() {
+    let (requester, responder) = channel::new_pair();
+    thread::spawn(move || text_router().merge(greek_router()).serve_on(responder));
+
+    assert_eq!(requester.request(TextUpper("abc")).unwrap(), "ABC");
+    assert_eq!(requester.request(GreekUpper("abc")).unwrap(), "ΑΛΦΑ abc");
+}
+
+#[test]
+fn unknown_service_is_rejected() {
+    let (requester, responder) = channel::new_pair();
+    thread::spawn(move || text_router().serve_on(responder));
+
+    let error = requester.request(GreekUpper("abc")).unwrap_err();
+    assert_eq!(error, Error::Other("Unknown service 'greek'".to_string()));
+
+    let error = requester
+        .send_request(Message {
+            kind: Kind::Request,
+            service: "text".to_string(),
+            api_name: "lower".to_string(),
+            data: b"\"ABC\"".to_vec(),
+            ..Default::default()
+        })
+        .unwrap_err();
+    assert_eq!(
+        error,
+        Error::Other("No handler for 'lower' in service 'text'".to_string())
+    );
+
+    // The router keeps serving after rejecting requests
+    assert_eq!(requester.request(TextUpper("abc")).unwrap(), "ABC");
+}
+
+#[test]
+fn serve_forever_answers_a_single_api() {
+    let (requester, responder) = channel::new_pair();
+    let service =
+        thread::spawn(move || responder.serve_forever::<TextUpper, _>(|req| req.0.to_uppercase()));
+
+    assert_eq!(requester.request(TextUpper("abc")).unwrap(), "ABC");
+    let error = requester.request(GreekUpper("abc")).unwrap_err();
+    assert_eq!(error, Error::Other("Unknown service 'greek'".to_string()));
+
+    drop(requester);
+    service.join().unwrap().unwrap();
+}
+
+#[test]
+fn replies_for_another_api_are_rejected() {
+    let (requester, responder) = channel::new_pair();
+    thread::spawn(move || {
+        let request = responder.next_request().unwrap();
+        let reply = Message {
+            service: "greek".to_string(),
+            ..request.reply(b"\"ABC\"".to_vec())
+        };
+        responder.send_response(reply).unwrap();
+    });
+
+    assert_eq!(
+        requester.request(TextUpper("abc")),
+        Err(Error::Other(
+            "Expected a reply for 'upper' in service 'text', but received one for 'upper' in \
+             service 'greek'"
+                .to_string()
+        ))
+    );
+}
+
+#[test]
+fn serves() {
+    let router = text_router();
+    assert!(router.serves("text"));
+    assert!(!router.serves("greek"));
+}
+
+#[test]
+#[should_panic(expected = "Duplicate handler for 'upper' in service 'text'")]
+fn duplicate_registration_panics() {
+    let _ = text_router().register_handler::<TextUpper, _>(|req| req.0.to_string());
+}
+
+#[test]
+#[should_panic(expected = "Duplicate handler for 'upper' in service 'greek'")]
+fn duplicate_merge_panics() {
+    let _ = greek_router().merge(greek_router());
+}
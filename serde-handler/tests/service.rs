@@ -0,0 +1,108 @@
+use std::thread;
+
+use serde_handler::{
+    api::*,
+    channel::{self, Error},
+};
+
+#[service]
+trait Text {
+    #[api(idempotent = true)]
+    fn upper(&self, s: &str) -> String;
+    fn repeat(&self, s: &str, times: usize) -> Vec<String>;
+    fn count(&mut self) -> u64;
+    fn reset(&mut self);
+}
+
+#[service(name = "math")]
+trait Arithmetic {
+    fn add_all(&self, numbers: Vec<u64>) -> u64;
+}
+
+#[derive(Default)]
+struct TextService {
+    calls: u64,
+}
+
+impl Text for TextService {
+    fn upper(&self, s: &str) -> String {
+        s.to_uppercase()
+    }
+
+    fn repeat(&self, s: &str, times: usize) -> Vec<String> {
+        vec![s.to_string(); times]
+    }
+
+    fn count(&mut self) -> u64 {
+        self.calls += 1;
+        self.calls
+    }
+
+    fn reset(&mut self) {
+        self.calls = 0;
+    }
+}
+
+struct Calculator;
+
+impl Arithmetic for Calculator {
+    fn add_all(&self, numbers: Vec<u64>) -> u64 {
+        numbers.iter().sum()
+    }
+}
+
+#[test]
+fn generated_apis() {
+    assert_eq!(TextUpper::SERVICE, "text");
+    assert_eq!(TextUpper::NAME, "upper");
+    assert_eq!(
+        [TextUpper::IDEMPOTENT, TextCount::IDEMPOTENT],
+        [true, false]
+    );
+    assert_eq!(TextRepeat::NAME, "repeat");
+    assert_eq!(ArithmeticAddAll::SERVICE, "math");
+    assert_eq!(ArithmeticAddAll::NAME, "add_all");
+}
+
+#[test]
+fn client_calls_service() {
+    let (requester, responder) = channel::new_pair();
+    thread::spawn(move || text_router(TextService::default()).serve_on(responder));
+
+    let client = TextClient::new(requester);
+    assert_eq!(client.upper("abc").unwrap(), "ABC");
+    assert_eq!(client.repeat("ab", 2).unwrap(), ["ab", "ab"]);
+    assert_eq!(client.count().unwrap(), 1);
+    assert_eq!(client.count().unwrap(), 2);
+    client.reset().unwrap();
+    assert_eq!(client.count().unwrap(), 1);
+}
+
+#[test]
+fn generated_apis_work_with_plain_requests() {
+    let (requester, responder) = channel::new_pair();
+    thread::spawn(move || {
+        text_router(TextService::default())
+            .merge(arithmetic_router(Calculator))
+            .serve_on(responder)
+    });
+
+    assert_eq!(requester.request(TextUpper { s: "abc" }).unwrap(), "ABC");
+    let client = ArithmeticClient::new(requester);
+    assert_eq!(client.add_all(vec![1, 2, 3]).unwrap(), 6);
+
+    let requester = client.into_inner();
+    assert_eq!(
+        requester.request(TextRepeat { s: "a", times: 1 }).unwrap(),
+        ["a"]
+    );
+}
+
+#[test]
+fn client_reports_missing_service() {
+    let (requester, responder) = channel::new_pair();
+    thread::spawn(move || arithmetic_router(Calculator).serve_on(responder));
+
+    let error = TextClient::new(requester).upper("abc").unwrap_err();
+    assert_eq!(error, Error::Other("Unknown service 'text'".to_string()));
+}
@@ -0,0 +1,102 @@
+use std::{
+    net::TcpListener,
+    sync::{Arc, Mutex},
+    thread,
+};
+
+use serde::{Deserialize, Serialize};
+
+use serde_handler::{
+    api::*,
+    channel::{self, Error},
+    transport::Server,
+};
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct UppercaseRequest<'a>(&'a str);
+
+impl Api for UppercaseRequest<'_> {
+    type Reply<'de> = String;
+    type Request<'de> = UppercaseRequest<'de>;
+
+    const NAME: &'static str = "upper";
+    const SERVICE: &'static str = "text";
+}
+
+fn text_service_router() -> ApiRouter {
+    ApiRouter::new().register_handler::<UppercaseRequest, _>(|req| req.0.to_uppercase())
+}
+
+#[test]
+fn stops_when_requester_is_dropped() {
+    let (requester, responder) = channel::new_pair();
+    let service = thread::spawn(move || text_service_router().serve_on(responder));
+
+    assert_eq!(requester.request(UppercaseRequest("abc")).unwrap(), "ABC");
+    drop(requester);
+    assert_eq!(service.join().unwrap(), Ok(()));
+}
+
+#[test]
+fn shutdown_handle_stops_after_pending_requests() {
+    let (requester, responder) = channel::new_pair();
+    let shutdown = responder.shutdown_handle().unwrap();
+    let service = thread::spawn(move || text_service_router().serve_on(responder));
+
+    assert_eq!(requester.request(UppercaseRequest("abc")).unwrap(), "ABC");
+    shutdown.shutdown().unwrap();
+    assert_eq!(service.join().unwrap(), Ok(()));
+
+    let error = requester.request(UppercaseRequest("abc")).unwrap_err();
+    assert!(
+        error.to_string().starts_with("Failed to send request"),
+        "{error}"
+    );
+    // Shutting down a stopped service is not an error
+    shutdown.shutdown().unwrap();
+}
+
+#[test]
+fn lifecycle_hooks() {
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let hook = |event: &'static str| {
+        let events = Arc::clone(&events);
+        move || events.lock().unwrap().push(event)
+    };
+    let (on_start, on_stop) = (hook("start"), hook("stop"));
+
+    let (requester, responder) = channel::new_pair();
+    let service = thread::spawn(move || {
+        text_service_router()
+            .on_start(on_start)
+            .on_stop(on_stop)
+            .serve_on(responder)
+    });
+
+    requester.request(UppercaseRequest("abc")).unwrap();
+    assert_eq!(*events.lock().unwrap(), ["start"]);
+    drop(requester);
+    service.join().unwrap().unwrap();
+    assert_eq!(*events.lock().unwrap(), ["start", "stop"]);
+}
+
+#[test]
+fn tcp_server_shutdown() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let responder = channel::Responder::new(Server::tcp(listener));
+    let shutdown = responder.shutdown_handle().unwrap();
+    let service = thread::spawn(move || text_service_router().serve_on(responder));
+
+    let first = channel::connect_tcp(addr).unwrap();
+    let second = channel::connect_tcp(addr).unwrap();
+    assert_eq!(first.request(UppercaseRequest("a")).unwrap(), "A");
+    // One client hanging up does not stop the server
+    drop(first);
+    assert_eq!(second.request(UppercaseRequest("b")).unwrap(), "B");
+
+    shutdown.shutdown().unwrap();
+    assert_eq!(service.join().unwrap(), Ok(()));
+    let error = second.request(UppercaseRequest("c")).unwrap_err();
+    assert_eq!(error, Error::Other("The service has shut down".to_string()));
+}
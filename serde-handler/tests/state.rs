@@ -0,0 +1,172 @@
+use std::{cell::Cell, collections::HashMap, rc::Rc, thread};
+
+use serde::{Deserialize, Serialize};
+
+use serde_handler::{
+    api::*,
+    channel::{self, Error, Message},
+    extract::{Context, FromRequest, State},
+};
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct SetRequest<'a> {
+    key: &'a str,
+    value: u64,
+}
+
+impl Api for SetRequest<'_> {
+    type Reply<'de> = Option<u64>;
+    type Request<'de> = SetRequest<'de>;
+
+    const NAME: &'static str = "set";
+    const SERVICE: &'static str = "cache";
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct GetRequest<'a>(&'a str);
+
+impl Api for GetRequest<'_> {
+    type Reply<'de> = Option<u64>;
+    type Request<'de> = GetRequest<'de>;
+
+    const NAME: &'static str = "get";
+    const SERVICE: &'static str = "cache";
+}
+
+/// Replies with the key of the request, to check that stateful handlers can still borrow.
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct TouchRequest<'a>(&'a str);
+
+impl Api for TouchRequest<'_> {
+    type Reply<'de> = &'de str;
+    type Request<'de> = TouchRequest<'de>;
+
+    const NAME: &'static str = "touch";
+    const SERVICE: &'static str = "cache";
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct WhoAmI;
+
+impl Api for WhoAmI {
+    type Reply<'de> = (String, String, u32);
+    type Request<'de> = WhoAmI;
+
+    const NAME: &'static str = "whoami";
+    const SERVICE: &'static str = "meta";
+    const VERSION: u32 = 3;
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct Hits;
+
+impl Api for Hits {
+    type Reply<'de> = u64;
+    type Request<'de> = Hits;
+
+    const NAME: &'static str = "hits";
+    const SERVICE: &'static str = "meta";
+}
+
+type Cache = HashMap<String, u64>;
+
+fn cache_router() -> ApiRouter<Cache> {
+    ApiRouter::with_state(Cache::new())
+        .register_stateful_handler::<SetRequest, _>(|cache: &mut Cache, req| {
+            cache.insert(req.key.to_string(), req.value)
+        })
+        .register_stateful_handler::<GetRequest, _>(|cache: &mut Cache, req| {
+            cache.get(req.0).copied()
+        })
+        .register_stateful_handler::<TouchRequest, _>(|cache: &mut Cache, req| {
+            *cache.entry(req.0.to_string()).or_default() += 1;
+            req.0
+        })
+}
+
+#[test]
+fn stateful_handlers_share_the_state() {
+    let (requester, responder) = channel::new_pair();
+    thread::spawn(move || cache_router().serve_on(responder));
+
+    let set = |key, value| requester.request(SetRequest { key, value }).unwrap();
+    assert_eq!(set("a", 1), None);
+    assert_eq!(set("a", 2), Some(1));
+    assert_eq!(requester.request(GetRequest("a")).unwrap(), Some(2));
+    assert_eq!(requester.request(GetRequest("b")).unwrap(), None);
+
+    let touched = requester.request_borrowed(TouchRequest("b")).unwrap();
+    assert_eq!(touched.reply().unwrap(), "b");
+    assert_eq!(requester.request(GetRequest("b")).unwrap(), Some(1));
+}
+
+#[test]
+fn handlers_extract_state_and_context() {
+    let (requester, responder) = channel::new_pair();
+    thread::spawn(move || {
+        let hits = Rc::new(Cell::new(0));
+        ApiRouter::with_state(hits)
+            .register_handler_with::<WhoAmI, _, _>(
+                |(State(hits), cx): (State<Rc<Cell<u64>>>, Context), _| {
+                    hits.set(hits.get() + 1);
+                    (cx.service, cx.api_name, cx.version)
+                },
+            )
+            .register_handler_with::<Hits, _, _>(|State(hits): State<Rc<Cell<u64>>>, _| hits.get())
+            .serve_on(responder)
+    });
+
+    let whoami = requester.request(WhoAmI).unwrap();
+    assert_eq!(whoami, ("meta".to_string(), "whoami".to_string(), 3));
+    requester.request(WhoAmI).unwrap();
+    assert_eq!(requester.request(Hits).unwrap(), 2);
+}
+
+/// Extracts the part of the request's API name before the first `_`.
+struct Prefix(String);
+
+impl<S> FromRequest<S> for Prefix {
+    fn from_request(request: &Message, _: &mut S) -> Result<Self, String> {
+        match request.api_name.split_once('_') {
+            Some((prefix, _)) => Ok(Prefix(prefix.to_string())),
+            None => Err(format!("'{}' has no prefix", request.api_name)),
+        }
+    }
+}
+
+#[test]
+fn failing_extractions_are_reported() {
+    let (requester, responder) = channel::new_pair();
+    thread::spawn(move || {
+        ApiRouter::new()
+            .register_handler_with::<WhoAmI, _, _>(|Prefix(prefix): Prefix, _| {
+                (prefix, String::new(), 0)
+            })
+            .serve_on(responder)
+    });
+
+    assert_eq!(
+        requester.request(WhoAmI),
+        Err(Error::Other("'whoami' has no prefix".to_string()))
+    );
+}
+
+#[test]
+fn merged_routers_keep_their_state() {
+    let (requester, responder) = channel::new_pair();
+    thread::spawn(move || {
+        ApiRouter::with_state(7)
+            .register_stateful_handler::<Hits, _>(|hits: &mut u64, _| {
+                *hits += 1;
+                *hits
+            })
+            .merge(cache_router())
+            .serve_on(responder)
+    });
+
+    requester
+        .request(SetRequest { key: "a", value: 1 })
+        .unwrap();
+    assert_eq!(requester.request(GetRequest("a")).unwrap(), Some(1));
+    assert_eq!(requester.request(Hits).unwrap(), 8);
+}
@@ -0,0 +1,230 @@
+use std::{
+    iter,
+    net::TcpListener,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+use serde_handler::{
+    api::*,
+    channel::{self, Error, Overload},
+    limit::Limits,
+    streaming::StreamingApi,
+    transport::Server,
+};
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct WordsRequest<'a>(&'a str);
+
+impl Api for WordsRequest<'_> {
+    type Reply<'de> = String;
+    type Request<'de> = WordsRequest<'de>;
+
+    const NAME: &'static str = "words";
+    const SERVICE: &'static str = "text";
+}
+
+impl StreamingApi for WordsRequest<'_> {}
+
+/// Subscribes to an endless stream of numbers, starting at the given one.
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct CountRequest(u64);
+
+impl Api for CountRequest {
+    type Reply<'de> = u64;
+    type Request<'de> = CountRequest;
+
+    const NAME: &'static str = "count";
+    const SERVICE: &'static str = "text";
+}
+
+impl StreamingApi for CountRequest {}
+
+/// Subscribes to a stream that sends one number, and then waits for the next one forever.
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct StalledRequest;
+
+impl Api for StalledRequest {
+    type Reply<'de> = u64;
+    type Request<'de> = StalledRequest;
+
+    const NAME: &'static str = "stalled";
+    const SERVICE: &'static str = "text";
+}
+
+impl StreamingApi for StalledRequest {}
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct UppercaseRequest<'a>(&'a str);
+
+impl Api for UppercaseRequest<'_> {
+    type Reply<'de> = String;
+    type Request<'de> = UppercaseRequest<'de>;
+
+    const NAME: &'static str = "upper";
+    const SERVICE: &'static str = "text";
+}
+
+/// Serves the text APIs, counting how many numbers the `count` handler produced.
+fn text_router(produced: Arc<AtomicUsize>) -> ApiRouter {
+    ApiRouter::new()
+        .register_handler::<UppercaseRequest, _>(|req| req.0.to_uppercase())
+        .register_stream_handler::<WordsRequest, _, _>(|req| {
+            let words: Vec<_> = req.0.split_whitespace().map(str::to_string).collect();
+            words
+        })
+        .register_stream_handler::<CountRequest, _, _>(move |req| {
+            let produced = Arc::clone(&produced);
+            (req.0..).inspect(move |_| {
+                produced.fetch_add(1, Ordering::SeqCst);
+            })
+        })
+        .register_stream_handler::<StalledRequest, _, _>(|_| {
+            iter::once(0).chain(iter::from_fn(|| {
+                thread::sleep(Duration::from_secs(3600));
+                None
+            }))
+        })
+}
+
+#[test]
+fn stream_of_replies() {
+    let (requester, responder) = channel::new_pair();
+    thread::spawn(move || text_router(Default::default()).serve_on(responder));
+
+    let words: Vec<_> = requester
+        .request_stream(WordsRequest("one two three"))
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(words, ["one", "two", "three"]);
+    let words = requester.request_stream(WordsRequest("")).unwrap();
+    assert_eq!(words.count(), 0);
+}
+
+#[test]
+fn dropping_the_stream_cancels_it() {
+    let produced = Arc::new(AtomicUsize::new(0));
+    let (requester, responder) = channel::new_pair();
+    let router_produced = Arc::clone(&produced);
+    thread::spawn(move || text_router(router_produced).serve_on(responder));
+
+    let numbers = requester.request_stream(CountRequest(5)).unwrap();
+    let numbers: Vec<_> = numbers.take(3).map(Result::unwrap).collect();
+    assert_eq!(numbers, [5, 6, 7]);
+
+    // The service is free for other requests again
+    assert_eq!(requester.request(UppercaseRequest("abc")).unwrap(), "ABC");
+    let produced_after_cancel = produced.load(Ordering::SeqCst);
+    assert!(produced_after_cancel < 10);
+    thread::sleep(Duration::from_millis(20));
+    assert_eq!(produced.load(Ordering::SeqCst), produced_after_cancel);
+}
+
+#[test]
+fn slow_requesters_slow_down_the_stream() {
+    let produced = Arc::new(AtomicUsize::new(0));
+    let (requester, responder) = channel::new_pair();
+    let router_produced = Arc::clone(&produced);
+    thread::spawn(move || text_router(router_produced).serve_on(responder));
+
+    let mut numbers = requester.request_stream(CountRequest(0)).unwrap();
+    assert_eq!(numbers.next(), Some(Ok(0)));
+    thread::sleep(Duration::from_millis(50));
+    // Only as many items as fit into the channel are produced ahead of time
+    assert!(produced.load(Ordering::SeqCst) <= 3);
+    assert_eq!(numbers.next(), Some(Ok(1)));
+}
+
+#[test]
+fn stream_errors() {
+    let (requester, responder) = channel::new_pair();
+    thread::spawn(move || text_router(Default::default()).serve_on(responder));
+
+    let error = requester.request(CountRequest(0)).unwrap_err();
+    assert_eq!(
+        error,
+        Error::Other("'count' in service 'text' is a streaming API".to_string())
+    );
+
+    let mut stream = requester.request_stream(WordsRequest("a b")).unwrap();
+    assert_eq!(stream.next(), Some(Ok("a".to_string())));
+    drop(stream);
+
+    let description = text_router(Default::default()).describe();
+    assert!(description.api("text", "count").unwrap().streaming);
+    assert!(!description.api("text", "upper").unwrap().streaming);
+}
+
+#[test]
+fn tcp_streams_are_cancelled_per_client() {
+    let produced = Arc::new(AtomicUsize::new(0));
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let responder = channel::Responder::new(Server::tcp(listener));
+    let router_produced = Arc::clone(&produced);
+    thread::spawn(move || text_router(router_produced).serve_on(responder));
+
+    let first = channel::connect_tcp(addr).unwrap();
+    let second = channel::connect_tcp(addr).unwrap();
+    let mut numbers = first.request_stream(CountRequest(0)).unwrap();
+    assert_eq!(numbers.next(), Some(Ok(0)));
+
+    // Handled once the first client cancels its stream
+    let upper = thread::spawn(move || second.request(UppercaseRequest("tcp")).unwrap());
+    assert_eq!(numbers.next(), Some(Ok(1)));
+    drop(numbers);
+    assert_eq!(upper.join().unwrap(), "TCP");
+
+    // A client that hangs up in the middle of a stream also ends it
+    let mut numbers = first.request_stream(CountRequest(0)).unwrap();
+    assert_eq!(numbers.next(), Some(Ok(0)));
+    std::mem::forget(numbers);
+    drop(first);
+    let third = channel::connect_tcp(addr).unwrap();
+    assert_eq!(third.request(UppercaseRequest("tcp")).unwrap(), "TCP");
+}
+
+#[test]
+fn other_clients_are_answered_while_a_stream_is_open() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let responder = channel::Responder::new(Server::tcp(listener));
+    thread::spawn(move || {
+        text_router(Default::default())
+            .limit::<StalledRequest>(Limits::new().max_concurrency(1))
+            .serve_on(responder)
+    });
+
+    let first = channel::connect_tcp(addr).unwrap();
+    let second = channel::connect_tcp(addr).unwrap();
+    let mut stalled = first.request_stream(StalledRequest).unwrap();
+    assert_eq!(stalled.next(), Some(Ok(0)));
+
+    assert_eq!(second.request(UppercaseRequest("tcp")).unwrap(), "TCP");
+    let numbers = second.request_stream(CountRequest(0)).unwrap();
+    let numbers: Vec<_> = numbers.take(3).map(Result::unwrap).collect();
+    assert_eq!(numbers, [0, 1, 2]);
+
+    // The open stream is still being handled
+    let mut overloaded = second.request_stream(StalledRequest).unwrap();
+    assert_eq!(
+        overloaded.next(),
+        Some(Err(Error::Overloaded {
+            limit: Overload::Concurrency,
+            retry_after: None,
+        }))
+    );
+
+    // A stream that is waiting for its next reply can be cancelled
+    drop(stalled);
+    let mut stalled = second.request_stream(StalledRequest).unwrap();
+    assert_eq!(stalled.next(), Some(Ok(0)));
+    assert_eq!(first.request(UppercaseRequest("tcp")).unwrap(), "TCP");
+}
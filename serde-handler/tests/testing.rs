@@ -0,0 +1,154 @@
+use std::{fs, process, thread};
+
+use serde::{Deserialize, Serialize};
+
+use serde_handler::{
+    api::*,
+    channel::{self, Error, Requester},
+    testing::{MockService, Recorder, Replayer},
+    transport::Transport,
+};
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct UppercaseRequest<'a>(&'a str);
+
+impl Api for UppercaseRequest<'_> {
+    type Reply<'de> = String;
+    type Request<'de> = UppercaseRequest<'de>;
+
+    const NAME: &'static str = "upper";
+    const SERVICE: &'static str = "text";
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct LenRequest<'a>(&'a str);
+
+impl Api for LenRequest<'_> {
+    type Reply<'de> = usize;
+    type Request<'de> = LenRequest<'de>;
+
+    const NAME: &'static str = "len";
+    const SERVICE: &'static str = "text";
+}
+
+/// The code under test.
+fn shout<T: Transport>(requester: &Requester<T>, text: &str) -> Result<String, Error> {
+    let upper = requester.request(UppercaseRequest(text))?;
+    let len = requester.request(LenRequest(&upper))?;
+    Ok(format!("{upper}{}", "!".repeat(len)))
+}
+
+#[test]
+fn mocks_answer_expected_requests() {
+    let mock = MockService::new();
+    mock.expect::<UppercaseRequest>()
+        .returning(|req| req.0.to_uppercase())
+        .times(2);
+    mock.expect::<LenRequest>().returning(|req| req.0.len());
+
+    let requester = mock.requester();
+    assert_eq!(shout(&requester, "hi").unwrap(), "HI!!");
+    assert_eq!(shout(&requester, "abc").unwrap(), "ABC!!!");
+    assert_eq!(mock.calls::<UppercaseRequest>(), 2);
+    assert_eq!(mock.calls::<LenRequest>(), 2);
+    mock.verify();
+}
+
+#[test]
+fn expectations_are_used_in_order() {
+    let mock = MockService::new();
+    mock.expect::<LenRequest>().returning(|_| 1).times(1);
+    mock.expect::<LenRequest>()
+        .returning_error("Too long".to_string());
+    mock.expect::<UppercaseRequest>();
+
+    let requester = mock.requester();
+    assert_eq!(requester.request(LenRequest("a")), Ok(1));
+    assert_eq!(
+        requester.request(LenRequest("ab")),
+        Err(Error::Other("Too long".to_string()))
+    );
+    assert_eq!(
+        shout(&mock.requester(), "x"),
+        Err(Error::Other(
+            "No reply was set up for 'upper' in service 'text'".to_string()
+        ))
+    );
+}
+
+#[test]
+fn mocks_answer_batches() {
+    let mock = MockService::new();
+    mock.expect::<UppercaseRequest>()
+        .returning(|req| req.0.to_uppercase());
+    mock.expect::<LenRequest>().never();
+
+    let (upper,) = mock
+        .requester()
+        .batch()
+        .add(UppercaseRequest("a"))
+        .send()
+        .unwrap();
+    assert_eq!(upper.unwrap(), "A");
+}
+
+#[test]
+#[should_panic(expected = "Unexpected request for 'len' in service 'text'")]
+fn unexpected_requests_fail_verification() {
+    let mock = MockService::new();
+    mock.expect::<UppercaseRequest>()
+        .returning(|req| req.0.to_uppercase());
+
+    assert_eq!(
+        shout(&mock.requester(), "hi"),
+        Err(Error::Other(
+            "Unexpected request for 'len' in service 'text'".to_string()
+        ))
+    );
+}
+
+#[test]
+#[should_panic(
+    expected = "Expected 'upper' in service 'text' to be requested 2 times, but it \
+                           was requested 1 times"
+)]
+fn missing_requests_fail_verification() {
+    let mock = MockService::new();
+    mock.expect::<UppercaseRequest>()
+        .returning(|req| req.0.to_uppercase())
+        .times(2);
+
+    mock.requester().request(UppercaseRequest("a")).unwrap();
+    mock.verify();
+}
+
+#[test]
+fn recordings_are_replayed() {
+    let path = std::env::temp_dir().join(format!("serde-handler-{}.recording", process::id()));
+    let (requester, responder) = channel::new_pair();
+    let service = thread::spawn(move || {
+        ApiRouter::new()
+            .register_handler::<UppercaseRequest, _>(|req| req.0.to_uppercase())
+            .register_handler::<LenRequest, _>(|req| req.0.len())
+            .serve_on(responder)
+    });
+    let recorder = Recorder::create(&path, requester.into_transport()).unwrap();
+    let requester = Requester::new(recorder);
+    assert_eq!(shout(&requester, "hi").unwrap(), "HI!!");
+    assert_eq!(shout(&requester, "abc").unwrap(), "ABC!!!");
+    drop(requester);
+    service.join().unwrap().unwrap();
+
+    let replayer = Replayer::load(&path).unwrap();
+    assert_eq!(replayer.remaining(), 4);
+    let requester = replayer.requester();
+    assert_eq!(shout(&requester, "abc").unwrap(), "ABC!!!");
+    assert_eq!(shout(&requester, "hi").unwrap(), "HI!!");
+    assert_eq!(
+        shout(&requester, "hi"),
+        Err(Error::Other(
+            "No recorded reply for 'upper' in service 'text'".to_string()
+        ))
+    );
+    fs::remove_file(&path).unwrap();
+}
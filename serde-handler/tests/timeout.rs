@@ -0,0 +1,137 @@
+use std::{
+    net::TcpListener,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use serde::{Deserialize, Serialize};
+
+use serde_handler::{
+    api::*,
+    channel::{self, Error},
+    transport::Server,
+};
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct SleepRequest(u64);
+
+impl Api for SleepRequest {
+    type Reply<'de> = u64;
+    type Request<'de> = SleepRequest;
+
+    const NAME: &'static str = "sleep";
+    const SERVICE: &'static str = "clock";
+}
+
+/// Like [`SleepRequest`], but with a default timeout.
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct ImpatientSleepRequest(u64);
+
+impl Api for ImpatientSleepRequest {
+    type Reply<'de> = u64;
+    type Request<'de> = ImpatientSleepRequest;
+
+    const NAME: &'static str = "impatient_sleep";
+    const SERVICE: &'static str = "clock";
+    const TIMEOUT: Option<Duration> = Some(Duration::from_millis(50));
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct CountRequest;
+
+impl Api for CountRequest {
+    type Reply<'de> = usize;
+    type Request<'de> = CountRequest;
+
+    const NAME: &'static str = "count";
+    const SERVICE: &'static str = "clock";
+}
+
+fn sleep(millis: u64) -> u64 {
+    thread::sleep(Duration::from_millis(millis));
+    millis
+}
+
+fn clock_service_router(count: Arc<AtomicUsize>) -> ApiRouter {
+    ApiRouter::new()
+        .register_handler::<SleepRequest, _>(|req| sleep(req.0))
+        .register_handler::<ImpatientSleepRequest, _>(|req| sleep(req.0))
+        .register_handler::<CountRequest, _>(move |_| count.fetch_add(1, Ordering::SeqCst) + 1)
+}
+
+#[test]
+fn request_with_timeout() {
+    let (requester, responder) = channel::new_pair();
+    thread::spawn(move || clock_service_router(Default::default()).serve_on(responder));
+
+    let timeout = Duration::from_millis(50);
+    let error = requester
+        .request_with_timeout(SleepRequest(300), timeout)
+        .unwrap_err();
+    assert_eq!(error, Error::Timeout);
+    // The late reply to the request that timed out is not mistaken for this one's
+    assert_eq!(requester.request(SleepRequest(1)).unwrap(), 1);
+    assert_eq!(
+        requester
+            .request_with_timeout(SleepRequest(2), Duration::from_secs(5))
+            .unwrap(),
+        2
+    );
+}
+
+#[test]
+fn default_timeout() {
+    let (requester, responder) = channel::new_pair();
+    thread::spawn(move || clock_service_router(Default::default()).serve_on(responder));
+
+    let error = requester.request(ImpatientSleepRequest(300)).unwrap_err();
+    assert_eq!(error, Error::Timeout);
+    // Wait for the service to finish the request that timed out
+    assert_eq!(requester.request(SleepRequest(1)).unwrap(), 1);
+    assert_eq!(requester.request(ImpatientSleepRequest(1)).unwrap(), 1);
+}
+
+#[test]
+fn expired_requests_are_skipped() {
+    let count = Arc::new(AtomicUsize::new(0));
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let responder = channel::Responder::new(Server::tcp(listener));
+    let router_count = Arc::clone(&count);
+    thread::spawn(move || clock_service_router(router_count).serve_on(responder));
+
+    // Keep the service busy, so that the next request expires while it is queued
+    let busy = thread::spawn(move || {
+        let requester = channel::connect_tcp(addr).unwrap();
+        requester.request(SleepRequest(300)).unwrap()
+    });
+    thread::sleep(Duration::from_millis(50));
+
+    let requester = channel::connect_tcp(addr).unwrap();
+    let error = requester
+        .request_with_timeout(CountRequest, Duration::from_millis(50))
+        .unwrap_err();
+    assert_eq!(error, Error::Timeout);
+    assert_eq!(busy.join().unwrap(), 300);
+
+    // The expired request never reached its handler
+    assert_eq!(requester.request(CountRequest).unwrap(), 1);
+    assert_eq!(count.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn past_deadline() {
+    let (requester, responder) = channel::new_pair();
+    thread::spawn(move || clock_service_router(Default::default()).serve_on(responder));
+
+    let deadline = SystemTime::now() - Duration::from_secs(1);
+    let error = requester
+        .request_with_deadline(CountRequest, Some(deadline))
+        .unwrap_err();
+    assert_eq!(error, Error::Timeout);
+    assert_eq!(requester.request(CountRequest).unwrap(), 1);
+}
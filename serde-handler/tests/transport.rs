@@ -0,0 +1,195 @@
+use std::{
+    io::{Read, Write},
+    net::TcpListener,
+    thread,
+    time::{Duration, Instant, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+use serde_handler::{
+    api::*,
+    channel,
+    compression::Encoding,
+    transport::{self, Server, Transport},
+};
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct UppercaseRequest<'a>(&'a str);
+
+impl Api for UppercaseRequest<'_> {
+    type Reply<'de> = String;
+    type Request<'de> = UppercaseRequest<'de>;
+
+    const NAME: &'static str = "upper";
+    const SERVICE: &'static str = "text";
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct LengthRequest<'a>(&'a str);
+
+impl Api for LengthRequest<'_> {
+    type Reply<'de> = usize;
+    type Request<'de> = LengthRequest<'de>;
+
+    const NAME: &'static str = "len";
+    const SERVICE: &'static str = "text";
+}
+
+fn text_service_router() -> ApiRouter {
+    ApiRouter::new()
+        .register_handler::<UppercaseRequest, _>(|req| req.0.to_uppercase())
+        .register_handler::<LengthRequest, _>(|req| req.0.len())
+}
+
+fn serve_tcp() -> std::net::SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let responder = channel::Responder::new(Server::tcp(listener));
+    thread::spawn(move || text_service_router().serve_on(responder));
+    addr
+}
+
+#[test]
+fn message_roundtrip() {
+    let message = channel::Message {
+        kind: channel::Kind::Request,
+        id: 42,
+        version: 3,
+        deadline: Some(UNIX_EPOCH + Duration::from_millis(1_700_000_000_123)),
+        service: "text".to_string(),
+        api_name: "upper".to_string(),
+        headers: [("correlation-id", "abc"), ("empty", "")]
+            .into_iter()
+            .collect(),
+        encoding: Encoding::Deflate,
+        data: b"\"some data\"".to_vec(),
+    };
+    let decoded = channel::Message::decode(&message.encode()).unwrap();
+    assert_eq!(decoded.kind, message.kind);
+    assert_eq!(decoded.id, message.id);
+    assert_eq!(decoded.version, message.version);
+    assert_eq!(decoded.deadline, message.deadline);
+    assert_eq!(decoded.service, message.service);
+    assert_eq!(decoded.api_name, message.api_name);
+    assert_eq!(decoded.headers, message.headers);
+    assert_eq!(decoded.encoding, message.encoding);
+    assert_eq!(decoded.data, message.data);
+
+    let mut truncated = message.encode();
+    truncated.pop();
+    assert!(channel::Message::decode(&truncated).is_err());
+}
+
+#[test]
+fn tcp_loopback() {
+    let addr = serve_tcp();
+    let requester = channel::connect_tcp(addr).unwrap();
+    assert_eq!(requester.request(UppercaseRequest("tcp")).unwrap(), "TCP");
+    assert_eq!(requester.request(LengthRequest("four")).unwrap(), 4);
+}
+
+#[test]
+fn tcp_multiple_clients() {
+    let addr = serve_tcp();
+    let clients: Vec<_> = (0..4)
+        .map(|i| {
+            thread::spawn(move || {
+                let requester = channel::connect_tcp(addr).unwrap();
+                for j in 0..10 {
+                    let input = format!("client {i} request {j}");
+                    let reply = requester.request(UppercaseRequest(&input)).unwrap();
+                    assert_eq!(reply, input.to_uppercase());
+                }
+            })
+        })
+        .collect();
+    for client in clients {
+        client.join().unwrap();
+    }
+}
+
+#[test]
+fn tcp_client_disconnect_does_not_stop_server() {
+    let addr = serve_tcp();
+    {
+        let requester = channel::connect_tcp(addr).unwrap();
+        assert_eq!(requester.request(LengthRequest("abc")).unwrap(), 3);
+    }
+    let requester = channel::connect_tcp(addr).unwrap();
+    assert_eq!(requester.request(LengthRequest("abcd")).unwrap(), 4);
+}
+
+#[test]
+fn sending_to_a_closed_connection_fails() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = Server::tcp(listener);
+    let client = channel::connect_tcp(addr).unwrap().into_transport();
+    client
+        .send(channel::Message {
+            kind: channel::Kind::Request,
+            service: "text".to_string(),
+            api_name: "len".to_string(),
+            data: b"\"abc\"".to_vec(),
+            ..Default::default()
+        })
+        .unwrap();
+    let request = server.recv().unwrap();
+
+    drop(client);
+    let hung_up = server
+        .recv_from_current_timeout(Duration::from_secs(5))
+        .unwrap();
+    assert_eq!(
+        hung_up.map(|message| message.kind),
+        Some(channel::Kind::Cancel)
+    );
+    assert_eq!(
+        server.send(request.reply(b"3".to_vec())),
+        Err("Connection 0 has closed".to_string())
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn unix_loopback() {
+    use std::os::unix::net::UnixListener;
+
+    let path = std::env::temp_dir().join(format!("serde-handler-{}.sock", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let listener = UnixListener::bind(&path).unwrap();
+    let responder = channel::Responder::new(Server::unix(listener));
+    thread::spawn(move || text_service_router().serve_on(responder));
+
+    let requester = channel::connect_unix(&path).unwrap();
+    assert_eq!(requester.request(UppercaseRequest("unix")).unwrap(), "UNIX");
+    assert_eq!(requester.request(LengthRequest("unix")).unwrap(), 4);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn partial_frames_do_not_outlast_the_timeout() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        transport::read_frame(&stream, usize::MAX).unwrap();
+        // Only half of the length of the reply ever arrives.
+        stream.write_all(&[0, 0]).unwrap();
+        let _ = stream.read(&mut [0; 1]);
+    });
+    let requester = channel::connect_tcp(addr).unwrap();
+
+    let start = Instant::now();
+    let error = requester
+        .request_with_timeout(UppercaseRequest("abc"), Duration::from_millis(200))
+        .unwrap_err();
+    assert!(start.elapsed() < Duration::from_secs(1));
+    assert_eq!(
+        error,
+        channel::Error::Transport(
+            "Error receiving response: Timed out in the middle of a frame".to_string()
+        )
+    );
+}
@@ -0,0 +1,7 @@
+use serde::{Deserialize, Serialize};
+use serde_handler::api::Api;
+
+#[derive(Serialize, Deserialize, Api)]
+struct UppercaseRequest<'a>(&'a str);
+
+fn main() {}
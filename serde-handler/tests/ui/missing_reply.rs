@@ -0,0 +1,8 @@
+use serde::{Deserialize, Serialize};
+use serde_handler::api::Api;
+
+#[derive(Serialize, Deserialize, Api)]
+#[api(service = "text", name = "upper")]
+struct UppercaseRequest<'a>(&'a str);
+
+fn main() {}
@@ -0,0 +1,8 @@
+use serde_handler::api::service;
+
+#[service]
+trait Text {
+    fn upper<S: AsRef<str>>(&self, s: S) -> String;
+}
+
+fn main() {}
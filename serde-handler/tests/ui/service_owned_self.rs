@@ -0,0 +1,8 @@
+use serde_handler::api::service;
+
+#[service]
+trait Text {
+    fn upper(self, s: &str) -> String;
+}
+
+fn main() {}
@@ -0,0 +1,8 @@
+use serde::{Deserialize, Serialize};
+use serde_handler::api::Api;
+
+#[derive(Serialize, Deserialize, Api)]
+#[api(service = "text", name = "concat", reply = String)]
+struct ConcatRequest<'a, 'b>(&'a str, &'b str);
+
+fn main() {}
@@ -0,0 +1,165 @@
+use std::thread;
+
+use serde::{Deserialize, Serialize};
+
+use serde_handler::{
+    api::*,
+    channel::{self, Error, Kind, Message},
+};
+
+/// The first version of the API, which only knew how to greet by name.
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct GreetV1<'a>(&'a str);
+
+impl Api for GreetV1<'_> {
+    type Reply<'de> = Greeting;
+    type Request<'de> = GreetV1<'de>;
+
+    const NAME: &'static str = "greet";
+    const SERVICE: &'static str = "text";
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct GreetV2<'a> {
+    name: &'a str,
+    greeting: &'a str,
+}
+
+impl Api for GreetV2<'_> {
+    type Reply<'de> = Greeting;
+    type Request<'de> = GreetV2<'de>;
+
+    const NAME: &'static str = "greet";
+    const SERVICE: &'static str = "text";
+    const VERSION: u32 = 2;
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct GreetV3<'a> {
+    names: Vec<&'a str>,
+    greeting: &'a str,
+}
+
+/// Replies with one greeting per name.
+impl Api for GreetV3<'_> {
+    type Reply<'de> = Vec<String>;
+    type Request<'de> = GreetV3<'de>;
+
+    const NAME: &'static str = "greet";
+    const SERVICE: &'static str = "text";
+    const VERSION: u32 = 3;
+}
+
+/// The reply of the first two versions, which can be built from the reply of the third.
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct Greeting(String);
+
+impl From<Vec<String>> for Greeting {
+    fn from(greetings: Vec<String>) -> Self {
+        Greeting(greetings.join(", "))
+    }
+}
+
+fn v2_router() -> ApiRouter {
+    ApiRouter::new()
+        .register_handler::<GreetV2, _>(|req| Greeting(format!("{} {}", req.greeting, req.name)))
+        .register_adapter::<GreetV1, GreetV2, _>(|req| GreetV2 {
+            name: req.0,
+            greeting: "Hello",
+        })
+}
+
+#[test]
+fn old_versions_are_adapted() {
+    let (requester, responder) = channel::new_pair();
+    thread::spawn(move || v2_router().serve_on(responder));
+
+    assert_eq!(
+        requester.request(GreetV1("Ferris")).unwrap().0,
+        "Hello Ferris"
+    );
+    let greeting = GreetV2 {
+        name: "Ferris",
+        greeting: "Hi",
+    };
+    assert_eq!(requester.request(greeting).unwrap().0, "Hi Ferris");
+}
+
+#[test]
+fn adapters_chain_and_convert_replies() {
+    let (requester, responder) = channel::new_pair();
+    thread::spawn(move || {
+        ApiRouter::new()
+            .register_handler::<GreetV3, _>(|req| {
+                let greet = |name| format!("{} {name}", req.greeting);
+                req.names.iter().map(greet).collect()
+            })
+            .register_adapter::<GreetV2, GreetV3, _>(|req| GreetV3 {
+                names: req.name.split(" and ").collect(),
+                greeting: req.greeting,
+            })
+            .register_adapter::<GreetV1, GreetV2, _>(|req| GreetV2 {
+                name: req.0,
+                greeting: "Hello",
+            })
+            .serve_on(responder)
+    });
+
+    let greeting = GreetV1("Ferris and Corro");
+    assert_eq!(
+        requester.request(greeting).unwrap().0,
+        "Hello Ferris, Hello Corro"
+    );
+    let greeting = GreetV3 {
+        names: vec!["Ferris"],
+        greeting: "Hi",
+    };
+    assert_eq!(requester.request(greeting).unwrap(), ["Hi Ferris"]);
+}
+
+#[test]
+fn unknown_version_is_incompatible() {
+    let (requester, responder) = channel::new_pair();
+    thread::spawn(move || v2_router().serve_on(responder));
+
+    let greeting = GreetV3 {
+        names: vec!["Ferris"],
+        greeting: "Hi",
+    };
+    assert_eq!(
+        requester.request(greeting).unwrap_err(),
+        Error::IncompatibleVersion {
+            requested: 3,
+            supported: vec![1, 2],
+        }
+    );
+}
+
+#[test]
+fn unversioned_requests_use_newest_version() {
+    let (requester, responder) = channel::new_pair();
+    thread::spawn(move || v2_router().serve_on(responder));
+
+    let reply = requester
+        .send_request(Message {
+            kind: Kind::Request,
+            service: "text".to_string(),
+            api_name: "greet".to_string(),
+            data: br#"{ "name": "Ferris", "greeting": "Hey" }"#.to_vec(),
+            ..Default::default()
+        })
+        .unwrap();
+    assert_eq!(reply.data, br#""Hey Ferris""#);
+}
+
+#[test]
+fn describe_lists_versions() {
+    let description = v2_router().describe();
+    assert_eq!(description.api("text", "greet").unwrap().versions, [1, 2]);
+}
+
+#[test]
+#[should_panic(expected = "Duplicate handler for 'greet' in service 'text' at version 1")]
+fn duplicate_version_panics() {
+    let _ = v2_router().register_handler::<GreetV1, _>(|req| Greeting(req.0.to_string()));
+}